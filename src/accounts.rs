@@ -0,0 +1,279 @@
+use crate::bsky_agent::BskyAgent;
+use crate::identity::IdentityResolver;
+use crate::session::{write_private_file, SessionStore};
+use crate::syntax::{Did, Handle};
+use crate::xrpc::{CreateSessionRequest, CreateSessionResponse, XrpcClient, XrpcResult};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// An account as it is persisted: the host it lives on and its session, if logged in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredAccount {
+    pub host: String,
+    pub session: Option<CreateSessionResponse>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AccountsFile {
+    current: Option<String>,
+    #[serde(default)]
+    accounts: BTreeMap<String, StoredAccount>,
+}
+
+/// What `AccountManager::list` reports about an account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountInfo {
+    pub name: String,
+    pub host: String,
+    pub did: Option<Did>,
+    pub handle: Option<Handle>,
+    pub current: bool,
+}
+
+struct Accounts {
+    path: Option<PathBuf>,
+    file: tokio::sync::Mutex<AccountsFile>,
+}
+
+impl Accounts {
+    async fn save(&self, file: &AccountsFile) -> Result<()> {
+        match &self.path {
+            Some(path) => write_private_file(path, &serde_json::to_vec_pretty(file)?).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// The `SessionStore` of one account, backed by the accounts file.
+struct AccountSessionStore {
+    accounts: Arc<Accounts>,
+    name: String,
+    host: String,
+}
+
+#[async_trait]
+impl SessionStore for AccountSessionStore {
+    async fn load(&self) -> Result<Option<CreateSessionResponse>> {
+        let file = self.accounts.file.lock().await;
+        Ok(file
+            .accounts
+            .get(&self.name)
+            .and_then(|account| account.session.clone()))
+    }
+
+    async fn save(&self, session: &CreateSessionResponse) -> Result<()> {
+        let mut file = self.accounts.file.lock().await;
+        file.accounts.insert(
+            self.name.clone(),
+            StoredAccount {
+                // The account may have turned out to live on another PDS.
+                host: session.pds_endpoint().unwrap_or_else(|| self.host.clone()),
+                session: Some(session.clone()),
+            },
+        );
+        if file.current.is_none() {
+            file.current = Some(self.name.clone());
+        }
+        self.accounts.save(&file).await
+    }
+
+    /// Keeps the account, logged out, so that it can be logged in again.
+    async fn clear(&self) -> Result<()> {
+        let mut file = self.accounts.file.lock().await;
+        match file.accounts.get_mut(&self.name) {
+            Some(account) => account.session = None,
+            None => return Ok(()),
+        }
+        self.accounts.save(&file).await
+    }
+}
+
+/// Keeps many named accounts, possibly on different hosts, and one `BskyAgent` per account.
+///
+/// Each account's agent refreshes its session on its own and writes every change back, so the
+/// accounts file always holds the latest tokens of all accounts. One account can be marked as
+/// the current one, for callers that don't name an account.
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// use rustysky::accounts::AccountManager;
+/// use rustysky::types::get_default_configuration;
+/// use rustysky::xrpc::XrpcClient;
+///
+/// let client = XrpcClient::new(get_default_configuration())?;
+/// let accounts = AccountManager::open(client, "accounts.json").await?;
+/// let profile = accounts.agent("brand-a").await?.get_profile("bsky.app").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AccountManager {
+    client: XrpcClient,
+    accounts: Arc<Accounts>,
+    agents: Arc<Mutex<HashMap<String, BskyAgent>>>,
+    identity: Option<IdentityResolver>,
+}
+
+impl AccountManager {
+    /// Accounts that only live as long as the manager, e.g. for tests.
+    pub fn in_memory(client: XrpcClient) -> Self {
+        Self::with_file(client, None, AccountsFile::default())
+    }
+
+    /// Loads the accounts from the JSON file at `path`, which is created on the first change.
+    pub async fn open(client: XrpcClient, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let file = match tokio::fs::read(&path).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("Invalid accounts file {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => AccountsFile::default(),
+            Err(err) => return Err(err).with_context(|| format!("Can't read {}", path.display())),
+        };
+        Ok(Self::with_file(client, Some(path), file))
+    }
+
+    fn with_file(client: XrpcClient, path: Option<PathBuf>, file: AccountsFile) -> Self {
+        Self {
+            client,
+            accounts: Arc::new(Accounts {
+                path,
+                file: tokio::sync::Mutex::new(file),
+            }),
+            agents: Arc::new(Mutex::new(HashMap::new())),
+            identity: None,
+        }
+    }
+
+    /// Looks up the PDS of accounts that are added without a host, see
+    /// `BskyAgent::with_pds_discovery`.
+    pub fn with_pds_discovery(mut self, identity: IdentityResolver) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    fn new_agent(
+        &self,
+        name: &str,
+        host: &str,
+        session: Option<CreateSessionResponse>,
+    ) -> BskyAgent {
+        let store = AccountSessionStore {
+            accounts: self.accounts.clone(),
+            name: name.to_string(),
+            host: host.to_string(),
+        };
+        let client = self.client.with_host(host);
+        let agent = match session {
+            Some(session) => BskyAgent::with_session(client, session),
+            None => BskyAgent::new(client),
+        };
+        agent.with_session_store(Arc::new(store))
+    }
+
+    /// Logs in to the account `name` on `host` and saves it. Without a host, the account's PDS
+    /// is looked up if discovery is on, or else the client's host is used. Logging in to an
+    /// existing account replaces its session.
+    pub async fn add(
+        &self,
+        name: &str,
+        host: Option<&str>,
+        request: &CreateSessionRequest,
+    ) -> XrpcResult<BskyAgent> {
+        let mut agent = self.new_agent(
+            name,
+            host.unwrap_or(&self.client.config().xrpc_host)
+                .trim_end_matches('/'),
+            None,
+        );
+        if let (None, Some(identity)) = (host, &self.identity) {
+            agent = agent.with_pds_discovery(identity.clone());
+        }
+        agent.login(request).await?;
+        self.agents
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), agent.clone());
+        Ok(agent)
+    }
+
+    /// Logs the account out and forgets it. The account is removed even if the server can't
+    /// be reached to revoke the session.
+    pub async fn remove(&self, name: &str) -> Result<()> {
+        let agent = self.agent(name).await?;
+        if agent.session().is_some() {
+            if let Err(err) = agent.logout().await {
+                warn!("Could not revoke the session of {}: {}", name, err);
+            }
+        }
+        self.agents.lock().unwrap().remove(name);
+
+        let mut file = self.accounts.file.lock().await;
+        file.accounts.remove(name);
+        if file.current.as_deref() == Some(name) {
+            file.current = None;
+        }
+        self.accounts.save(&file).await
+    }
+
+    /// Makes `name` the current account.
+    pub async fn switch(&self, name: &str) -> Result<()> {
+        let mut file = self.accounts.file.lock().await;
+        if !file.accounts.contains_key(name) {
+            bail!("No account named {}", name);
+        }
+        file.current = Some(name.to_string());
+        self.accounts.save(&file).await
+    }
+
+    /// The name of the current account.
+    pub async fn current(&self) -> Option<String> {
+        self.accounts.file.lock().await.current.clone()
+    }
+
+    pub async fn list(&self) -> Vec<AccountInfo> {
+        let file = self.accounts.file.lock().await;
+        file.accounts
+            .iter()
+            .map(|(name, account)| AccountInfo {
+                name: name.clone(),
+                host: account.host.clone(),
+                did: account.session.as_ref().map(|session| session.did.clone()),
+                handle: account
+                    .session
+                    .as_ref()
+                    .map(|session| session.handle.clone()),
+                current: file.current.as_ref() == Some(name),
+            })
+            .collect()
+    }
+
+    /// The agent for the account `name`. Every call returns the same agent (or a clone of it),
+    /// so that the account's refreshes are coalesced.
+    pub async fn agent(&self, name: &str) -> Result<BskyAgent> {
+        if let Some(agent) = self.agents.lock().unwrap().get(name) {
+            return Ok(agent.clone());
+        }
+        let account = match self.accounts.file.lock().await.accounts.get(name) {
+            Some(account) => account.clone(),
+            None => bail!("No account named {}", name),
+        };
+
+        let agent = self.new_agent(name, &account.host, account.session);
+        // Another task may have created the agent in the meantime; keep the first one.
+        let mut agents = self.agents.lock().unwrap();
+        Ok(agents.entry(name.to_string()).or_insert(agent).clone())
+    }
+
+    /// The agent for the current account.
+    pub async fn current_agent(&self) -> Result<BskyAgent> {
+        match self.current().await {
+            Some(name) => self.agent(&name).await,
+            None => bail!("No current account"),
+        }
+    }
+}
@@ -1,100 +1,372 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
 use env_logger::{Builder, Env};
 use log::{info, LevelFilter};
+#[cfg(feature = "media")]
+use rustysky::media::{process_image_file, ImageOptions};
 use rustysky::{
+    accounts::AccountManager,
+    bsky_agent::BskyAgent,
+    identity::{HandleResolver, IdentityResolver},
+    link_card::LinkCardBuilder,
+    oauth::{LoopbackListener, OAuthClient, OAuthClientConfig, DEFAULT_SCOPE},
     types::{get_default_configuration, BlueskyConfiguration},
     xrpc::{
-        clear_client, create_post, create_session, get_profile, refresh_session,
-        set_http_debug_logging, CreatePostRequest, CreateSessionRequest, CreateSessionResponse,
-        Post, ProfileViewDetailedResponse, ReplyRef, SelfLabel, SelfLabels, StrongRef,
+        self, ApplyWrites, CreateSessionRequest, Embed, ImagesEmbedBuilder, Post,
+        ProfileViewDetailedResponse, Record, ReplyRef, SelfLabel, SelfLabels, StrongRef,
+        XrpcClient,
     },
 };
+use serde::{Deserialize, Serialize};
 
-use std::{env, fs::File, io::Write, path::Path};
-
-const MAX_RETRIES: u32 = 5;
+use std::{
+    env,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    set_http_debug_logging(true);
     configure_logging(""); // pass a filename to log to a file, or "" for stdout
-    let config: BlueskyConfiguration = get_default_configuration();
+    let mut config: BlueskyConfiguration = get_default_configuration();
+    config.http_debug_logging = true;
 
-    let create_session_request = credentials_from_env()?;
-    info!(
-        "Using Bluesky credentials for {} from BLUESKY_USERNAME, BLUESKY_PASSWORD",
-        create_session_request.identifier
+    let client = XrpcClient::new(config)?;
+    let accounts = AccountManager::open(client.clone(), accounts_file_path())
+        .await?
+        .with_pds_discovery(IdentityResolver::new(client.clone()));
+
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let account = take_option(&mut args, "--account")?;
+    let dry_run = take_flag(&mut args, "--dry-run");
+    let images = take_images(&mut args)?;
+    let link_card = take_flag(&mut args, "--link-card");
+    let quote = take_option(&mut args, "--quote")?;
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [] => demo(&accounts, account).await,
+        ["logout"] => logout(&accounts, account).await,
+        ["oauth-login"] => oauth_login(client, DEFAULT_PDS).await,
+        ["oauth-login", pds] => oauth_login(client, pds).await,
+        ["accounts"] | ["accounts", "list"] => list_accounts(&accounts).await,
+        ["accounts", "add", name] => login_account(&accounts, name, None).await.map(|_| ()),
+        ["accounts", "add", name, host] => {
+            login_account(&accounts, name, Some(host)).await.map(|_| ())
+        }
+        ["accounts", "remove", name] => accounts.remove(name).await,
+        ["accounts", "switch", name] => accounts.switch(name).await,
+        ["post", text] => post(&accounts, account, text, images, link_card, quote).await,
+        ["delete-old-posts", days] => delete_old_posts(&accounts, account, days, dry_run).await,
+        _ => bail!(USAGE),
+    }
+}
+
+const USAGE: &str = "Usage: rustysky_cli [--account <name>] [logout | oauth-login [<pds-url>]]
+       rustysky_cli accounts [list | add <name> [<host>] | remove <name> | switch <name>]
+       rustysky_cli [--account <name>] post <text> [--image <path> --alt <text>]... [--link-card]
+                                                   [--quote <bsky.app-url>]
+       rustysky_cli [--account <name>] delete-old-posts <days> [--dry-run]";
+
+const DEFAULT_PDS: &str = "https://bsky.social";
+
+/// Removes `--name <value>` from the arguments and returns the value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>> {
+    let Some(index) = args.iter().position(|arg| arg == name) else {
+        return Ok(None);
+    };
+    if index + 1 >= args.len() {
+        bail!("{} needs a value.\n{}", name, USAGE);
+    }
+    let value = args.remove(index + 1);
+    args.remove(index);
+    Ok(Some(value))
+}
+
+/// Removes `--name` from the arguments and returns whether it was there.
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let before = args.len();
+    args.retain(|arg| arg != name);
+    args.len() != before
+}
+
+/// Removes the `--image <path> --alt <text>` pairs from the arguments, in order.
+fn take_images(args: &mut Vec<String>) -> Result<Vec<(String, String)>> {
+    let mut images = Vec::new();
+    while let Some(path) = take_option(args, "--image")? {
+        let Some(alt) = take_option(args, "--alt")? else {
+            bail!("--image {} needs an --alt text.\n{}", path, USAGE);
+        };
+        images.push((path, alt));
+    }
+    if take_option(args, "--alt")?.is_some() {
+        bail!("--alt needs an --image.\n{}", USAGE);
+    }
+    Ok(images)
+}
+
+/// The account named with `--account`, or else the current account, or else `default`.
+async fn selected_account(accounts: &AccountManager, account: Option<String>) -> String {
+    match account {
+        Some(account) => account,
+        None => accounts
+            .current()
+            .await
+            .unwrap_or_else(|| "default".to_string()),
+    }
+}
+
+async fn list_accounts(accounts: &AccountManager) -> Result<()> {
+    for account in accounts.list().await {
+        println!(
+            "{} {} ({}) on {}",
+            if account.current { "*" } else { " " },
+            account.name,
+            account.handle.as_deref().unwrap_or("logged out"),
+            account.host
+        );
+    }
+    Ok(())
+}
+
+/// Logs in with OAuth in the browser and fetches the profile with the DPoP-bound token.
+async fn oauth_login(client: XrpcClient, pds: &str) -> Result<()> {
+    let listener = LoopbackListener::bind(0).await?;
+    let config = OAuthClientConfig::loopback(listener.redirect_uri(), DEFAULT_SCOPE);
+    let oauth = OAuthClient::new(client.clone(), config);
+
+    let request = oauth.authorize(pds, None).await?;
+    println!(
+        "Open this URL in your browser to log in:\n\n{}\n",
+        request.authorization_url
     );
+    let callback = listener.wait_for_callback().await?;
+    let session = oauth.callback(&request, &callback).await?;
+    info!("OAuth login successful for {}", session.did);
 
-    let mut session: CreateSessionResponse;
-    let mut errcount = 0;
-    loop {
-        match create_session(&create_session_request, &config).await {
-            Ok(response_data) => {
-                info!("Login successful: {:#?}", response_data);
-                session = response_data;
-                break;
-            }
-            Err((Some(code), message)) => {
-                if errcount >= MAX_RETRIES {
-                    bail!("Login failed too many times, exiting.");
+    let profile = xrpc::get_profile(
+        &session.did,
+        &session.access_token,
+        &session.client(&client),
+    )
+    .await?;
+    info!("Hello {}!", profile.handle);
+    Ok(())
+}
+
+/// Revokes the account's session. The account stays in the list, logged out.
+async fn logout(accounts: &AccountManager, account: Option<String>) -> Result<()> {
+    let name = selected_account(accounts, account).await;
+    let agent = accounts.agent(&name).await?;
+    let Some(session) = agent.session() else {
+        info!("{} is not logged in, nothing to do.", name);
+        return Ok(());
+    };
+    agent.logout().await?;
+    info!("Logged out {} ({})", session.handle, name);
+    Ok(())
+}
+
+/// The agent of the selected account, resuming its stored session or else logging in.
+async fn logged_in_agent(accounts: &AccountManager, account: Option<String>) -> Result<BskyAgent> {
+    let name = selected_account(accounts, account).await;
+    let agent = match accounts.agent(&name).await {
+        Ok(agent) => {
+            let host = agent.config().xrpc_host.clone();
+            match agent.resume_from_store().await {
+                Ok(Some(_)) => {
+                    info!("Resumed the session of {}", name);
+                    agent
+                }
+                Ok(None) => login_account(accounts, &name, Some(&host)).await?,
+                Err(err) => {
+                    info!("Could not resume the session of {}: {:#}", name, err);
+                    login_account(accounts, &name, Some(&host)).await?
                 }
-                info!("HTTP error with code {}: {}", code, message);
-                errcount += 1;
-                info!("Login failed {} times, retrying.", errcount);
-                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-            }
-            Err((None, message)) => {
-                bail!("Other error: {}", message);
             }
         }
+        Err(_) => login_account(accounts, &name, None).await?,
+    };
+    Ok(agent)
+}
+
+/// Posts `text` with the given images and their alt texts, or else with a card for its first
+/// link if `link_card` is set. With `quote`, the bsky.app URL of a post, the post quotes it
+/// along with the images or card.
+async fn post(
+    accounts: &AccountManager,
+    account: Option<String>,
+    text: &str,
+    images: Vec<(String, String)>,
+    link_card: bool,
+    quote: Option<String>,
+) -> Result<()> {
+    let agent = logged_in_agent(accounts, account).await?;
+    let Some(session) = agent.session() else {
+        bail!("Not logged in");
+    };
+    // Resolved first, so that nothing is uploaded for a post that can't be quoted.
+    let quoted = match &quote {
+        Some(url) => Some(
+            agent
+                .resolve_web_url(url)
+                .await
+                .with_context(|| format!("Can't quote {}", url))?,
+        ),
+        None => None,
+    };
+
+    let mut builder = ImagesEmbedBuilder::new();
+    for (path, alt) in &images {
+        builder = add_image(builder, path, alt).await?;
     }
-    info!("Hello {}!", session.handle);
+    let mut post = Post::new(text, &session.did, None, None, None, None)?;
+    post.resolve_mentions(&HandleResolver::new(agent.client().clone()))
+        .await;
+    if !builder.is_empty() {
+        // All images are checked before the first one is uploaded.
+        post = post.with_embed(builder.upload(&agent).await?);
+    } else if link_card {
+        let cards = LinkCardBuilder::new(agent.client());
+        if !cards.attach(&mut post, &agent).await? {
+            info!("The post has no link to make a card for");
+        }
+    }
+    if let Some(quoted) = quoted {
+        post.embed = Some(match post.embed.take() {
+            Some(media) => Embed::quote_with_media(quoted, media)?,
+            None => Embed::quote(quoted),
+        });
+    }
+    let posted = agent.create_post(post).await?;
+    match posted.uri.to_web_url() {
+        Some(url) => println!("Posted {}", url),
+        None => println!("Posted {}", posted.uri),
+    }
+    Ok(())
+}
 
-    // get the full profile
-    let profile: ProfileViewDetailedResponse;
-    errcount = 0;
+/// Adds the image as it is. Build with `--features media` to have it resized, compressed and
+/// stripped of its metadata first.
+#[cfg(not(feature = "media"))]
+async fn add_image(
+    builder: ImagesEmbedBuilder,
+    path: &str,
+    alt: &str,
+) -> Result<ImagesEmbedBuilder> {
+    let upload = xrpc::BlobUpload::from_file(path)
+        .await
+        .with_context(|| format!("Can't read {}", path))?;
+    Ok(builder.image(upload, alt))
+}
+
+/// Adds the image resized and compressed to fit, without its metadata.
+#[cfg(feature = "media")]
+async fn add_image(
+    builder: ImagesEmbedBuilder,
+    path: &str,
+    alt: &str,
+) -> Result<ImagesEmbedBuilder> {
+    let image = process_image_file(path, &ImageOptions::default())
+        .await
+        .with_context(|| format!("Can't process {}", path))?;
+    info!(
+        "{} is {}x{} and {} bytes after processing",
+        path,
+        image.width,
+        image.height,
+        image.bytes.len()
+    );
+    Ok(builder.image_with_aspect_ratio(image.to_upload(), alt, image.aspect_ratio))
+}
+
+/// Just enough of a post to tell how old it is.
+#[derive(Serialize, Deserialize)]
+struct PostDate {
+    #[serde(rename = "createdAt")]
+    created_at: String,
+}
+
+impl Record for PostDate {
+    const NSID: &'static str = "app.bsky.feed.post";
+}
+
+/// Deletes the account's posts that are older than `days`, with as few `applyWrites` calls
+/// as possible.
+async fn delete_old_posts(
+    accounts: &AccountManager,
+    account: Option<String>,
+    days: &str,
+    dry_run: bool,
+) -> Result<()> {
+    let days: i64 = days
+        .parse()
+        .with_context(|| format!("<days> must be a number\n{}", USAGE))?;
+    let agent = logged_in_agent(accounts, account).await?;
+    let Some(session) = agent.session() else {
+        bail!("Not logged in");
+    };
+    let cutoff = Utc::now() - Duration::days(days);
+
+    let mut batch = ApplyWrites::new(&session.did);
+    let mut cursor: Option<String> = None;
     loop {
-        match get_profile(&mut session, &config).await {
-            Ok(response_data) => {
-                info!("Get Profile successful: {:#?}", response_data);
-                profile = response_data;
-                break;
-            }
-            Err((Some(code), message)) => {
-                if errcount >= MAX_RETRIES {
-                    bail!("Get Profile failed too many times, exiting.");
+        let page = agent
+            .list_records::<PostDate>(&session.did, Some(100), cursor.as_deref())
+            .await?;
+        for record in &page.records {
+            let created_at = DateTime::parse_from_rfc3339(&record.value.created_at);
+            if let (Ok(created_at), Some(rkey)) = (created_at, record.uri.rkey()) {
+                if created_at < cutoff {
+                    batch.delete::<Post>(rkey);
                 }
-                info!("HTTP error with code {}: {}", code, message);
-                errcount += 1;
-                info!("Get Profile failed {} times, retrying.", errcount);
-                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-            }
-            Err((None, message)) => {
-                bail!("Other error: {}", message);
             }
         }
+        match page.cursor {
+            Some(next) if !page.records.is_empty() => cursor = Some(next),
+            _ => break,
+        }
     }
+
+    if dry_run || batch.is_empty() {
+        println!("{} posts are older than {} days", batch.len(), days);
+        return Ok(());
+    }
+    let applied = agent.apply_writes(&batch).await?;
+    println!(
+        "Deleted {} posts older than {} days in {} commits",
+        applied.results.len(),
+        days,
+        applied.commits.len()
+    );
+    Ok(())
+}
+
+/// Logs in (or resumes the stored session) and walks through the API: profile, refresh, a
+/// post and a reply to it.
+async fn demo(accounts: &AccountManager, account: Option<String>) -> Result<()> {
+    let agent = logged_in_agent(accounts, account).await?;
+    let Some(session) = agent.session() else {
+        bail!("Not logged in");
+    };
+    info!("Hello {}!", session.handle);
+
+    // get the full profile
+    let profile: ProfileViewDetailedResponse = agent.get_profile(&session.did).await?;
+    info!("Get Profile successful: {:#?}", profile);
     info!(
         "Congrats {}, you already have {} followers!",
         profile.display_name.as_deref().unwrap_or(&session.handle),
         profile.followers_count.unwrap_or(0)
     );
 
-    // just to test this, I clear teh client
-    clear_client();
-
-    match refresh_session(&session.refresh_jwt, &config).await {
-        Ok(response_data) => {
-            session.update_from_refresh(&response_data);
-            session.print_token_info();
-        }
-        Err((Some(code), message)) => {
-            bail!("HTTP error with code {}: {}", code, message)
-        }
-        Err((None, message)) => {
-            bail!("Other error: {}", message)
-        }
+    agent.refresh_session().await?;
+    if let Some(session) = agent.session() {
+        session.print_token_info();
     }
 
     let text = format!(
@@ -109,7 +381,7 @@ async fn main() -> anyhow::Result<()> {
         }],
     };
 
-    let post = Post::new(
+    let mut post = Post::new(
         &text,
         &session.did,
         None,
@@ -117,22 +389,13 @@ async fn main() -> anyhow::Result<()> {
         Some(vec!["test".to_string()]),
         Some(labels),
     )?;
+    post.resolve_mentions(&HandleResolver::new(agent.client().clone()))
+        .await;
 
-    let parent: StrongRef;
-
-    let did = session.did.clone();
-    let create_post_request = CreatePostRequest::new(&did, post);
-    match create_post(&create_post_request, &mut session, &config).await {
-        Ok(response_data) => {
-            info!("Post created successfully: {:#?}", response_data);
-            parent = response_data;
-        }
-        Err((Some(code), message)) => {
-            bail!("HTTP error with code {}: {}", code, message)
-        }
-        Err((None, message)) => {
-            bail!("Other error: {}", message)
-        }
+    let parent: StrongRef = agent.create_post(post).await?;
+    info!("Post created successfully: {:#?}", parent);
+    if let Some(url) = parent.uri.to_web_url() {
+        println!("Posted {}", url);
     }
 
     let reply = ReplyRef {
@@ -148,18 +411,8 @@ async fn main() -> anyhow::Result<()> {
         None,
         None,
     )?;
-    let reply_post_request = CreatePostRequest::new(&did.clone(), replypost);
-    match create_post(&reply_post_request, &mut session, &config).await {
-        Ok(response_data) => {
-            info!("Post reply created successfully: {:#?}", response_data);
-        }
-        Err((Some(code), message)) => {
-            bail!("HTTP error with code {}: {}", code, message)
-        }
-        Err((None, message)) => {
-            bail!("Other error: {}", message)
-        }
-    }
+    let reply_ref = agent.create_post(replypost).await?;
+    info!("Post reply created successfully: {:#?}", reply_ref);
 
     Ok(())
 }
@@ -259,6 +512,54 @@ fn configure_logging(log_filename: &str) {
     builder.init()
 }
 
+/// Logs in to the account `name` with the credentials from the environment and saves it.
+async fn login_account(
+    accounts: &AccountManager,
+    name: &str,
+    host: Option<&str>,
+) -> Result<BskyAgent> {
+    let mut create_session_request = credentials_from_env()?;
+    info!(
+        "Using Bluesky credentials for {} from BLUESKY_USERNAME, BLUESKY_PASSWORD",
+        create_session_request.identifier
+    );
+    let agent = match accounts.add(name, host, &create_session_request).await {
+        Err(err) if err.is_auth_factor_token_required() => {
+            // The server has just sent the sign-in code by email.
+            create_session_request.auth_factor_token = Some(prompt_auth_factor_token()?);
+            accounts.add(name, host, &create_session_request).await?
+        }
+        result => result?,
+    };
+    info!("Login successful: {:#?}", agent.session());
+    Ok(agent)
+}
+
+fn prompt_auth_factor_token() -> Result<String> {
+    print!("Enter the sign-in code sent to your email: ");
+    std::io::stdout().flush()?;
+    let mut token = String::new();
+    std::io::stdin().read_line(&mut token)?;
+    let token = token.trim().to_string();
+    if token.is_empty() {
+        bail!("No sign-in code entered.");
+    }
+    Ok(token)
+}
+
+/// The accounts are kept in `RUSTYSKY_ACCOUNTS_FILE`, or `~/.rustysky/accounts.json` by
+/// default.
+fn accounts_file_path() -> PathBuf {
+    if let Some(path) = env::var_os("RUSTYSKY_ACCOUNTS_FILE").filter(|path| !path.is_empty()) {
+        return PathBuf::from(path);
+    }
+    let home = env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    home.join(".rustysky").join("accounts.json")
+}
+
 fn credentials_from_env() -> Result<CreateSessionRequest> {
     let bluesky_username_var: &str = "BLUESKY_USERNAME";
     let bluesky_password_var: &str = "BLUESKY_PASSWORD";
@@ -282,5 +583,6 @@ fn credentials_from_env() -> Result<CreateSessionRequest> {
     Ok(CreateSessionRequest {
         identifier,
         password,
+        auth_factor_token: None,
     })
 }
@@ -1,4 +1,503 @@
+use crate::identity::{DidDocument, IdentityResolver};
+use crate::session::{SessionEvent, SessionStore};
+use crate::syntax::{AtUri, RecordKey};
+use crate::types::BlueskyConfiguration;
+use crate::xrpc::{
+    self, AppliedWrites, ApplyWrites, BlobRef, BlobUpload, CreatePostRequest, CreateRecordRequest,
+    CreateRecordResponse, CreateSessionRequest, CreateSessionResponse, DeleteRecordRequest,
+    DeleteRecordResponse, GetRecordResponse, ListRecordsResponse, Post,
+    ProfileViewDetailedResponse, PutRecordRequest, PutRecordResponse, Record, StrongRef,
+    XrpcClient, XrpcError, XrpcResult, MAX_WRITES_PER_CALL,
+};
 use anyhow::Result;
+use log::{info, warn};
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+
+/// Called with every session change, and the session after the change (`None` once it expired).
+pub type SessionListener = Arc<dyn Fn(SessionEvent, Option<&CreateSessionResponse>) + Send + Sync>;
+
+/// A client that owns a session and keeps it fresh.
+///
+/// Every authenticated call refreshes the session shortly before the access token expires, and
+/// if the server still answers with `ExpiredToken`, refreshes once and replays the call.
+/// Clones share the session, and concurrent refreshes are coalesced into a single
+/// `refreshSession` call, since refresh tokens rotate on use and racing refreshes would
+/// invalidate each other.
+///
+/// With a session store attached, every change to the session is written to the store, so a
+/// later run can `resume_from_store` instead of logging in again.
+///
+/// The agent talks to the PDS named in the session's DID document, whatever host its client
+/// started out with. With `with_pds_discovery`, it also looks up the PDS before logging in.
+#[derive(Clone)]
+pub struct BskyAgent {
+    client: Arc<RwLock<XrpcClient>>,
+    session: Arc<RwLock<Option<CreateSessionResponse>>>,
+    refresh_lock: Arc<tokio::sync::Mutex<()>>,
+    store: Option<Arc<dyn SessionStore>>,
+    listeners: Arc<RwLock<Vec<SessionListener>>>,
+    identity: Option<IdentityResolver>,
+}
+
+impl BskyAgent {
+    pub fn new(client: XrpcClient) -> Self {
+        Self {
+            client: Arc::new(RwLock::new(client)),
+            session: Arc::new(RwLock::new(None)),
+            refresh_lock: Arc::new(tokio::sync::Mutex::new(())),
+            store: None,
+            listeners: Arc::new(RwLock::new(Vec::new())),
+            identity: None,
+        }
+    }
+
+    /// Resolves handles and DIDs with `identity` at login and sends `createSession` to the
+    /// account's own PDS. If resolution fails, the client's host is used.
+    pub fn with_pds_discovery(mut self, identity: IdentityResolver) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Keeps `store` up to date with every change to the session.
+    pub fn with_session_store(mut self, store: Arc<dyn SessionStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Registers a listener for session changes, e.g. to persist tokens somewhere other than a
+    /// `SessionStore`.
+    pub fn on_session_change<F>(&self, listener: F)
+    where
+        F: Fn(SessionEvent, Option<&CreateSessionResponse>) + Send + Sync + 'static,
+    {
+        self.listeners.write().unwrap().push(Arc::new(listener));
+    }
+
+    async fn session_changed(&self, event: SessionEvent) {
+        let session = self.session();
+        if let Some(store) = &self.store {
+            let result = match &session {
+                Some(session) => store.save(session).await,
+                None => store.clear().await,
+            };
+            if let Err(err) = result {
+                warn!("Failed to update the session store: {:#}", err);
+            }
+        }
+        let listeners = self.listeners.read().unwrap().clone();
+        for listener in listeners {
+            listener(event, session.as_ref());
+        }
+    }
+
+    pub fn with_session(client: XrpcClient, session: CreateSessionResponse) -> Self {
+        let agent = Self::new(client);
+        agent.follow_pds(&session);
+        *agent.session.write().unwrap() = Some(session);
+        agent
+    }
+
+    /// The client for the account's PDS.
+    pub fn client(&self) -> XrpcClient {
+        self.client.read().unwrap().clone()
+    }
+
+    pub fn config(&self) -> Arc<BlueskyConfiguration> {
+        self.client.read().unwrap().shared_config()
+    }
+
+    fn set_host(&self, host: &str) {
+        let mut client = self.client.write().unwrap();
+        if client.config().xrpc_host != host.trim_end_matches('/') {
+            info!("Using the PDS at {}", host);
+            *client = client.with_host(host);
+        }
+    }
+
+    /// Re-points the agent at the PDS in the session's DID document, e.g. when the entryway
+    /// hands out a session for an account that lives on another host.
+    fn follow_pds(&self, session: &CreateSessionResponse) {
+        if let Some(pds) = session.pds_endpoint() {
+            self.set_host(&pds);
+        }
+    }
+
+    /// Points the agent at the PDS of `identifier` if discovery is on and it is a handle or
+    /// DID, and returns the DID document it was found in.
+    async fn discover_pds(&self, identifier: &str) -> Option<DidDocument> {
+        let identity = self.identity.as_ref()?;
+        let identifier = identifier.trim().trim_start_matches('@');
+        if identifier.contains('@') {
+            // An email address.
+            return None;
+        }
+        match identity.resolve(identifier).await {
+            Ok(resolved) => match resolved.pds_endpoint() {
+                Some(pds) => {
+                    self.set_host(pds);
+                    Some(resolved.document)
+                }
+                None => {
+                    warn!("The DID document of {} names no PDS", identifier);
+                    None
+                }
+            },
+            Err(err) => {
+                warn!(
+                    "Could not discover the PDS of {}, using {}: {}",
+                    identifier,
+                    self.config().xrpc_host,
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// A snapshot of the current session.
+    pub fn session(&self) -> Option<CreateSessionResponse> {
+        self.session.read().unwrap().clone()
+    }
+
+    fn current_session(&self) -> XrpcResult<CreateSessionResponse> {
+        self.session().ok_or(XrpcError::NoSession)
+    }
+
+    pub async fn login(&self, request: &CreateSessionRequest) -> XrpcResult<CreateSessionResponse> {
+        let document = self.discover_pds(&request.identifier).await;
+        let mut session = xrpc::create_session(request, &self.client()).await?;
+        if session.did_doc.is_none() {
+            // Keep the discovered document, so that a resumed session finds the PDS again.
+            session.did_doc = document
+                .filter(|document| document.id == session.did)
+                .and_then(|document| serde_json::to_value(document).ok());
+        }
+        self.follow_pds(&session);
+        *self.session.write().unwrap() = Some(session.clone());
+        self.session_changed(SessionEvent::Created).await;
+        Ok(session)
+    }
+
+    /// Takes a stored session into use after checking it with `getSession`, refreshing it first
+    /// if the access token has expired. If the session can't be refreshed anymore, it is
+    /// dropped and the error returned, and the caller needs to log in again.
+    pub async fn resume_session(
+        &self,
+        session: CreateSessionResponse,
+    ) -> XrpcResult<CreateSessionResponse> {
+        self.follow_pds(&session);
+        *self.session.write().unwrap() = Some(session);
+        let current = self
+            .call_authenticated(|access_jwt| async move {
+                xrpc::get_session(&access_jwt, &self.client()).await
+            })
+            .await;
+        match current {
+            Ok(current) => {
+                let updated = match self.session.write().unwrap().as_mut() {
+                    Some(session) => session
+                        .update_from_get_session(&current)
+                        .map(|()| self.follow_pds(session)),
+                    None => Ok(()),
+                };
+                if let Err(err) = updated {
+                    // A session for another account is of no use; the caller logs in again.
+                    warn!("Dropping the stored session: {}", err);
+                    self.expire_session().await;
+                    return Err(err);
+                }
+                self.session_changed(SessionEvent::Resumed).await;
+                self.current_session()
+            }
+            Err(err) => {
+                if err.auth_kind().is_some() {
+                    self.expire_session().await;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Resumes the session from the attached store. Returns `None` if there is no store or it
+    /// holds no session.
+    pub async fn resume_from_store(&self) -> Result<Option<CreateSessionResponse>> {
+        let Some(store) = &self.store else {
+            return Ok(None);
+        };
+        match store.load().await? {
+            Some(session) => Ok(Some(self.resume_session(session).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Revokes the session on the server and drops it, which also wipes it from the session
+    /// store. A session the server no longer accepts is dropped as well; on any other error it
+    /// is kept, so that the logout can be retried.
+    pub async fn logout(&self) -> XrpcResult<()> {
+        let _guard = self.refresh_lock.lock().await;
+        let session = self.current_session()?;
+        match xrpc::delete_session(&session.refresh_jwt, &self.client()).await {
+            Ok(()) => {}
+            Err(err) if err.auth_kind().is_some() => {
+                info!("The session was already revoked: {}", err);
+            }
+            Err(err) => return Err(err),
+        }
+        *self.session.write().unwrap() = None;
+        self.session_changed(SessionEvent::LoggedOut).await;
+        Ok(())
+    }
+
+    async fn expire_session(&self) {
+        info!("The session can no longer be refreshed, dropping it.");
+        *self.session.write().unwrap() = None;
+        self.session_changed(SessionEvent::Expired).await;
+    }
+
+    /// Refreshes the session now, whether or not it is about to expire.
+    pub async fn refresh_session(&self) -> XrpcResult<()> {
+        let access_jwt = self.current_session()?.access_jwt;
+        self.refresh_if_current(&access_jwt).await
+    }
+
+    /// Refreshes the session unless another task already replaced `stale_access_jwt` while we
+    /// were waiting for the refresh lock.
+    async fn refresh_if_current(&self, stale_access_jwt: &str) -> XrpcResult<()> {
+        let _guard = self.refresh_lock.lock().await;
+        let session = self.current_session()?;
+        if session.access_jwt != stale_access_jwt {
+            return Ok(());
+        }
+        let refreshed = match xrpc::refresh_session(&session.refresh_jwt, &self.client()).await {
+            Ok(refreshed) => refreshed,
+            Err(err) => {
+                if err.auth_kind().is_some() {
+                    self.expire_session().await;
+                }
+                return Err(err);
+            }
+        };
+        let updated = match self.session.write().unwrap().as_mut() {
+            Some(session) => session
+                .update_from_refresh(&refreshed)
+                .map(|()| self.follow_pds(session)),
+            None => Ok(()),
+        };
+        if let Err(err) = updated {
+            warn!("Dropping the session: {}", err);
+            self.expire_session().await;
+            return Err(err);
+        }
+        self.session_changed(SessionEvent::Refreshed).await;
+        Ok(())
+    }
+
+    async fn fresh_access_jwt(&self) -> XrpcResult<String> {
+        let session = self.current_session()?;
+        if !session.session_needs_refresh() {
+            return Ok(session.access_jwt);
+        }
+        self.refresh_if_current(&session.access_jwt).await?;
+        Ok(self.current_session()?.access_jwt)
+    }
+
+    /// Runs an authenticated call with a fresh access token, refreshing and replaying it once
+    /// if the server reports the token as expired.
+    pub async fn call_authenticated<T, F, Fut>(&self, call: F) -> XrpcResult<T>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = XrpcResult<T>>,
+    {
+        let access_jwt = self.fresh_access_jwt().await?;
+        match call(access_jwt.clone()).await {
+            Err(err) if err.is_expired_token() => {
+                info!("Access token expired, refreshing the session and retrying.");
+                self.refresh_if_current(&access_jwt).await?;
+                call(self.current_session()?.access_jwt).await
+            }
+            result => result,
+        }
+    }
+
+    pub async fn get_profile(&self, actor: &str) -> XrpcResult<ProfileViewDetailedResponse> {
+        self.call_authenticated(|access_jwt| async move {
+            xrpc::get_profile(actor, &access_jwt, &self.client()).await
+        })
+        .await
+    }
+
+    /// Creates the post in the session's own repo.
+    pub async fn create_post(&self, post: Post) -> XrpcResult<StrongRef> {
+        let request = CreatePostRequest::new(&self.current_session()?.did, post);
+        let response = self.create_record(&request).await?;
+        Ok(response.strong_ref())
+    }
+
+    pub async fn create_record<R: Record>(
+        &self,
+        request: &CreateRecordRequest<R>,
+    ) -> XrpcResult<CreateRecordResponse> {
+        self.call_authenticated(|access_jwt| async move {
+            xrpc::create_record(request, &access_jwt, &self.client()).await
+        })
+        .await
+    }
+
+    pub async fn put_record<R: Record>(
+        &self,
+        request: &PutRecordRequest<R>,
+    ) -> XrpcResult<PutRecordResponse> {
+        self.call_authenticated(|access_jwt| async move {
+            xrpc::put_record(request, &access_jwt, &self.client()).await
+        })
+        .await
+    }
+
+    pub async fn delete_record(
+        &self,
+        request: &DeleteRecordRequest,
+    ) -> XrpcResult<DeleteRecordResponse> {
+        self.call_authenticated(|access_jwt| async move {
+            xrpc::delete_record(request, &access_jwt, &self.client()).await
+        })
+        .await
+    }
+
+    /// Uploads a blob for a record to reference. Uploads from a reader can't be sent twice, so
+    /// they fail instead of being retried after the session is refreshed.
+    pub async fn upload_blob(&self, upload: &BlobUpload) -> XrpcResult<BlobRef> {
+        self.call_authenticated(|access_jwt| async move {
+            xrpc::upload_blob(upload, &access_jwt, &self.client()).await
+        })
+        .await
+    }
+
+    /// Applies the batch in calls of at most `MAX_WRITES_PER_CALL` writes. Each call commits
+    /// atomically, but if one fails, the calls before it stay committed. With a `swap_commit`,
+    /// every call after the first is swapped against the commit of the call before it.
+    pub async fn apply_writes(&self, batch: &ApplyWrites) -> XrpcResult<AppliedWrites> {
+        let mut applied = AppliedWrites::default();
+        let mut swap_commit = batch.swap_commit.clone();
+        let chunks = batch.chunks(MAX_WRITES_PER_CALL);
+        let last = chunks.len().saturating_sub(1);
+        for (index, mut chunk) in chunks.into_iter().enumerate() {
+            chunk.swap_commit = swap_commit.take();
+            let result = self
+                .call_authenticated(|access_jwt| {
+                    let chunk = &chunk;
+                    async move { xrpc::apply_writes(chunk, &access_jwt, &self.client()).await }
+                })
+                .await;
+            let response = match result {
+                Ok(response) => response,
+                Err(err) => {
+                    if !applied.results.is_empty() {
+                        warn!(
+                            "applyWrites failed after {} of {} writes were committed",
+                            applied.results.len(),
+                            batch.len()
+                        );
+                    }
+                    return Err(err);
+                }
+            };
+            // The call was committed either way, so results that don't add up are only unknown.
+            match response.results {
+                Some(results) if results.len() == chunk.len() => {
+                    applied.results.extend(results.into_iter().map(Some));
+                }
+                results => {
+                    if let Some(results) = results {
+                        warn!(
+                            "applyWrites returned {} results for {} writes",
+                            results.len(),
+                            chunk.len()
+                        );
+                    }
+                    applied.results.extend((0..chunk.len()).map(|_| None));
+                }
+            }
+            if batch.swap_commit.is_some() && index < last {
+                let Some(commit) = &response.commit else {
+                    return Err(XrpcError::Deserialization(format!(
+                        "applyWrites returned no commit to swap the next call against, after {} \
+                         of {} writes were committed",
+                        applied.results.len(),
+                        batch.len()
+                    )));
+                };
+                swap_commit = Some(commit.cid.clone());
+            }
+            applied.commits.extend(response.commit);
+        }
+        Ok(applied)
+    }
+
+    /// Fetches the record of type `R` at `rkey` in `repo`, a DID or handle.
+    pub async fn get_record<R: Record>(
+        &self,
+        repo: &str,
+        rkey: &RecordKey,
+    ) -> XrpcResult<GetRecordResponse<R>> {
+        self.call_authenticated(|access_jwt| async move {
+            xrpc::get_record(repo, rkey, None, Some(&access_jwt), &self.client()).await
+        })
+        .await
+    }
+
+    /// The strong reference to the current version of the record `uri` points to, to quote or
+    /// reply to it.
+    pub async fn resolve_strong_ref(&self, uri: &AtUri) -> XrpcResult<StrongRef> {
+        let response = self
+            .call_authenticated(|access_jwt| async move {
+                xrpc::get_record_at::<serde_json::Value>(
+                    uri,
+                    None,
+                    Some(&access_jwt),
+                    &self.client(),
+                )
+                .await
+            })
+            .await?;
+        let cid = response.cid.ok_or_else(|| {
+            XrpcError::Deserialization(format!("no CID in the response for {}", uri))
+        })?;
+        // The response names the repo by DID even when `uri` used a handle.
+        Ok(StrongRef {
+            uri: response.uri,
+            cid,
+        })
+    }
+
+    /// `resolve_strong_ref` for the bsky.app URL of a post, feed, list or starter pack, e.g.
+    /// to quote it with `Embed::quote`.
+    pub async fn resolve_web_url(&self, url: &str) -> XrpcResult<StrongRef> {
+        self.resolve_strong_ref(&AtUri::from_web_url(url)?).await
+    }
+
+    /// Lists a page of the records of type `R` in `repo`, newest first. Pass the previous
+    /// page's cursor to get the next one.
+    pub async fn list_records<R: Record>(
+        &self,
+        repo: &str,
+        limit: Option<u32>,
+        cursor: Option<&str>,
+    ) -> XrpcResult<ListRecordsResponse<R>> {
+        self.call_authenticated(|access_jwt| async move {
+            xrpc::list_records(
+                repo,
+                limit,
+                cursor,
+                false,
+                Some(&access_jwt),
+                &self.client(),
+            )
+            .await
+        })
+        .await
+    }
+}
 
 /// Returns the module name.
 pub fn get_module_name() -> Result<String> {
@@ -0,0 +1,271 @@
+use super::encode::to_dag_cbor;
+use crate::syntax::SyntaxError;
+use crate::xrpc::XrpcResult;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+const KIND: &str = "CID";
+const BASE32: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// The multicodec of DAG-CBOR, which records and repo blocks are encoded with.
+pub const DAG_CBOR: u64 = 0x71;
+/// The multicodec of raw bytes, which blobs are identified with.
+pub const RAW: u64 = 0x55;
+const SHA2_256: u64 = 0x12;
+
+/// A CIDv1 content identifier, e.g.
+/// `bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm`, written as base32 with the `b`
+/// multibase prefix, the only form atproto uses.
+///
+/// ```
+/// use rustysky::dag_cbor::Cid;
+///
+/// let cid = Cid::for_raw(b"");
+/// assert_eq!(cid, "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku");
+/// assert_eq!(cid.to_string().parse::<Cid>().unwrap(), cid);
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Cid {
+    codec: u64,
+    hash: u64,
+    digest: Vec<u8>,
+}
+
+impl Cid {
+    pub fn new(cid: &str) -> Result<Self, SyntaxError> {
+        let Some(encoded) = cid.strip_prefix('b') else {
+            return Err(SyntaxError::new(
+                KIND,
+                cid,
+                "only base32 CIDv1 starting with b is supported",
+            ));
+        };
+        let bytes = base32_decode(encoded)
+            .ok_or_else(|| SyntaxError::new(KIND, cid, "is not lowercase base32"))?;
+        Self::from_bytes(&bytes).map_err(|err| SyntaxError::new(KIND, cid, err.reason()))
+    }
+
+    /// Parses the binary form of a CID, as found in DAG-CBOR links and CAR files.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SyntaxError> {
+        let invalid = |reason: &str| SyntaxError::new(KIND, &base32_encode(bytes), reason);
+        let mut rest = bytes;
+        let mut next = || read_varint(&mut rest).ok_or_else(|| invalid("is truncated"));
+        if next()? != 1 {
+            return Err(invalid("must be a CIDv1"));
+        }
+        let codec = next()?;
+        let hash = next()?;
+        let length = next()?;
+        if rest.len() as u64 != length {
+            return Err(invalid("has a digest of the wrong length"));
+        }
+        Ok(Self {
+            codec,
+            hash,
+            digest: rest.to_vec(),
+        })
+    }
+
+    /// The CID of a DAG-CBOR block.
+    pub fn for_dag_cbor(block: &[u8]) -> Self {
+        Self::sha256(DAG_CBOR, block)
+    }
+
+    /// The CID of raw bytes, e.g. of a blob.
+    pub fn for_raw(bytes: &[u8]) -> Self {
+        Self::sha256(RAW, bytes)
+    }
+
+    /// The CID of a record, i.e. of its DAG-CBOR encoding. This is the `cid` the server
+    /// reports for the record and expects in `swapRecord`.
+    pub fn for_record<T: Serialize + ?Sized>(record: &T) -> XrpcResult<Self> {
+        Ok(Self::for_dag_cbor(&to_dag_cbor(record)?))
+    }
+
+    fn sha256(codec: u64, bytes: &[u8]) -> Self {
+        Self {
+            codec,
+            hash: SHA2_256,
+            digest: Sha256::digest(bytes).to_vec(),
+        }
+    }
+
+    /// The binary form: version, codec and multihash.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.digest.len() + 8);
+        for value in [1, self.codec, self.hash, self.digest.len() as u64] {
+            write_varint(&mut bytes, value);
+        }
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    /// The multicodec of the content, [`DAG_CBOR`] or [`RAW`] in atproto.
+    pub fn codec(&self) -> u64 {
+        self.codec
+    }
+
+    /// The multihash function code; `0x12` for sha-256.
+    pub fn hash_code(&self) -> u64 {
+        self.hash
+    }
+
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..63).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(encoded.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.bytes() {
+        let digit = BASE32.iter().position(|&b| b == c)? as u32;
+        buffer = (buffer << 5) | digit;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    // Whatever is left over is padding: less than a character, and zero.
+    (bits < 5 && buffer & ((1 << bits) - 1) == 0).then_some(out)
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "b{}", base32_encode(&self.to_bytes()))
+    }
+}
+
+impl fmt::Debug for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cid({})", self)
+    }
+}
+
+impl FromStr for Cid {
+    type Err = SyntaxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<&str> for Cid {
+    type Error = SyntaxError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Self::new(s)
+    }
+}
+
+impl PartialEq<str> for Cid {
+    fn eq(&self, other: &str) -> bool {
+        Cid::new(other).is_ok_and(|cid| cid == *self)
+    }
+}
+
+impl PartialEq<&str> for Cid {
+    fn eq(&self, other: &&str) -> bool {
+        Cid::new(other).is_ok_and(|cid| cid == *self)
+    }
+}
+
+impl serde::Serialize for Cid {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Cid {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Self::new(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let cid = Cid::new("bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm").unwrap();
+        assert_eq!(cid.codec(), DAG_CBOR);
+        assert_eq!(cid.hash_code(), SHA2_256);
+        assert_eq!(cid.digest().len(), 32);
+        assert_eq!(
+            cid.to_string(),
+            "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"
+        );
+        assert_eq!(Cid::from_bytes(&cid.to_bytes()).unwrap(), cid);
+    }
+
+    #[test]
+    fn test_known_cids() {
+        // The well-known CIDs of an empty file and of an empty DAG-CBOR map.
+        assert_eq!(
+            Cid::for_raw(b""),
+            "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku"
+        );
+        assert_eq!(
+            Cid::for_dag_cbor(&[0xa0]),
+            "bafyreigbtj4x7ip5legnfznufuopl4sg4knzc2cof6duas4b3q2fy6swua"
+        );
+    }
+
+    #[test]
+    fn test_invalid_cids() {
+        for cid in [
+            "",
+            "b",
+            "QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n",
+            "zb2rhe5P4gXftAwvA4eXQ5HJwsER2owDyS9sKaQRRVQPn93bA",
+            "BAFYREIE5737GDXLW5I64VZICHCALBA3Z2V5N6ICIFVX5XYTVSKE7MR3HPM",
+            "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hp",
+            "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpma",
+        ] {
+            assert!(Cid::new(cid).is_err(), "{}", cid);
+        }
+    }
+}
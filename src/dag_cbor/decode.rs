@@ -0,0 +1,158 @@
+use super::cid::Cid;
+use super::{bytes_to_json, canonical_order, TAG_CID};
+use crate::xrpc::{XrpcError, XrpcResult};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use std::cmp::Ordering;
+
+/// How deeply arrays and maps may nest, so that hostile input can't exhaust the stack.
+const MAX_DEPTH: usize = 128;
+
+/// Decodes DAG-CBOR into a value that deserializes from the atproto JSON data model.
+pub fn from_dag_cbor<T: DeserializeOwned>(bytes: &[u8]) -> XrpcResult<T> {
+    serde_json::from_value(decode(bytes)?)
+        .map_err(|err| XrpcError::Deserialization(err.to_string()))
+}
+
+/// Decodes canonical DAG-CBOR into atproto JSON, the inverse of [`encode`](super::encode).
+/// Anything that `encode` would not have produced is rejected: indefinite lengths, lengths
+/// that aren't in their shortest form, unsorted or duplicate map keys, floats, tags other than
+/// CID links and trailing bytes.
+pub fn decode(bytes: &[u8]) -> XrpcResult<Value> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let value = reader.value(0).map_err(|err| {
+        XrpcError::Deserialization(format!("DAG-CBOR at byte {}: {}", reader.pos, err))
+    })?;
+    if reader.pos != bytes.len() {
+        return Err(XrpcError::Deserialization(format!(
+            "DAG-CBOR has {} trailing bytes",
+            bytes.len() - reader.pos
+        )));
+    }
+    Ok(value)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or("unexpected end of input")?;
+        let taken = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+
+    /// Reads a major type and its argument, which must be in its shortest form.
+    fn header(&mut self) -> Result<(u8, u64), String> {
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        if major == 7 {
+            return Ok((major, u64::from(info)));
+        }
+        let (n, min) = match info {
+            0..=23 => return Ok((major, u64::from(info))),
+            24 => (u64::from(self.take(1)?[0]), 24),
+            25 => (u64::from(u16::from_be_bytes(self.fixed()?)), 1 << 8),
+            26 => (u64::from(u32::from_be_bytes(self.fixed()?)), 1 << 16),
+            27 => (u64::from_be_bytes(self.fixed()?), 1 << 32),
+            31 => return Err("indefinite lengths are not allowed".to_string()),
+            _ => return Err(format!("invalid additional information {}", info)),
+        };
+        if n < min {
+            return Err(format!("{} is not encoded in its shortest form", n));
+        }
+        Ok((major, n))
+    }
+
+    fn fixed<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn length(&mut self, n: u64) -> Result<usize, String> {
+        usize::try_from(n)
+            .ok()
+            .filter(|&n| n <= self.bytes.len() - self.pos)
+            .ok_or_else(|| format!("length {} runs past the end of the input", n))
+    }
+
+    fn text(&mut self, n: u64) -> Result<String, String> {
+        let n = self.length(n)?;
+        let bytes = self.take(n)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| "text is not valid UTF-8".to_string())
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!("nested more than {} levels deep", MAX_DEPTH));
+        }
+        let (major, n) = self.header()?;
+        match major {
+            0 => Ok(json!(n)),
+            1 => i64::try_from(n)
+                .map(|n| json!(-1 - n))
+                .map_err(|_| format!("-1 - {} does not fit in 64 bits", n)),
+            2 => {
+                let n = self.length(n)?;
+                Ok(bytes_to_json(self.take(n)?))
+            }
+            3 => self.text(n).map(Value::String),
+            4 => {
+                let n = self.length(n)?;
+                (0..n)
+                    .map(|_| self.value(depth + 1))
+                    .collect::<Result<_, _>>()
+                    .map(Value::Array)
+            }
+            5 => {
+                let n = self.length(n)?;
+                let mut map = Map::new();
+                let mut previous: Option<String> = None;
+                for _ in 0..n {
+                    let (major, len) = self.header()?;
+                    if major != 3 {
+                        return Err("map keys must be strings".to_string());
+                    }
+                    let key = self.text(len)?;
+                    if let Some(previous) = &previous {
+                        if canonical_order(previous, &key) != Ordering::Less {
+                            return Err(format!("map key {:?} is out of order or repeated", key));
+                        }
+                    }
+                    let value = self.value(depth + 1)?;
+                    map.insert(key.clone(), value);
+                    previous = Some(key);
+                }
+                Ok(Value::Object(map))
+            }
+            6 => {
+                if n != TAG_CID {
+                    return Err(format!("tag {} is not allowed", n));
+                }
+                let (major, len) = self.header()?;
+                let len = self.length(len)?;
+                let bytes = self.take(len)?;
+                match bytes.split_first() {
+                    Some((0, cid)) if major == 2 => {
+                        let cid = Cid::from_bytes(cid).map_err(|err| err.to_string())?;
+                        Ok(json!({ "$link": cid.to_string() }))
+                    }
+                    _ => Err("a CID link must be bytes starting with 0x00".to_string()),
+                }
+            }
+            _ => match n {
+                20 => Ok(Value::Bool(false)),
+                21 => Ok(Value::Bool(true)),
+                22 => Ok(Value::Null),
+                25..=27 => Err("floats are not allowed in the atproto data model".to_string()),
+                _ => Err(format!("simple value {} is not allowed", n)),
+            },
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use super::cid::Cid;
+use super::{bytes_from_json, sort_keys, TAG_CID};
+use crate::xrpc::{XrpcError, XrpcResult};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Encodes a value that serializes to the atproto JSON data model, e.g. a record.
+pub fn to_dag_cbor<T: Serialize + ?Sized>(value: &T) -> XrpcResult<Vec<u8>> {
+    let json =
+        serde_json::to_value(value).map_err(|err| XrpcError::Serialization(err.to_string()))?;
+    encode(&json)
+}
+
+/// Encodes atproto JSON as canonical DAG-CBOR: `{"$link": ..}` objects become CID links,
+/// `{"$bytes": ..}` objects become byte strings, map keys are sorted by length and then
+/// bytewise, and every length uses the shortest form. Floats are not part of the data model
+/// and are rejected.
+pub fn encode(value: &Value) -> XrpcResult<Vec<u8>> {
+    let mut out = Vec::new();
+    write_value(&mut out, value).map_err(XrpcError::Serialization)?;
+    Ok(out)
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) -> Result<(), String> {
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(false) => out.push(0xf4),
+        Value::Bool(true) => out.push(0xf5),
+        Value::Number(number) => {
+            if let Some(n) = number.as_u64() {
+                write_header(out, 0, n);
+            } else if let Some(n) = number.as_i64() {
+                write_header(out, 1, !(n as u64));
+            } else {
+                return Err(format!(
+                    "{} is a float, which the atproto data model does not allow",
+                    number
+                ));
+            }
+        }
+        Value::String(s) => write_text(out, s),
+        Value::Array(items) => {
+            write_header(out, 4, items.len() as u64);
+            for item in items {
+                write_value(out, item)?;
+            }
+        }
+        Value::Object(map) => write_object(out, map)?,
+    }
+    Ok(())
+}
+
+fn write_object(out: &mut Vec<u8>, map: &Map<String, Value>) -> Result<(), String> {
+    if let Some(link) = single(map, "$link") {
+        let cid = link
+            .as_str()
+            .and_then(|s| Cid::new(s).ok())
+            .ok_or_else(|| format!("{} is not a valid $link", link))?;
+        let bytes = cid.to_bytes();
+        write_header(out, 6, TAG_CID);
+        // Links carry the identity multibase prefix.
+        write_header(out, 2, bytes.len() as u64 + 1);
+        out.push(0);
+        out.extend_from_slice(&bytes);
+        return Ok(());
+    }
+    if let Some(encoded) = single(map, "$bytes") {
+        let bytes =
+            bytes_from_json(encoded).ok_or_else(|| format!("{} is not valid $bytes", encoded))?;
+        write_header(out, 2, bytes.len() as u64);
+        out.extend_from_slice(&bytes);
+        return Ok(());
+    }
+    let mut keys: Vec<&String> = map.keys().collect();
+    sort_keys(&mut keys);
+    write_header(out, 5, keys.len() as u64);
+    for key in keys {
+        write_text(out, key);
+        write_value(out, &map[key])?;
+    }
+    Ok(())
+}
+
+/// The value of `key` if it is the only key of the object.
+fn single<'a>(map: &'a Map<String, Value>, key: &str) -> Option<&'a Value> {
+    if map.len() == 1 {
+        map.get(key)
+    } else {
+        None
+    }
+}
+
+fn write_text(out: &mut Vec<u8>, s: &str) {
+    write_header(out, 3, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_header(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    if n < 24 {
+        out.push(major | n as u8);
+    } else if n <= u8::MAX as u64 {
+        out.extend_from_slice(&[major | 24, n as u8]);
+    } else if n <= u16::MAX as u64 {
+        out.push(major | 25);
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= u32::MAX as u64 {
+        out.push(major | 26);
+        out.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
@@ -0,0 +1,119 @@
+//! DAG-CBOR, the binary encoding of the atproto data model, and the CIDs that identify records
+//! and blobs. See <https://atproto.com/specs/data-model>.
+//!
+//! Values go through the JSON form of the data model, in which a CID link is written as
+//! `{"$link": "bafy..."}` and bytes as `{"$bytes": "<base64>"}`, so every record type that
+//! serializes to atproto JSON can be encoded and hashed.
+//!
+//! ```
+//! use rustysky::dag_cbor::{self, Cid};
+//! use serde_json::json;
+//!
+//! let record = json!({ "$type": "app.bsky.feed.like", "createdAt": "2024-01-01T00:00:00Z" });
+//! let block = dag_cbor::encode(&record).unwrap();
+//! assert_eq!(dag_cbor::decode(&block).unwrap(), record);
+//! assert_eq!(Cid::for_record(&record).unwrap(), Cid::for_dag_cbor(&block));
+//! ```
+
+mod cid;
+mod decode;
+mod encode;
+
+pub use cid::{Cid, DAG_CBOR, RAW};
+pub use decode::{decode, from_dag_cbor};
+pub use encode::{encode, to_dag_cbor};
+
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+
+/// The CBOR tag of a CID link.
+const TAG_CID: u64 = 42;
+
+/// DAG-CBOR orders map keys by their length first, then bytewise.
+fn canonical_order(a: &str, b: &str) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+fn sort_keys(keys: &mut [&String]) {
+    keys.sort_by(|a, b| canonical_order(a, b));
+}
+
+fn bytes_to_json(bytes: &[u8]) -> Value {
+    json!({ "$bytes": STANDARD_NO_PAD.encode(bytes) })
+}
+
+/// `$bytes` is unpadded base64, but padded input is accepted too.
+fn bytes_from_json(value: &Value) -> Option<Vec<u8>> {
+    let encoded = value.as_str()?;
+    STANDARD_NO_PAD
+        .decode(encoded)
+        .or_else(|_| STANDARD.decode(encoded))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_order() {
+        let keys = ["bb", "a", "aaa", "b", "ab"].map(String::from);
+        let mut keys: Vec<&String> = keys.iter().collect();
+        sort_keys(&mut keys);
+        assert_eq!(keys, ["a", "b", "ab", "bb", "aaa"]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let value = json!({
+            "text": "hello",
+            "count": -1000,
+            "big": u64::MAX,
+            "flags": [true, false, null],
+            "data": { "$bytes": "AAEC" },
+            "ref": { "$link": "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm" },
+            "nested": { "$link": "not alone", "other": 1 },
+        });
+        let bytes = encode(&value).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(encode(&json!({})).unwrap(), [0xa0]);
+        assert_eq!(encode(&json!(-1)).unwrap(), [0x20]);
+        assert_eq!(encode(&json!(24)).unwrap(), [0x18, 24]);
+        assert_eq!(encode(&json!(i64::MIN)).unwrap()[0], 0x3b);
+        assert_eq!(
+            encode(&json!({ "b": 1, "aa": 2 })).unwrap(),
+            [0xa2, 0x61, b'b', 0x01, 0x62, b'a', b'a', 0x02]
+        );
+        assert!(encode(&json!(1.5)).is_err());
+        assert!(encode(&json!({ "$link": "bafy" })).is_err());
+        assert!(encode(&json!({ "$bytes": "!!" })).is_err());
+    }
+
+    #[test]
+    fn test_strict_decoding() {
+        for bytes in [
+            &[][..],
+            &[0xa0, 0x00],                               // trailing bytes
+            &[0x18, 0x01],                               // not the shortest form
+            &[0x9f, 0xff],                               // indefinite length
+            &[0xf9, 0x3c, 0x00],                         // float
+            &[0xa2, 0x61, b'b', 0x01, 0x61, b'a', 0x02], // unsorted keys
+            &[0xa2, 0x61, b'a', 0x01, 0x61, b'a', 0x02], // repeated key
+            &[0xa1, 0x01, 0x02],                         // integer key
+            &[0xc1, 0x00],                               // tag other than 42
+            &[0xd8, 0x2a, 0x41, 0x01],                   // link without the 0x00 prefix
+            &[0x62, 0xff, 0xfe],                         // invalid UTF-8
+            &[0x5a, 0xff, 0xff, 0xff, 0xff],             // length past the end
+        ] {
+            assert!(decode(bytes).is_err(), "{:02x?}", bytes);
+        }
+        let deep = [vec![0x81; 200], vec![0xf6]].concat();
+        assert!(decode(&deep).is_err());
+    }
+}
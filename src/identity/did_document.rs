@@ -0,0 +1,136 @@
+use crate::syntax::Did;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/*
+A DID document as atproto uses it, e.g.
+{
+    "id": "did:plc:ewvi7nxzyoun6zhxrhs64oiz",
+    "alsoKnownAs": ["at://atproto.com"],
+    "verificationMethod": [{
+        "id": "did:plc:ewvi7nxzyoun6zhxrhs64oiz#atproto",
+        "type": "Multikey",
+        "controller": "did:plc:ewvi7nxzyoun6zhxrhs64oiz",
+        "publicKeyMultibase": "zQ3shunBKsXixLxKtC5qeSG9E4J5RkGN57im31pcTzbNQnm5w"
+    }],
+    "service": [{
+        "id": "#atproto_pds",
+        "type": "AtprotoPersonalDataServer",
+        "serviceEndpoint": "https://enoki.us-east.host.bsky.network"
+    }]
+}
+*/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidDocument {
+    #[serde(rename = "@context", skip_serializing_if = "Option::is_none")]
+    pub context: Option<Value>,
+    pub id: Did,
+    #[serde(default)]
+    pub also_known_as: Vec<String>,
+    #[serde(default)]
+    pub verification_method: Vec<VerificationMethod>,
+    #[serde(default)]
+    pub service: Vec<Service>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationMethod {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub method_type: String,
+    pub controller: String,
+    pub public_key_multibase: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Service {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub service_type: String,
+    /// A URL for atproto services. Other services may use a map or a list.
+    pub service_endpoint: Value,
+}
+
+impl DidDocument {
+    /// The handle the DID claims, from its first `at://` alias. It still has to be verified
+    /// by resolving it back to the DID.
+    pub fn handle(&self) -> Option<&str> {
+        self.also_known_as
+            .iter()
+            .find_map(|alias| alias.strip_prefix("at://"))
+    }
+
+    /// The URL of the account's PDS.
+    pub fn pds_endpoint(&self) -> Option<&str> {
+        self.service
+            .iter()
+            .find(|service| {
+                self.is_fragment(&service.id, "atproto_pds")
+                    && service.service_type == "AtprotoPersonalDataServer"
+            })
+            .and_then(|service| service.service_endpoint.as_str())
+            .map(|endpoint| endpoint.trim_end_matches('/'))
+    }
+
+    /// The key that signs the account's repo commits.
+    pub fn signing_key(&self) -> Option<&VerificationMethod> {
+        self.verification_method
+            .iter()
+            .find(|method| self.is_fragment(&method.id, "atproto"))
+    }
+
+    /// Ids may be relative (`#atproto`) or absolute (`did:plc:...#atproto`).
+    fn is_fragment(&self, id: &str, fragment: &str) -> bool {
+        let id = id.strip_prefix(self.id.as_str()).unwrap_or(id);
+        id.strip_prefix('#') == Some(fragment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_atproto_helpers() {
+        let document: DidDocument = serde_json::from_value(json!({
+            "@context": ["https://www.w3.org/ns/did/v1"],
+            "id": "did:plc:alice",
+            "alsoKnownAs": ["https://alice.example.com", "at://alice.test"],
+            "verificationMethod": [{
+                "id": "did:plc:alice#atproto",
+                "type": "Multikey",
+                "controller": "did:plc:alice",
+                "publicKeyMultibase": "zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF"
+            }],
+            "service": [
+                {"id": "#bsky_chat", "type": "BskyChatService", "serviceEndpoint": {"origin": "x"}},
+                {"id": "#atproto_pds", "type": "AtprotoPersonalDataServer", "serviceEndpoint": "https://pds.example.com/"}
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(document.handle(), Some("alice.test"));
+        assert_eq!(document.pds_endpoint(), Some("https://pds.example.com"));
+        assert_eq!(
+            document
+                .signing_key()
+                .unwrap()
+                .public_key_multibase
+                .as_deref(),
+            Some("zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF")
+        );
+    }
+
+    #[test]
+    fn test_minimal_document() {
+        let document: DidDocument =
+            serde_json::from_value(json!({"id": "did:web:alice.test"})).unwrap();
+        assert_eq!(document.handle(), None);
+        assert_eq!(document.pds_endpoint(), None);
+        assert!(document.signing_key().is_none());
+    }
+}
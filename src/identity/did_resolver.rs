@@ -0,0 +1,201 @@
+use super::did_document::DidDocument;
+use crate::xrpc::{decode_response, HttpMethod, HttpRequest, XrpcClient, XrpcError, XrpcResult};
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_PLC_DIRECTORY: &str = "https://plc.directory";
+
+/// DID documents are a few KB at most; did:web ones come from any domain.
+const MAX_DOCUMENT_SIZE: u64 = 64 * 1024;
+
+/// Fetches DID documents: `did:plc` from a PLC directory, `did:web` from the domain's
+/// `/.well-known/did.json`. Documents are cached; clones share the cache.
+///
+/// ```no_run
+/// # async fn example() -> rustysky::xrpc::XrpcResult<()> {
+/// use rustysky::identity::DidResolver;
+/// use rustysky::types::get_default_configuration;
+/// use rustysky::xrpc::XrpcClient;
+///
+/// let resolver = DidResolver::new(XrpcClient::new(get_default_configuration())?);
+/// let document = resolver.resolve("did:plc:z72i7hdynmk6r22z27h6tvur").await?;
+/// println!("{:?}", document.pds_endpoint());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct DidResolver {
+    client: XrpcClient,
+    plc_directory: String,
+    timeout: Duration,
+    cache_ttl: Duration,
+    cache: Arc<Mutex<HashMap<String, (DidDocument, Instant)>>>,
+}
+
+impl DidResolver {
+    /// Uses `https://plc.directory`, with a 10 second timeout and a 10 minute cache.
+    pub fn new(client: XrpcClient) -> Self {
+        Self {
+            client,
+            plc_directory: DEFAULT_PLC_DIRECTORY.to_string(),
+            timeout: Duration::from_secs(10),
+            cache_ttl: Duration::from_secs(600),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn with_plc_directory(mut self, plc_directory: &str) -> Self {
+        self.plc_directory = plc_directory.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How long documents are cached. `Duration::ZERO` turns the cache off.
+    pub fn with_cache_ttl(mut self, cache_ttl: Duration) -> Self {
+        self.cache_ttl = cache_ttl;
+        self
+    }
+
+    /// The DID document of `did`. A document that describes another DID is an error.
+    pub async fn resolve(&self, did: &str) -> XrpcResult<DidDocument> {
+        if let Some(document) = self.cached(did) {
+            return Ok(document);
+        }
+
+        let document = tokio::time::timeout(self.timeout, self.fetch(did))
+            .await
+            .map_err(|_| XrpcError::Transport(format!("Timed out after {:?}", self.timeout)))?
+            .map_err(|err| match err.status() {
+                Some(404) => XrpcError::Identity(format!("{} is not registered", did)),
+                Some(410) => XrpcError::Identity(format!("{} has been deactivated", did)),
+                _ => err,
+            })?;
+        if document.id != did {
+            return Err(XrpcError::Identity(format!(
+                "The document of {} is for {}",
+                did, document.id
+            )));
+        }
+
+        if !self.cache_ttl.is_zero() {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(did.to_string(), (document.clone(), Instant::now()));
+        }
+        Ok(document)
+    }
+
+    /// Fetches the document straight through the transport: the PLC directory's or domain's
+    /// rate limits have nothing to do with the PDS client's. Oversized documents are refused.
+    async fn fetch(&self, did: &str) -> XrpcResult<DidDocument> {
+        let request = HttpRequest {
+            method: HttpMethod::Get,
+            url: self.document_url(did)?,
+            headers: HeaderMap::new(),
+            body: None,
+            stream: None,
+        };
+        let response = self
+            .client
+            .transport()
+            .send_limited(request, MAX_DOCUMENT_SIZE)
+            .await
+            .map_err(|err| match err {
+                XrpcError::Deserialization(_) => XrpcError::Identity(format!(
+                    "The document of {} is larger than {} bytes",
+                    did, MAX_DOCUMENT_SIZE
+                )),
+                err => err,
+            })?;
+        decode_response(response, self.client.config())
+    }
+
+    /// Forgets the cached document of `did`, e.g. after the account moved to another PDS.
+    pub fn invalidate(&self, did: &str) {
+        self.cache.lock().unwrap().remove(did);
+    }
+
+    fn cached(&self, did: &str) -> Option<DidDocument> {
+        let cache = self.cache.lock().unwrap();
+        let (document, resolved_at) = cache.get(did)?;
+        (resolved_at.elapsed() < self.cache_ttl).then(|| document.clone())
+    }
+
+    fn document_url(&self, did: &str) -> XrpcResult<String> {
+        if let Some(id) = did.strip_prefix("did:plc:") {
+            if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(XrpcError::Identity(format!("Invalid DID {}", did)));
+            }
+            return Ok(format!("{}/{}", self.plc_directory, did));
+        }
+        if let Some(host) = did.strip_prefix("did:web:") {
+            // atproto only allows hostnames, with the port percent-encoded, not paths.
+            let host = host.replace("%3A", ":").replace("%3a", ":");
+            if host.is_empty() || host.contains(['/', '%']) || host.matches(':').count() > 1 {
+                return Err(XrpcError::Identity(format!("Unsupported did:web {}", did)));
+            }
+            let scheme = if host.starts_with("localhost") {
+                "http"
+            } else {
+                "https"
+            };
+            return Ok(format!("{}://{}/.well-known/did.json", scheme, host));
+        }
+        Err(XrpcError::Identity(format!(
+            "Unsupported DID method in {}",
+            did
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::get_default_configuration;
+
+    fn resolver() -> DidResolver {
+        DidResolver::new(XrpcClient::new(get_default_configuration()).unwrap())
+            .with_plc_directory("https://plc.example.com/")
+    }
+
+    #[test]
+    fn test_document_urls() {
+        let resolver = resolver();
+        assert_eq!(
+            resolver.document_url("did:plc:abc234").unwrap(),
+            "https://plc.example.com/did:plc:abc234"
+        );
+        assert_eq!(
+            resolver.document_url("did:web:alice.test").unwrap(),
+            "https://alice.test/.well-known/did.json"
+        );
+        assert_eq!(
+            resolver.document_url("did:web:localhost%3A8080").unwrap(),
+            "http://localhost:8080/.well-known/did.json"
+        );
+    }
+
+    #[test]
+    fn test_rejects_unsupported_dids() {
+        let resolver = resolver();
+        for did in [
+            "did:key:zQ3sh",
+            "did:plc:../admin",
+            "did:web:alice.test:users:bob",
+            "handle.test",
+        ] {
+            assert!(
+                matches!(resolver.document_url(did), Err(XrpcError::Identity(_))),
+                "{}",
+                did
+            );
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::xrpc::{HttpMethod, HttpRequest, XrpcClient, XrpcError, XrpcResult};
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
+use serde::Deserialize;
+
+pub const CLOUDFLARE_DOH: &str = "https://cloudflare-dns.com/dns-query";
+pub const GOOGLE_DOH: &str = "https://dns.google/resolve";
+
+const TXT: u16 = 16;
+
+/// Looks up DNS TXT records for handle resolution. The default is `DohResolver`; implement this
+/// to use the system resolver or a DNS library instead.
+#[async_trait]
+pub trait DnsResolver: Send + Sync {
+    /// The TXT records of `name`, each with its character strings joined. A name without TXT
+    /// records, or that does not exist, has none.
+    async fn txt_records(&self, name: &str) -> XrpcResult<Vec<String>>;
+}
+
+/*
+The JSON API of DNS-over-HTTPS resolvers, e.g.
+{"Status": 0, "Answer": [{"name": "_atproto.example.com", "type": 16, "TTL": 300, "data": "\"did=did:plc:abc\""}]}
+*/
+#[derive(Debug, Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Status")]
+    status: u32,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    data: String,
+}
+
+/// Resolves TXT records with the JSON API of a DNS-over-HTTPS resolver, through the client's
+/// transport. Works everywhere HTTPS does, without a DNS library.
+pub struct DohResolver {
+    client: XrpcClient,
+    endpoint: String,
+}
+
+impl DohResolver {
+    /// Uses Cloudflare's resolver.
+    pub fn new(client: XrpcClient) -> Self {
+        Self::with_endpoint(client, CLOUDFLARE_DOH)
+    }
+
+    /// Uses the resolver at `endpoint`, e.g. `GOOGLE_DOH`.
+    pub fn with_endpoint(client: XrpcClient, endpoint: &str) -> Self {
+        Self {
+            client,
+            endpoint: endpoint.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl DnsResolver for DohResolver {
+    async fn txt_records(&self, name: &str) -> XrpcResult<Vec<String>> {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/dns-json"));
+        let request = HttpRequest {
+            method: HttpMethod::Get,
+            url: format!("{}?name={}&type=TXT", self.endpoint, name),
+            headers,
+            body: None,
+            stream: None,
+        };
+        let response = self.client.transport().send(request).await?;
+        if !response.is_success() {
+            return Err(XrpcError::from_response(
+                response.status,
+                &String::from_utf8_lossy(&response.body),
+            ));
+        }
+        let response: DohResponse = serde_json::from_slice(&response.body)
+            .map_err(|err| XrpcError::Deserialization(err.to_string()))?;
+
+        match response.status {
+            0 => Ok(response
+                .answer
+                .iter()
+                .filter(|answer| answer.record_type == TXT)
+                .map(|answer| parse_txt_data(&answer.data))
+                .collect()),
+            // NXDOMAIN
+            3 => Ok(Vec::new()),
+            status => Err(XrpcError::Identity(format!(
+                "DNS lookup of {} failed with rcode {}",
+                name, status
+            ))),
+        }
+    }
+}
+
+/// Joins the character strings of a TXT record in presentation format, e.g.
+/// `"did=did:plc:" "abc"` is `did=did:plc:abc`.
+fn parse_txt_data(data: &str) -> String {
+    if !data.starts_with('"') {
+        return data.to_string();
+    }
+    let mut joined = String::new();
+    let mut in_string = false;
+    let mut chars = data.chars();
+    while let Some(c) = chars.next() {
+        match (c, in_string) {
+            ('"', _) => in_string = !in_string,
+            ('\\', true) => joined.extend(chars.next()),
+            (c, true) => joined.push(c),
+            _ => {}
+        }
+    }
+    joined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_txt_presentation_format() {
+        assert_eq!(parse_txt_data(r#""did=did:plc:abc""#), "did=did:plc:abc");
+        assert_eq!(parse_txt_data(r#""did=did:plc:" "abc""#), "did=did:plc:abc");
+        assert_eq!(parse_txt_data(r#""say \"hi\"""#), r#"say "hi""#);
+        assert_eq!(parse_txt_data("did=did:plc:abc"), "did=did:plc:abc");
+    }
+}
@@ -0,0 +1,192 @@
+use super::dns::{DnsResolver, DohResolver};
+use crate::syntax::{Did, Handle};
+use crate::xrpc::{self, HttpMethod, HttpRequest, XrpcClient, XrpcError, XrpcResult};
+use log::debug;
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The `/.well-known/atproto-did` body is only a DID.
+const MAX_WELL_KNOWN_SIZE: u64 = 4 * 1024;
+
+/// Resolves handles to DIDs the way the atproto spec asks for: with the `_atproto.<handle>`
+/// TXT record, then with `https://<handle>/.well-known/atproto-did`. If neither works, the
+/// host's `com.atproto.identity.resolveHandle` is asked, unless that is turned off.
+///
+/// Successful resolutions are cached. Clones share the cache.
+///
+/// ```no_run
+/// # async fn example() -> rustysky::xrpc::XrpcResult<()> {
+/// use rustysky::identity::HandleResolver;
+/// use rustysky::types::get_default_configuration;
+/// use rustysky::xrpc::XrpcClient;
+///
+/// let resolver = HandleResolver::new(XrpcClient::new(get_default_configuration())?);
+/// let did = resolver.resolve("bsky.app").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct HandleResolver {
+    client: XrpcClient,
+    dns: Arc<dyn DnsResolver>,
+    timeout: Duration,
+    cache_ttl: Duration,
+    fallback: bool,
+    cache: Arc<Mutex<HashMap<Handle, (Did, Instant)>>>,
+}
+
+impl HandleResolver {
+    /// Resolves DNS over HTTPS, with a 5 second timeout per method and a 10 minute cache.
+    pub fn new(client: XrpcClient) -> Self {
+        Self {
+            dns: Arc::new(DohResolver::new(client.clone())),
+            client,
+            timeout: Duration::from_secs(5),
+            cache_ttl: Duration::from_secs(600),
+            fallback: true,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn with_dns_resolver(mut self, dns: Arc<dyn DnsResolver>) -> Self {
+        self.dns = dns;
+        self
+    }
+
+    /// How long each resolution method may take before the next one is tried.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How long resolved handles are cached. `Duration::ZERO` turns the cache off.
+    pub fn with_cache_ttl(mut self, cache_ttl: Duration) -> Self {
+        self.cache_ttl = cache_ttl;
+        self
+    }
+
+    /// Whether to ask the host's `resolveHandle` when DNS and HTTPS both fail.
+    pub fn with_fallback(mut self, fallback: bool) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Resolves `handle` (with or without a leading `@`) to a DID.
+    pub async fn resolve(&self, handle: &str) -> XrpcResult<Did> {
+        let handle = Handle::new(handle.trim().trim_start_matches('@'))?;
+        if let Some(did) = self.cached(&handle) {
+            return Ok(did);
+        }
+
+        let mut did = None;
+        match self.timed(self.resolve_dns(&handle)).await {
+            Ok(found) => did = found,
+            Err(err) => debug!("DNS resolution of {} failed: {}", handle, err),
+        }
+        if did.is_none() {
+            match self.timed(self.resolve_well_known(&handle)).await {
+                Ok(found) => did = found,
+                Err(err) => debug!("HTTPS resolution of {} failed: {}", handle, err),
+            }
+        }
+        if did.is_none() && self.fallback {
+            match self
+                .timed(xrpc::resolve_handle(&handle, &self.client))
+                .await
+            {
+                Ok(response) => did = Some(response.did),
+                Err(err) => debug!("resolveHandle of {} failed: {}", handle, err),
+            }
+        }
+
+        let did =
+            did.ok_or_else(|| XrpcError::Identity(format!("Could not resolve handle {}", handle)))?;
+        if !self.cache_ttl.is_zero() {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(handle, (did.clone(), Instant::now()));
+        }
+        Ok(did)
+    }
+
+    /// Forgets the cached DID of `handle`, e.g. after it failed verification.
+    pub fn invalidate(&self, handle: &str) {
+        if let Ok(handle) = Handle::new(handle.trim().trim_start_matches('@')) {
+            self.cache.lock().unwrap().remove(&handle);
+        }
+    }
+
+    fn cached(&self, handle: &Handle) -> Option<Did> {
+        let cache = self.cache.lock().unwrap();
+        let (did, resolved_at) = cache.get(handle)?;
+        (resolved_at.elapsed() < self.cache_ttl).then(|| did.clone())
+    }
+
+    async fn timed<T>(&self, future: impl Future<Output = XrpcResult<T>>) -> XrpcResult<T> {
+        tokio::time::timeout(self.timeout, future)
+            .await
+            .map_err(|_| XrpcError::Transport(format!("Timed out after {:?}", self.timeout)))?
+    }
+
+    /// The DID in the `_atproto.<handle>` TXT record. More than one DID there is an error.
+    pub async fn resolve_dns(&self, handle: &str) -> XrpcResult<Option<Did>> {
+        let records = self
+            .dns
+            .txt_records(&format!("_atproto.{}", handle))
+            .await?;
+        let mut dids: Vec<&str> = records
+            .iter()
+            .filter_map(|record| record.strip_prefix("did="))
+            .map(str::trim)
+            .collect();
+        dids.sort_unstable();
+        dids.dedup();
+        match dids.as_slice() {
+            [] => Ok(None),
+            [did] => Ok(Some(Did::new(did)?)),
+            _ => Err(XrpcError::Identity(format!(
+                "_atproto.{} has more than one DID",
+                handle
+            ))),
+        }
+    }
+
+    /// The DID served at `https://<handle>/.well-known/atproto-did`. Bodies over 4 KB are
+    /// refused.
+    pub async fn resolve_well_known(&self, handle: &str) -> XrpcResult<Option<Did>> {
+        let request = HttpRequest {
+            method: HttpMethod::Get,
+            url: format!("https://{}/.well-known/atproto-did", handle),
+            headers: HeaderMap::new(),
+            body: None,
+            stream: None,
+        };
+        let response = self
+            .client
+            .transport()
+            .send_limited(request, MAX_WELL_KNOWN_SIZE)
+            .await
+            .map_err(|err| match err {
+                XrpcError::Deserialization(_) => XrpcError::Identity(format!(
+                    "https://{}/.well-known/atproto-did is larger than {} bytes",
+                    handle, MAX_WELL_KNOWN_SIZE
+                )),
+                err => err,
+            })?;
+        if response.status == 404 {
+            return Ok(None);
+        }
+        if !response.is_success() {
+            return Err(XrpcError::from_response(
+                response.status,
+                &String::from_utf8_lossy(&response.body),
+            ));
+        }
+        let body = String::from_utf8_lossy(&response.body);
+        Ok(Some(Did::new(body.trim())?))
+    }
+}
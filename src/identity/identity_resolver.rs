@@ -0,0 +1,107 @@
+use super::did_document::DidDocument;
+use super::did_resolver::DidResolver;
+use super::handle_resolver::HandleResolver;
+use crate::syntax::{Did, Handle};
+use crate::xrpc::{XrpcClient, XrpcError, XrpcResult};
+
+/// An account's DID and document, with the handle only if it resolves back to the DID.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedIdentity {
+    pub did: Did,
+    pub handle: Option<Handle>,
+    pub document: DidDocument,
+}
+
+impl ResolvedIdentity {
+    /// The verified handle, or `handle.invalid`.
+    pub fn display_handle(&self) -> &str {
+        self.handle.as_deref().unwrap_or(Handle::INVALID)
+    }
+
+    pub fn pds_endpoint(&self) -> Option<&str> {
+        self.document.pds_endpoint()
+    }
+}
+
+/// Resolves handles and DIDs and checks them against each other: a handle belongs to an
+/// account only if it resolves to the DID *and* the DID document claims the handle.
+#[derive(Clone)]
+pub struct IdentityResolver {
+    handles: HandleResolver,
+    dids: DidResolver,
+}
+
+impl IdentityResolver {
+    pub fn new(client: XrpcClient) -> Self {
+        Self {
+            handles: HandleResolver::new(client.clone()),
+            dids: DidResolver::new(client),
+        }
+    }
+
+    /// Uses the given resolvers, e.g. with another PLC directory or DNS backend.
+    pub fn with_resolvers(handles: HandleResolver, dids: DidResolver) -> Self {
+        Self { handles, dids }
+    }
+
+    pub fn handles(&self) -> &HandleResolver {
+        &self.handles
+    }
+
+    pub fn dids(&self) -> &DidResolver {
+        &self.dids
+    }
+
+    /// Resolves `handle` and checks that its DID claims it back.
+    pub async fn resolve_handle(&self, handle: &str) -> XrpcResult<ResolvedIdentity> {
+        let handle = Handle::new(handle.trim().trim_start_matches('@'))?;
+        let did = self.handles.resolve(&handle).await?;
+        let document = self.dids.resolve(&did).await?;
+        if !claims(&document, &handle) {
+            self.handles.invalidate(&handle);
+            return Err(XrpcError::Identity(format!(
+                "{} resolves to {}, which does not claim it",
+                handle, did
+            )));
+        }
+        Ok(ResolvedIdentity {
+            did,
+            handle: Some(handle),
+            document,
+        })
+    }
+
+    /// Resolves `did` and verifies the handle it claims. A claimed handle that doesn't resolve
+    /// back to the DID is left out rather than failing the resolution.
+    pub async fn resolve_did(&self, did: &str) -> XrpcResult<ResolvedIdentity> {
+        let did = Did::new(did)?;
+        let document = self.dids.resolve(&did).await?;
+        let handle = match document.handle().map(Handle::new) {
+            Some(Ok(claimed)) => match self.handles.resolve(&claimed).await {
+                Ok(resolved) if resolved == did => Some(claimed),
+                _ => None,
+            },
+            _ => None,
+        };
+        Ok(ResolvedIdentity {
+            did,
+            handle,
+            document,
+        })
+    }
+
+    /// Resolves a handle or a DID.
+    pub async fn resolve(&self, identifier: &str) -> XrpcResult<ResolvedIdentity> {
+        if identifier.starts_with("did:") {
+            self.resolve_did(identifier).await
+        } else {
+            self.resolve_handle(identifier).await
+        }
+    }
+}
+
+fn claims(document: &DidDocument, handle: &str) -> bool {
+    document
+        .handle()
+        .is_some_and(|claimed| claimed.eq_ignore_ascii_case(handle))
+}
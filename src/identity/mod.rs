@@ -0,0 +1,11 @@
+mod did_document;
+mod did_resolver;
+mod dns;
+mod handle_resolver;
+mod identity_resolver;
+
+pub use did_document::{DidDocument, Service, VerificationMethod};
+pub use did_resolver::{DidResolver, DEFAULT_PLC_DIRECTORY};
+pub use dns::{DnsResolver, DohResolver, CLOUDFLARE_DOH, GOOGLE_DOH};
+pub use handle_resolver::HandleResolver;
+pub use identity_resolver::{IdentityResolver, ResolvedIdentity};
@@ -1,6 +1,15 @@
+pub mod accounts;
 pub mod bsky_agent;
 pub mod client;
+pub mod dag_cbor;
+pub mod identity;
+pub mod link_card;
+#[cfg(feature = "media")]
+pub mod media;
 pub mod moderation;
+pub mod oauth;
 pub mod richtext;
+pub mod session;
+pub mod syntax;
 pub mod types;
 pub mod xrpc;
@@ -0,0 +1,137 @@
+use super::fetcher::{HttpLinkFetcher, LinkFetcher};
+use super::metadata::{parse_metadata, PageMetadata};
+use crate::bsky_agent::BskyAgent;
+use crate::xrpc::{
+    BlobRef, BlobUpload, Embed, External, ExternalEmbed, Post, XrpcClient, XrpcError, XrpcResult,
+    MAX_IMAGE_SIZE,
+};
+use log::warn;
+use std::sync::Arc;
+
+/// Builds `app.bsky.embed.external` link cards: fetches the page, reads its OpenGraph or
+/// Twitter card tags and uploads its image as the thumbnail.
+///
+/// ```no_run
+/// # async fn example(agent: rustysky::bsky_agent::BskyAgent) -> anyhow::Result<()> {
+/// use rustysky::link_card::LinkCardBuilder;
+/// use rustysky::xrpc::Post;
+///
+/// let did = agent.session().unwrap().did;
+/// let mut post = Post::new("Read this: https://atproto.com", &did, None, None, None, None)?;
+/// LinkCardBuilder::new(agent.client()).attach(&mut post, &agent).await?;
+/// agent.create_post(post).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct LinkCardBuilder {
+    fetcher: Arc<dyn LinkFetcher>,
+    thumbnail: bool,
+}
+
+impl LinkCardBuilder {
+    /// Fetches pages through the client's transport.
+    pub fn new(client: XrpcClient) -> Self {
+        Self::with_fetcher(Arc::new(HttpLinkFetcher::new(client)))
+    }
+
+    pub fn with_fetcher(fetcher: Arc<dyn LinkFetcher>) -> Self {
+        Self {
+            fetcher,
+            thumbnail: true,
+        }
+    }
+
+    /// Whether to upload the page's image as the thumbnail. On by default.
+    pub fn with_thumbnail(mut self, thumbnail: bool) -> Self {
+        self.thumbnail = thumbnail;
+        self
+    }
+
+    /// Fetches the page at `url` and reads its metadata.
+    pub async fn preview(&self, url: &str) -> XrpcResult<PageMetadata> {
+        let page = self.fetcher.fetch(url).await?;
+        match page.mime_type().as_deref() {
+            Some("text/html" | "application/xhtml+xml") | None => {}
+            Some(mime_type) => {
+                return Err(XrpcError::Deserialization(format!(
+                    "{} is {}, not a web page",
+                    url, mime_type
+                )))
+            }
+        }
+        Ok(parse_metadata(&String::from_utf8_lossy(&page.body), url))
+    }
+
+    /// The link card for `url`. A thumbnail that can't be fetched or is too large is left out
+    /// rather than failing the card.
+    pub async fn build(&self, url: &str, agent: &BskyAgent) -> XrpcResult<Embed> {
+        let metadata = self.preview(url).await?;
+        let thumb = match &metadata.image {
+            Some(image) if self.thumbnail => match self.upload_thumbnail(image, agent).await {
+                Ok(thumb) => Some(thumb),
+                Err(err) => {
+                    warn!("Leaving out the thumbnail {} of {}: {}", image, url, err);
+                    None
+                }
+            },
+            _ => None,
+        };
+        Ok(Embed::External(ExternalEmbed {
+            external: External {
+                uri: url.to_string(),
+                title: metadata.title.unwrap_or_default(),
+                description: metadata.description.unwrap_or_default(),
+                thumb,
+            },
+        }))
+    }
+
+    /// Attaches a card for the first link of the post, unless it has no links or already has
+    /// an embed. Returns whether a card was attached.
+    pub async fn attach(&self, post: &mut Post, agent: &BskyAgent) -> XrpcResult<bool> {
+        if post.embed.is_some() {
+            return Ok(false);
+        }
+        let Some(url) = post.links().first().map(|url| url.to_string()) else {
+            return Ok(false);
+        };
+        post.embed = Some(self.build(&url, agent).await?);
+        Ok(true)
+    }
+
+    async fn upload_thumbnail(&self, url: &str, agent: &BskyAgent) -> XrpcResult<BlobRef> {
+        let image = self.fetcher.fetch(url).await?;
+        let upload = thumbnail_upload(image.body).await?;
+        agent.upload_blob(&upload).await
+    }
+}
+
+/// Checks that the thumbnail is an image that fits.
+#[cfg(not(feature = "media"))]
+async fn thumbnail_upload(bytes: Vec<u8>) -> XrpcResult<BlobUpload> {
+    let upload = BlobUpload::from_bytes(bytes).with_max_size(MAX_IMAGE_SIZE);
+    if !upload.mime_type().starts_with("image/") {
+        return Err(XrpcError::InvalidRecord(format!(
+            "the thumbnail is {}, not an image",
+            upload.mime_type()
+        )));
+    }
+    upload.check_size()?;
+    Ok(upload)
+}
+
+/// Shrinks the thumbnail to fit, without its metadata.
+#[cfg(feature = "media")]
+async fn thumbnail_upload(bytes: Vec<u8>) -> XrpcResult<BlobUpload> {
+    use crate::media::{process_image, ImageOptions};
+
+    let options = ImageOptions {
+        max_dimension: 1000,
+        max_size: MAX_IMAGE_SIZE,
+        ..ImageOptions::default()
+    };
+    let image = tokio::task::spawn_blocking(move || process_image(&bytes, &options))
+        .await
+        .map_err(|err| XrpcError::Media(err.to_string()))??;
+    Ok(image.to_upload())
+}
@@ -0,0 +1,103 @@
+use crate::xrpc::{HttpMethod, HttpRequest, XrpcClient, XrpcError, XrpcResult};
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE, USER_AGENT};
+
+const DEFAULT_USER_AGENT: &str = concat!("rustysky/", env!("CARGO_PKG_VERSION"), " (link card)");
+/// The default limit on the size of a fetched page or image, 5 MiB.
+pub const DEFAULT_MAX_FETCH_SIZE: u64 = 5 * 1024 * 1024;
+
+/// A page or image fetched for a link card.
+#[derive(Debug, Clone)]
+pub struct FetchedResource {
+    /// The `Content-Type` header, e.g. `text/html; charset=utf-8`.
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl FetchedResource {
+    /// The MIME type without parameters, lowercased.
+    pub fn mime_type(&self) -> Option<String> {
+        let content_type = self.content_type.as_deref()?;
+        let mime_type = content_type.split(';').next().unwrap_or_default().trim();
+        Some(mime_type.to_ascii_lowercase())
+    }
+}
+
+/// Fetches the pages and thumbnails of link cards. The default is `HttpLinkFetcher`; implement
+/// this to fetch through a proxy or a cache, or to serve fixtures in tests.
+#[async_trait]
+pub trait LinkFetcher: Send + Sync {
+    /// Fetches `url`. Responses other than 2xx are errors.
+    async fn fetch(&self, url: &str) -> XrpcResult<FetchedResource>;
+}
+
+/// Fetches over HTTP through the client's transport, with its timeouts and proxy. Links point
+/// anywhere, so responses larger than the size limit are refused without reading them whole.
+pub struct HttpLinkFetcher {
+    client: XrpcClient,
+    user_agent: String,
+    max_size: u64,
+}
+
+impl HttpLinkFetcher {
+    /// Uses a size limit of [`DEFAULT_MAX_FETCH_SIZE`].
+    pub fn new(client: XrpcClient) -> Self {
+        Self {
+            client,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            max_size: DEFAULT_MAX_FETCH_SIZE,
+        }
+    }
+
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Some sites only serve their OpenGraph tags to user agents they recognize.
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+}
+
+#[async_trait]
+impl LinkFetcher for HttpLinkFetcher {
+    async fn fetch(&self, url: &str) -> XrpcResult<FetchedResource> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("text/html,application/xhtml+xml,image/*;q=0.8"),
+        );
+        let user_agent = HeaderValue::from_str(&self.user_agent)
+            .map_err(|err| XrpcError::Configuration(err.to_string()))?;
+        headers.insert(USER_AGENT, user_agent);
+        let request = HttpRequest {
+            method: HttpMethod::Get,
+            url: url.to_string(),
+            headers,
+            body: None,
+            stream: None,
+        };
+        let response = self
+            .client
+            .transport()
+            .send_limited(request, self.max_size)
+            .await?;
+        if !response.is_success() {
+            return Err(XrpcError::from_response(
+                response.status,
+                &String::from_utf8_lossy(&response.body),
+            ));
+        }
+        let content_type = response
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        Ok(FetchedResource {
+            content_type,
+            body: response.body,
+        })
+    }
+}
@@ -0,0 +1,186 @@
+use regex::Regex;
+use url::Url;
+
+/// What a page says about itself for previews.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// The absolute URL of the preview image.
+    pub image: Option<String>,
+}
+
+/// Extracts the title, description and image of a page from its OpenGraph tags, falling back to
+/// its Twitter card tags and then to `<title>` and `<meta name="description">`. Relative image
+/// URLs are resolved against `page_url`.
+///
+/// ```
+/// use rustysky::link_card::parse_metadata;
+///
+/// let html = r#"<head>
+///     <title>Fallback</title>
+///     <meta property="og:title" content="Rust &amp; Bluesky">
+///     <meta name="twitter:image" content="/card.png">
+/// </head>"#;
+/// let metadata = parse_metadata(html, "https://example.com/posts/1");
+/// assert_eq!(metadata.title.as_deref(), Some("Rust & Bluesky"));
+/// assert_eq!(metadata.image.as_deref(), Some("https://example.com/card.png"));
+/// ```
+pub fn parse_metadata(html: &str, page_url: &str) -> PageMetadata {
+    let tags = meta_tags(html);
+    let find = |keys: &[&str]| {
+        keys.iter().find_map(|key| {
+            tags.iter()
+                .find(|(name, content)| name == key && !content.is_empty())
+                .map(|(_, content)| content.clone())
+        })
+    };
+
+    let title = find(&["og:title", "twitter:title"]).or_else(|| title_element(html));
+    let description = find(&["og:description", "twitter:description", "description"]);
+    let image = find(&[
+        "og:image:secure_url",
+        "og:image:url",
+        "og:image",
+        "twitter:image",
+        "twitter:image:src",
+    ])
+    .and_then(|image| resolve(page_url, &image));
+    PageMetadata {
+        title,
+        description,
+        image,
+    }
+}
+
+/// The `property` or `name` of each `<meta>` tag, lowercased, with its decoded `content`.
+fn meta_tags(html: &str) -> Vec<(String, String)> {
+    let tag_regex = Regex::new(r"(?is)<meta\s[^>]*>").unwrap();
+    let attribute_regex =
+        Regex::new(r#"(?s)([a-zA-Z:_-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap();
+
+    tag_regex
+        .find_iter(html)
+        .filter_map(|tag| {
+            let mut name = None;
+            let mut content = None;
+            for attribute in attribute_regex.captures_iter(tag.as_str()) {
+                let value = attribute
+                    .get(2)
+                    .or_else(|| attribute.get(3))
+                    .or_else(|| attribute.get(4))
+                    .map_or("", |value| value.as_str());
+                match attribute[1].to_ascii_lowercase().as_str() {
+                    "property" | "name" => name = Some(value.to_ascii_lowercase()),
+                    "content" => content = Some(decode_entities(value).trim().to_string()),
+                    _ => {}
+                }
+            }
+            Some((name?, content?))
+        })
+        .collect()
+}
+
+fn title_element(html: &str) -> Option<String> {
+    let title_regex = Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap();
+    let title = title_regex.captures(html)?.get(1)?.as_str();
+    let title = decode_entities(title).trim().to_string();
+    (!title.is_empty()).then_some(title)
+}
+
+fn resolve(page_url: &str, image: &str) -> Option<String> {
+    let url = match Url::parse(page_url) {
+        Ok(base) => base.join(image).ok()?,
+        Err(_) => Url::parse(image).ok()?,
+    };
+    matches!(url.scheme(), "http" | "https").then(|| url.to_string())
+}
+
+/// Decodes the character references that show up in titles and descriptions.
+fn decode_entities(text: &str) -> String {
+    let entity_regex = Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").unwrap();
+    entity_regex
+        .replace_all(text, |captures: &regex::Captures| {
+            let entity = &captures[1];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32),
+            };
+            decoded.map_or_else(|| captures[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefers_opengraph() {
+        let html = r#"<!doctype html><html><head>
+            <title>The &lt;title&gt;</title>
+            <meta name="description" content="Plain description">
+            <meta name="twitter:title" content="Twitter title">
+            <meta content="OG title" property="og:title" />
+            <meta property='og:description' content='It&#39;s the &#x201C;OG&#x201D; one'>
+            <meta property="og:image" content="https://cdn.example.com/og.jpg">
+            <meta name="twitter:image" content="https://cdn.example.com/twitter.jpg">
+        </head><body><meta property="og:title" content="Not this one"></body></html>"#;
+        assert_eq!(
+            parse_metadata(html, "https://example.com/"),
+            PageMetadata {
+                title: Some("OG title".to_string()),
+                description: Some("It's the \u{201c}OG\u{201d} one".to_string()),
+                image: Some("https://cdn.example.com/og.jpg".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_falls_back() {
+        let html = r#"<head>
+            <TITLE>
+                Just a &amp; title
+            </TITLE>
+            <META NAME="Description" CONTENT="Plain description">
+            <meta name="twitter:image:src" content="../img/card.png">
+            <meta property="og:title" content="">
+        </head>"#;
+        assert_eq!(
+            parse_metadata(html, "https://example.com/blog/post.html"),
+            PageMetadata {
+                title: Some("Just a & title".to_string()),
+                description: Some("Plain description".to_string()),
+                image: Some("https://example.com/img/card.png".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_nothing_to_find() {
+        assert_eq!(
+            parse_metadata("<p>hello</p>", "https://example.com/"),
+            PageMetadata::default()
+        );
+        let html = r#"<meta property="og:image" content="data:image/png;base64,AAAA">"#;
+        assert_eq!(parse_metadata(html, "https://example.com/").image, None);
+    }
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(decode_entities("a &amp;&amp; b"), "a && b");
+        assert_eq!(decode_entities("&#128640; &#x1F980;"), "🚀 🦀");
+        assert_eq!(decode_entities("&unknown; &#xZZ;"), "&unknown; &#xZZ;");
+    }
+}
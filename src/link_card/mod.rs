@@ -0,0 +1,7 @@
+mod card_builder;
+mod fetcher;
+mod metadata;
+
+pub use card_builder::LinkCardBuilder;
+pub use fetcher::{FetchedResource, HttpLinkFetcher, LinkFetcher, DEFAULT_MAX_FETCH_SIZE};
+pub use metadata::{parse_metadata, PageMetadata};
@@ -0,0 +1,290 @@
+//! Image preprocessing before upload, behind the `media` feature.
+//!
+//! Camera originals are usually too large for a post, which takes images of at most
+//! [`MAX_IMAGE_SIZE`] bytes, and carry EXIF metadata, often including where they were taken.
+//! [`process_image`] decodes JPEG, PNG and WebP images, turns them upright, downscales them and
+//! re-encodes them until they fit. The result carries no metadata at all.
+//!
+//! ```no_run
+//! # async fn example(agent: rustysky::bsky_agent::BskyAgent) -> anyhow::Result<()> {
+//! use rustysky::media::{process_image_file, ImageOptions};
+//! use rustysky::xrpc::ImagesEmbedBuilder;
+//!
+//! let image = process_image_file("IMG_0001.jpg", &ImageOptions::default()).await?;
+//! let embed = ImagesEmbedBuilder::new()
+//!     .image_with_aspect_ratio(image.to_upload(), "Sunset at the lake", image.aspect_ratio)
+//!     .upload(&agent)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::xrpc::{AspectRatio, BlobUpload, XrpcError, XrpcResult, MAX_IMAGE_SIZE};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use std::io::Cursor;
+use std::path::Path;
+
+/// How far `process_image` may go to make an image fit.
+#[derive(Debug, Clone)]
+pub struct ImageOptions {
+    /// Images are downscaled until neither side is longer than this.
+    pub max_dimension: u32,
+    /// The size the encoded image has to fit in.
+    pub max_size: u64,
+    /// The JPEG quality to start with. It is lowered step by step down to `min_quality`
+    /// before the image is downscaled further.
+    pub quality: u8,
+    pub min_quality: u8,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            max_dimension: 2000,
+            max_size: MAX_IMAGE_SIZE,
+            quality: 90,
+            min_quality: 50,
+        }
+    }
+}
+
+const QUALITY_STEP: u8 = 10;
+/// Images aren't downscaled below this, however large they still are.
+const MIN_DIMENSION: u32 = 64;
+
+/// An image that fits the size limit, ready to upload.
+#[derive(Debug, Clone)]
+pub struct ProcessedImage {
+    pub bytes: Vec<u8>,
+    /// `image/jpeg`, or `image/png` for images with transparency.
+    pub mime_type: &'static str,
+    pub width: u32,
+    pub height: u32,
+    /// The aspect ratio to put in the embed.
+    pub aspect_ratio: AspectRatio,
+    /// The JPEG quality it was encoded with; `None` for PNG.
+    pub quality: Option<u8>,
+}
+
+impl ProcessedImage {
+    pub fn to_upload(&self) -> BlobUpload {
+        BlobUpload::from_bytes(self.bytes.clone()).with_mime_type(self.mime_type)
+    }
+}
+
+/// Decodes the image, applies its EXIF orientation and re-encodes it without any metadata,
+/// downscaled and compressed until it fits `options.max_size`.
+///
+/// Opaque images become JPEGs, tried at decreasing quality before they are downscaled further.
+/// Images with transparency stay PNGs, which are only downscaled.
+pub fn process_image(bytes: &[u8], options: &ImageOptions) -> XrpcResult<ProcessedImage> {
+    let mut image = decode(bytes)?;
+    let max_dimension = options.max_dimension.max(MIN_DIMENSION);
+    if image.width() > max_dimension || image.height() > max_dimension {
+        image = image.resize(max_dimension, max_dimension, FilterType::CatmullRom);
+    }
+    // Transparent images are encoded as PNG, which has no quality to lower.
+    let qualities = if image.color().has_alpha() {
+        vec![None]
+    } else {
+        jpeg_qualities(options)
+    };
+
+    loop {
+        for &quality in &qualities {
+            let encoded = encode(&image, quality)?;
+            if encoded.len() as u64 <= options.max_size {
+                return Ok(ProcessedImage {
+                    bytes: encoded,
+                    mime_type: if quality.is_some() {
+                        "image/jpeg"
+                    } else {
+                        "image/png"
+                    },
+                    width: image.width(),
+                    height: image.height(),
+                    aspect_ratio: AspectRatio::new(image.width(), image.height()),
+                    quality,
+                });
+            }
+        }
+
+        let longest = image.width().max(image.height());
+        if longest <= MIN_DIMENSION {
+            return Err(XrpcError::Media(format!(
+                "the image doesn't fit in {} bytes even at {} pixels",
+                options.max_size, longest
+            )));
+        }
+        // Shrinking each side by a quarter about halves the size.
+        let target = (longest * 3 / 4).max(MIN_DIMENSION);
+        image = image.resize(target, target, FilterType::CatmullRom);
+    }
+}
+
+/// The JPEG qualities to try, from `options.quality` down to `options.min_quality`.
+fn jpeg_qualities(options: &ImageOptions) -> Vec<Option<u8>> {
+    let min_quality = options.min_quality.clamp(1, 100);
+    let mut quality = options.quality.clamp(min_quality, 100);
+    let mut qualities = vec![Some(quality)];
+    while quality > min_quality {
+        quality = quality.saturating_sub(QUALITY_STEP).max(min_quality);
+        qualities.push(Some(quality));
+    }
+    qualities
+}
+
+/// `process_image` for the image at `path`, off the async runtime's threads.
+pub async fn process_image_file(
+    path: impl AsRef<Path>,
+    options: &ImageOptions,
+) -> XrpcResult<ProcessedImage> {
+    let path = path.as_ref();
+    let bytes = tokio::fs::read(path)
+        .await
+        .map_err(|err| XrpcError::Media(format!("could not read {}: {}", path.display(), err)))?;
+    let options = options.clone();
+    tokio::task::spawn_blocking(move || process_image(&bytes, &options))
+        .await
+        .map_err(|err| XrpcError::Media(err.to_string()))?
+}
+
+fn decode(bytes: &[u8]) -> XrpcResult<DynamicImage> {
+    let invalid = |err: image::ImageError| XrpcError::Media(format!("invalid image: {}", err));
+    let reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|err| XrpcError::Media(err.to_string()))?;
+    let mut decoder = reader.into_decoder().map_err(invalid)?;
+    let orientation = decoder.orientation().map_err(invalid)?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(invalid)?;
+    // The orientation lives in the EXIF data that is dropped, so it is applied to the pixels.
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+fn encode(image: &DynamicImage, quality: Option<u8>) -> XrpcResult<Vec<u8>> {
+    let mut encoded = Vec::new();
+    let result = match quality {
+        Some(quality) => {
+            let encoder = JpegEncoder::new_with_quality(&mut encoded, quality);
+            image.to_rgb8().write_with_encoder(encoder)
+        }
+        None => image
+            .to_rgba8()
+            .write_with_encoder(PngEncoder::new(&mut encoded)),
+    };
+    result.map_err(|err| XrpcError::Media(format!("could not encode the image: {}", err)))?;
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+    use rand::Rng;
+
+    fn noise(width: u32, height: u32) -> RgbImage {
+        let mut rng = rand::thread_rng();
+        RgbImage::from_fn(width, height, |_, _| Rgb(rng.gen()))
+    }
+
+    fn jpeg(image: &RgbImage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, 95))
+            .unwrap();
+        bytes
+    }
+
+    /// An EXIF segment with orientation 6 (rotate 90° clockwise) and a GPS latitude.
+    fn exif_segment() -> Vec<u8> {
+        let mut tiff = b"MM\x00\x2a\x00\x00\x00\x08".to_vec();
+        // IFD0: orientation, and where the GPS IFD is.
+        tiff.extend([0, 2]);
+        tiff.extend([0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0]);
+        tiff.extend([0x88, 0x25, 0, 4, 0, 0, 0, 1, 0, 0, 0, 38]);
+        tiff.extend([0, 0, 0, 0]);
+        // The GPS IFD: latitude ref "N".
+        tiff.extend([0, 1]);
+        tiff.extend([0x00, 0x01, 0, 2, 0, 0, 0, 2, b'N', 0, 0, 0]);
+        tiff.extend([0, 0, 0, 0]);
+
+        let mut payload = b"Exif\x00\x00".to_vec();
+        payload.extend(tiff);
+        let mut segment = vec![0xff, 0xe1];
+        segment.extend(((payload.len() + 2) as u16).to_be_bytes());
+        segment.extend(payload);
+        segment
+    }
+
+    #[test]
+    fn test_strips_exif_and_applies_orientation() {
+        let original = jpeg(&noise(40, 20));
+        // The EXIF segment goes right after the SOI marker.
+        let with_exif = [&original[..2], &exif_segment(), &original[2..]].concat();
+        assert!(with_exif.windows(4).any(|bytes| bytes == b"Exif"));
+
+        let processed = process_image(&with_exif, &ImageOptions::default()).unwrap();
+        assert_eq!(processed.mime_type, "image/jpeg");
+        assert!(!processed.bytes.windows(4).any(|bytes| bytes == b"Exif"));
+        assert_eq!(processed.aspect_ratio, AspectRatio::new(20, 40));
+        assert_eq!(processed.quality, Some(90));
+    }
+
+    #[test]
+    fn test_downscales_to_max_dimension() {
+        let options = ImageOptions {
+            max_dimension: 100,
+            ..ImageOptions::default()
+        };
+        let processed = process_image(&jpeg(&noise(300, 150)), &options).unwrap();
+        assert_eq!((processed.width, processed.height), (100, 50));
+        assert_eq!(processed.aspect_ratio, AspectRatio::new(100, 50));
+    }
+
+    #[test]
+    fn test_lowers_quality_then_size_to_fit() {
+        let bytes = jpeg(&noise(400, 300));
+        let options = ImageOptions {
+            max_size: 40_000,
+            ..ImageOptions::default()
+        };
+        let processed = process_image(&bytes, &options).unwrap();
+        assert!(processed.bytes.len() <= 40_000);
+        assert!(processed.quality < Some(90));
+        assert!(processed.width < 400);
+
+        let impossible = ImageOptions {
+            max_size: 100,
+            ..ImageOptions::default()
+        };
+        assert!(matches!(
+            process_image(&bytes, &impossible),
+            Err(XrpcError::Media(_))
+        ));
+    }
+
+    #[test]
+    fn test_transparent_images_stay_png() {
+        let image = RgbaImage::from_pixel(30, 10, Rgba([255, 0, 0, 128]));
+        let mut bytes = Vec::new();
+        image
+            .write_with_encoder(PngEncoder::new(&mut bytes))
+            .unwrap();
+        let processed = process_image(&bytes, &ImageOptions::default()).unwrap();
+        assert_eq!(processed.mime_type, "image/png");
+        assert_eq!(processed.quality, None);
+        assert_eq!(processed.to_upload().mime_type(), "image/png");
+    }
+
+    #[test]
+    fn test_invalid_images() {
+        assert!(matches!(
+            process_image(b"not an image", &ImageOptions::default()),
+            Err(XrpcError::Media(_))
+        ));
+    }
+}
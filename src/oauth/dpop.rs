@@ -0,0 +1,282 @@
+use crate::xrpc::{HttpMethod, HttpRequest, HttpResponse, HttpTransport, XrpcError, XrpcResult};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use p256::ecdsa::signature::Signer;
+use p256::ecdsa::{Signature, SigningKey};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+const DPOP_NONCE: &str = "dpop-nonce";
+
+/// The ES256 key that OAuth tokens are bound to, together with the `DPoP-Nonce` each server
+/// last handed out.
+///
+/// Every request made with a DPoP-bound token carries a fresh proof signed with this key, so the
+/// key has to be kept for as long as the tokens are, see `OAuthSession`. Clones share the nonces.
+/// It serializes to the base64url encoded secret scalar.
+#[derive(Clone)]
+pub struct DpopKey {
+    signing_key: SigningKey,
+    nonces: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl DpopKey {
+    pub fn generate() -> Self {
+        Self::from_signing_key(SigningKey::random(&mut OsRng))
+    }
+
+    fn from_signing_key(signing_key: SigningKey) -> Self {
+        Self {
+            signing_key,
+            nonces: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Restores a key from `secret()`.
+    pub fn from_secret(secret: &str) -> XrpcResult<Self> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(secret)
+            .map_err(|err| XrpcError::Configuration(format!("Invalid DPoP key: {}", err)))?;
+        let signing_key = SigningKey::from_slice(&bytes)
+            .map_err(|err| XrpcError::Configuration(format!("Invalid DPoP key: {}", err)))?;
+        Ok(Self::from_signing_key(signing_key))
+    }
+
+    /// The private key, base64url encoded. Keep it as secret as the tokens bound to it.
+    pub fn secret(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.signing_key.to_bytes())
+    }
+
+    /// The public key as a JWK, as embedded in every proof.
+    pub fn public_jwk(&self) -> Value {
+        let point = self.signing_key.verifying_key().to_encoded_point(false);
+        json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": URL_SAFE_NO_PAD.encode(point.x().expect("uncompressed point")),
+            "y": URL_SAFE_NO_PAD.encode(point.y().expect("uncompressed point")),
+        })
+    }
+
+    /// Signs a proof for a `method` request to `url`. Requests made with an access token must
+    /// pass it, so that the proof is bound to the token (`ath`).
+    pub fn proof(&self, method: HttpMethod, url: &str, access_token: Option<&str>) -> String {
+        let htu = url.split(['?', '#']).next().unwrap_or(url);
+        let mut jti = [0u8; 16];
+        OsRng.fill_bytes(&mut jti);
+
+        let header = json!({
+            "typ": "dpop+jwt",
+            "alg": "ES256",
+            "jwk": self.public_jwk(),
+        });
+        let mut claims = json!({
+            "jti": URL_SAFE_NO_PAD.encode(jti),
+            "htm": match method {
+                HttpMethod::Get => "GET",
+                HttpMethod::Post => "POST",
+            },
+            "htu": htu,
+            "iat": chrono::Utc::now().timestamp(),
+        });
+        if let Some(nonce) = self.nonce(url) {
+            claims["nonce"] = Value::String(nonce);
+        }
+        if let Some(access_token) = access_token {
+            claims["ath"] = Value::String(s256(access_token));
+        }
+
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let signature: Signature = self.signing_key.sign(signing_input.as_bytes());
+        format!(
+            "{}.{}",
+            signing_input,
+            URL_SAFE_NO_PAD.encode(signature.to_bytes())
+        )
+    }
+
+    /// The nonce the server at `url` last handed out.
+    pub fn nonce(&self, url: &str) -> Option<String> {
+        self.nonces.lock().unwrap().get(&origin(url)).cloned()
+    }
+
+    fn remember_nonce(&self, url: &str, headers: &HeaderMap) {
+        if let Some(nonce) = headers
+            .get(DPOP_NONCE)
+            .and_then(|nonce| nonce.to_str().ok())
+        {
+            self.nonces
+                .lock()
+                .unwrap()
+                .insert(origin(url), nonce.to_string());
+        }
+    }
+
+    /// Sends `request` with a proof, and with `access_token` as a DPoP-bound token if given.
+    ///
+    /// Servers hand out nonces in responses and reject proofs without the current one. If that
+    /// happens, the request is sent again once with the new nonce.
+    pub(crate) async fn send(
+        &self,
+        transport: &dyn HttpTransport,
+        request: &HttpRequest,
+        access_token: Option<&str>,
+    ) -> XrpcResult<HttpResponse> {
+        let url = &request.url;
+        let mut retried = false;
+        loop {
+            let mut request = request.clone();
+            let proof = self.proof(request.method, &request.url, access_token);
+            request.headers.insert("dpop", header_value(&proof)?);
+            if let Some(access_token) = access_token {
+                request.headers.insert(
+                    AUTHORIZATION,
+                    header_value(&format!("DPoP {}", access_token))?,
+                );
+            }
+
+            let response = transport.send(request).await?;
+            self.remember_nonce(url, &response.headers);
+            if retried || !requires_nonce(&response) {
+                return Ok(response);
+            }
+            retried = true;
+        }
+    }
+}
+
+impl fmt::Debug for DpopKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DpopKey")
+            .field("jwk", &self.public_jwk())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Serialize for DpopKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.secret())
+    }
+}
+
+impl<'de> Deserialize<'de> for DpopKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let secret = String::deserialize(deserializer)?;
+        DpopKey::from_secret(&secret).map_err(serde::de::Error::custom)
+    }
+}
+
+/// base64url(SHA-256(`input`)), as used for PKCE challenges and `ath`.
+pub(crate) fn s256(input: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(input.as_bytes()))
+}
+
+fn header_value(value: &str) -> XrpcResult<HeaderValue> {
+    HeaderValue::from_str(value).map_err(|err| XrpcError::Serialization(err.to_string()))
+}
+
+/// Nonces are handed out per server, so they are kept by origin.
+fn origin(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(url) => url.origin().ascii_serialization(),
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Authorization servers ask for a nonce with a `use_dpop_nonce` error in the body, resource
+/// servers in the `WWW-Authenticate` header.
+fn requires_nonce(response: &HttpResponse) -> bool {
+    if !matches!(response.status, 400 | 401) || !response.headers.contains_key(DPOP_NONCE) {
+        return false;
+    }
+    let in_header = response
+        .headers
+        .get(WWW_AUTHENTICATE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("use_dpop_nonce"));
+    let in_body = serde_json::from_slice::<Value>(&response.body)
+        .is_ok_and(|body| body["error"] == "use_dpop_nonce");
+    in_header || in_body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Verifier;
+    use p256::ecdsa::VerifyingKey;
+    use p256::EncodedPoint;
+
+    fn decode(part: &str) -> Value {
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(part).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_proof_is_signed_with_the_embedded_key() {
+        let key = DpopKey::generate();
+        let proof = key.proof(
+            HttpMethod::Post,
+            "https://pds.example.com/xrpc/com.atproto.repo.createRecord?x=1",
+            Some("access-token"),
+        );
+        let parts: Vec<&str> = proof.split('.').collect();
+        let header = decode(parts[0]);
+        let claims = decode(parts[1]);
+        assert_eq!(header["typ"], "dpop+jwt");
+        assert_eq!(header["alg"], "ES256");
+        assert_eq!(claims["htm"], "POST");
+        assert_eq!(
+            claims["htu"],
+            "https://pds.example.com/xrpc/com.atproto.repo.createRecord"
+        );
+        assert_eq!(claims["ath"], s256("access-token"));
+        assert!(claims.get("nonce").is_none());
+
+        let jwk = &header["jwk"];
+        let x = URL_SAFE_NO_PAD.decode(jwk["x"].as_str().unwrap()).unwrap();
+        let y = URL_SAFE_NO_PAD.decode(jwk["y"].as_str().unwrap()).unwrap();
+        let point = EncodedPoint::from_affine_coordinates(x[..].into(), y[..].into(), false);
+        let verifying_key = VerifyingKey::from_encoded_point(&point).unwrap();
+        let signature = Signature::from_slice(&URL_SAFE_NO_PAD.decode(parts[2]).unwrap()).unwrap();
+        let signing_input = format!("{}.{}", parts[0], parts[1]);
+        verifying_key
+            .verify(signing_input.as_bytes(), &signature)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_nonces_are_kept_per_origin() {
+        let key = DpopKey::generate();
+        let mut headers = HeaderMap::new();
+        headers.insert(DPOP_NONCE, HeaderValue::from_static("nonce-1"));
+        key.remember_nonce("https://auth.example.com/oauth/par", &headers);
+
+        let proof = key.proof(
+            HttpMethod::Post,
+            "https://auth.example.com/oauth/token",
+            None,
+        );
+        let claims = decode(proof.split('.').nth(1).unwrap());
+        assert_eq!(claims["nonce"], "nonce-1");
+        assert_eq!(key.nonce("https://pds.example.com/xrpc"), None);
+    }
+
+    #[test]
+    fn test_key_round_trips_through_its_secret() {
+        let key = DpopKey::generate();
+        let restored: DpopKey =
+            serde_json::from_value(serde_json::to_value(&key).unwrap()).unwrap();
+        assert_eq!(restored.public_jwk(), key.public_jwk());
+        assert!(DpopKey::from_secret("not a key").is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use super::AuthorizationCallback;
+use crate::xrpc::{XrpcError, XrpcResult};
+use log::debug;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const CALLBACK_PATH: &str = "/callback";
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+/// How long a connection may take to send its request head before it is dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Receives the authorization redirect on `http://127.0.0.1:<port>/callback`, for CLI and
+/// desktop apps that have no web server of their own.
+///
+/// ```no_run
+/// # async fn example() -> rustysky::xrpc::XrpcResult<()> {
+/// use rustysky::oauth::{LoopbackListener, OAuthClientConfig, DEFAULT_SCOPE};
+///
+/// let listener = LoopbackListener::bind(0).await?;
+/// let config = OAuthClientConfig::loopback(listener.redirect_uri(), DEFAULT_SCOPE);
+/// // ... OAuthClient::authorize, open the authorization URL in a browser, then:
+/// let callback = listener.wait_for_callback().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct LoopbackListener {
+    listener: TcpListener,
+    redirect_uri: String,
+}
+
+impl LoopbackListener {
+    /// Listens on the given port of the loopback interface; 0 picks a free port.
+    pub async fn bind(port: u16) -> XrpcResult<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .await
+            .map_err(|err| XrpcError::Transport(err.to_string()))?;
+        let port = listener
+            .local_addr()
+            .map_err(|err| XrpcError::Transport(err.to_string()))?
+            .port();
+        Ok(Self {
+            listener,
+            redirect_uri: format!("http://127.0.0.1:{}{}", port, CALLBACK_PATH),
+        })
+    }
+
+    pub fn redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
+
+    /// Waits for the browser to be redirected back and answers with a page telling the user to
+    /// return to the app. Other requests, e.g. for a favicon, get a 404.
+    pub async fn wait_for_callback(&self) -> XrpcResult<AuthorizationCallback> {
+        loop {
+            let (mut stream, _) = self
+                .listener
+                .accept()
+                .await
+                .map_err(|err| XrpcError::Transport(err.to_string()))?;
+            let target = tokio::time::timeout(READ_TIMEOUT, read_request_target(&mut stream))
+                .await
+                .unwrap_or_else(|_| Err(std::io::ErrorKind::TimedOut.into()));
+            let target = match target {
+                Ok(target) => target,
+                Err(err) => {
+                    debug!("Ignoring invalid request on the loopback listener: {}", err);
+                    continue;
+                }
+            };
+
+            let query = match target.split_once('?') {
+                Some((CALLBACK_PATH, query)) => query,
+                _ if target == CALLBACK_PATH => "",
+                _ => {
+                    respond(&mut stream, "404 Not Found", "Not found.").await;
+                    continue;
+                }
+            };
+            let callback = AuthorizationCallback::from_query(query);
+            let message = match &callback {
+                Ok(_) => "You are logged in. You can close this window and return to the app.",
+                Err(_) => "The login failed. You can close this window and return to the app.",
+            };
+            respond(&mut stream, "200 OK", message).await;
+            return callback;
+        }
+    }
+}
+
+/// Reads the request head and returns the request target of a GET request.
+async fn read_request_target(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buffer.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 || buffer.len() + read > MAX_REQUEST_SIZE {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&buffer);
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(target)) => Ok(target.to_string()),
+        _ => Err(std::io::ErrorKind::InvalidData.into()),
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, message: &str) {
+    let body = format!(
+        "<!DOCTYPE html><html><head><title>rustysky</title></head><body><p>{}</p></body></html>",
+        message
+    );
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    if let Err(err) = stream.write_all(response.as_bytes()).await {
+        debug!("Failed to answer on the loopback listener: {}", err);
+    }
+    let _ = stream.shutdown().await;
+}
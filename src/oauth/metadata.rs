@@ -0,0 +1,64 @@
+use crate::xrpc::{XrpcError, XrpcResult};
+use serde::{Deserialize, Serialize};
+
+/*
+The PDS points to its authorization server in
+`/.well-known/oauth-protected-resource` (RFC 9728).
+*/
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProtectedResourceMetadata {
+    pub resource: Option<String>,
+    #[serde(default)]
+    pub authorization_servers: Vec<String>,
+}
+
+/*
+`/.well-known/oauth-authorization-server` (RFC 8414). Only the fields the atproto profile
+relies on are kept.
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorizationServerMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub pushed_authorization_request_endpoint: Option<String>,
+    pub revocation_endpoint: Option<String>,
+    #[serde(default)]
+    pub scopes_supported: Vec<String>,
+    #[serde(default)]
+    pub dpop_signing_alg_values_supported: Vec<String>,
+    #[serde(default)]
+    pub authorization_response_iss_parameter_supported: bool,
+}
+
+impl AuthorizationServerMetadata {
+    /// Checks that the metadata was served by `issuer` and that the server supports what the
+    /// atproto profile requires: pushed authorization requests and ES256 DPoP proofs.
+    pub fn validate(&self, issuer: &str) -> XrpcResult<()> {
+        if self.issuer.trim_end_matches('/') != issuer.trim_end_matches('/') {
+            return Err(invalid_metadata(format!(
+                "issuer {} does not match {}",
+                self.issuer, issuer
+            )));
+        }
+        if self.pushed_authorization_request_endpoint.is_none() {
+            return Err(invalid_metadata(
+                "no pushed_authorization_request_endpoint".to_string(),
+            ));
+        }
+        if !self
+            .dpop_signing_alg_values_supported
+            .iter()
+            .any(|alg| alg == "ES256")
+        {
+            return Err(invalid_metadata(
+                "ES256 DPoP proofs not supported".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn invalid_metadata(reason: String) -> XrpcError {
+    XrpcError::Deserialization(format!("Invalid authorization server metadata: {}", reason))
+}
@@ -0,0 +1,13 @@
+mod dpop;
+mod loopback;
+mod metadata;
+mod oauth_client;
+mod oauth_session;
+
+pub use dpop::DpopKey;
+pub use loopback::LoopbackListener;
+pub use metadata::{AuthorizationServerMetadata, ProtectedResourceMetadata};
+pub use oauth_client::{
+    AuthorizationCallback, AuthorizationRequest, OAuthClient, OAuthClientConfig, DEFAULT_SCOPE,
+};
+pub use oauth_session::OAuthSession;
@@ -0,0 +1,466 @@
+use super::dpop::{s256, DpopKey};
+use super::metadata::{AuthorizationServerMetadata, ProtectedResourceMetadata};
+use super::oauth_session::{OAuthSession, TokenResponse};
+use crate::identity::IdentityResolver;
+use crate::syntax::Did;
+use crate::xrpc::{
+    decode_response, AuthErrorKind, HttpMethod, HttpRequest, XrpcClient, XrpcError,
+    XrpcErrorResponse, XrpcResult,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use log::info;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use serde::de::DeserializeOwned;
+use url::Url;
+
+/// The scope for full account access, like a password session.
+pub const DEFAULT_SCOPE: &str = "atproto transition:generic";
+
+/// How the app identifies itself to authorization servers.
+#[derive(Debug, Clone)]
+pub struct OAuthClientConfig {
+    /// The URL of the app's client metadata document.
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: String,
+}
+
+impl OAuthClientConfig {
+    /// A client for apps without a published client metadata document, like CLIs and desktop
+    /// apps. Authorization servers accept `http://localhost` client IDs that carry the redirect
+    /// URI and scope in the query, as long as the redirect goes to a loopback address.
+    pub fn loopback(redirect_uri: &str, scope: &str) -> Self {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("scope", scope)
+            .finish();
+        Self {
+            client_id: format!("http://localhost?{}", query),
+            redirect_uri: redirect_uri.to_string(),
+            scope: scope.to_string(),
+        }
+    }
+}
+
+/// A started authorization: send the user to `authorization_url`, then pass what the redirect
+/// brings back to `OAuthClient::callback`.
+#[derive(Debug, Clone)]
+pub struct AuthorizationRequest {
+    pub authorization_url: String,
+    pub state: String,
+    pub pds: String,
+    pub metadata: AuthorizationServerMetadata,
+    /// The account the login was started for, if it was started with a handle or DID. The
+    /// token has to be for this account.
+    pub expected_did: Option<Did>,
+    code_verifier: String,
+    dpop_key: DpopKey,
+}
+
+/// The query parameters of the authorization redirect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizationCallback {
+    pub code: String,
+    pub state: String,
+    pub iss: Option<String>,
+}
+
+impl AuthorizationCallback {
+    /// Parses the redirect's query string. A redirect with an `error`, e.g. because the user
+    /// denied access, is returned as an `XrpcError::Auth`.
+    pub fn from_query(query: &str) -> XrpcResult<Self> {
+        let mut code = None;
+        let mut state = None;
+        let mut iss = None;
+        let mut error = None;
+        let mut error_description = None;
+        for (name, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            let value = Some(value.into_owned());
+            match name.as_ref() {
+                "code" => code = value,
+                "state" => state = value,
+                "iss" => iss = value,
+                "error" => error = value,
+                "error_description" => error_description = value,
+                _ => {}
+            }
+        }
+
+        match (error, code, state) {
+            (None, Some(code), Some(state)) => Ok(Self { code, state, iss }),
+            (error, _, _) => Err(authorization_failed(
+                error.or(Some("invalid_request".to_string())),
+                error_description.or(Some("Incomplete authorization callback".to_string())),
+            )),
+        }
+    }
+}
+
+/// Logs in with the atproto OAuth profile: pushed authorization requests, PKCE and
+/// DPoP-bound tokens.
+///
+/// 1. `authorize` finds the PDS's authorization server and returns the URL to send the user to.
+/// 2. `callback` exchanges the code the user comes back with for an `OAuthSession`.
+/// 3. `OAuthSession::client` makes XRPC calls with the session's tokens, and `refresh` renews
+///    them.
+///
+/// The authorization server says which account a token is for, so `callback` doesn't take its
+/// word for it: the account's DID document has to name a PDS that delegates to that same
+/// authorization server, and the account has to be the one the login was started for.
+#[derive(Clone)]
+pub struct OAuthClient {
+    client: XrpcClient,
+    config: OAuthClientConfig,
+    identity: IdentityResolver,
+}
+
+impl OAuthClient {
+    pub fn new(client: XrpcClient, config: OAuthClientConfig) -> Self {
+        Self {
+            identity: IdentityResolver::new(client.clone()),
+            client,
+            config,
+        }
+    }
+
+    /// Resolves accounts with `identity`, e.g. with another PLC directory or DNS backend.
+    pub fn with_identity_resolver(mut self, identity: IdentityResolver) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    pub fn config(&self) -> &OAuthClientConfig {
+        &self.config
+    }
+
+    /// Finds the authorization server the PDS at `pds_url` delegates to and fetches its
+    /// metadata.
+    pub async fn discover(&self, pds_url: &str) -> XrpcResult<AuthorizationServerMetadata> {
+        let issuer = self.authorization_server(pds_url).await?;
+        let issuer = issuer.trim_end_matches('/');
+        let metadata: AuthorizationServerMetadata = self
+            .get_json(&format!(
+                "{}/.well-known/oauth-authorization-server",
+                issuer
+            ))
+            .await?;
+        metadata.validate(issuer)?;
+        Ok(metadata)
+    }
+
+    /// The issuer of the authorization server the PDS at `pds_url` delegates to.
+    async fn authorization_server(&self, pds_url: &str) -> XrpcResult<String> {
+        let pds = pds_url.trim_end_matches('/');
+        let resource: ProtectedResourceMetadata = self
+            .get_json(&format!("{}/.well-known/oauth-protected-resource", pds))
+            .await?;
+        resource
+            .authorization_servers
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                XrpcError::Deserialization(format!("{} names no authorization server", pds))
+            })
+    }
+
+    /// Starts logging in to an account on the PDS at `pds_url`. `login_hint` (a handle or DID)
+    /// pre-fills the account on the authorization page, and is resolved so that `callback`
+    /// only accepts a token for that account.
+    pub async fn authorize(
+        &self,
+        pds_url: &str,
+        login_hint: Option<&str>,
+    ) -> XrpcResult<AuthorizationRequest> {
+        let expected_did = match login_hint {
+            Some(login_hint) => Some(self.identity.resolve(login_hint).await?.did),
+            None => None,
+        };
+        let metadata = self.discover(pds_url).await?;
+        let par_endpoint = metadata
+            .pushed_authorization_request_endpoint
+            .clone()
+            .expect("validated metadata has a PAR endpoint");
+
+        let code_verifier = random_token();
+        let state = random_token();
+        let dpop_key = DpopKey::generate();
+        let code_challenge = s256(&code_verifier);
+        let mut params = vec![
+            ("client_id", self.config.client_id.as_str()),
+            ("response_type", "code"),
+            ("redirect_uri", self.config.redirect_uri.as_str()),
+            ("scope", self.config.scope.as_str()),
+            ("state", state.as_str()),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ];
+        if let Some(login_hint) = login_hint {
+            params.push(("login_hint", login_hint));
+        }
+
+        #[derive(serde::Deserialize)]
+        struct ParResponse {
+            request_uri: String,
+        }
+        let par: ParResponse = self.post_form(&par_endpoint, &params, &dpop_key).await?;
+
+        let mut authorization_url = Url::parse(&metadata.authorization_endpoint)
+            .map_err(|err| XrpcError::Deserialization(err.to_string()))?;
+        authorization_url
+            .query_pairs_mut()
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("request_uri", &par.request_uri);
+
+        Ok(AuthorizationRequest {
+            authorization_url: authorization_url.to_string(),
+            state,
+            pds: pds_url.trim_end_matches('/').to_string(),
+            metadata,
+            expected_did,
+            code_verifier,
+            dpop_key,
+        })
+    }
+
+    /// Finishes logging in with the redirect the user came back with. The token is rejected
+    /// unless its account is the expected one, if any, and lives on a PDS that delegates to
+    /// this authorization server. The session is for that PDS.
+    pub async fn callback(
+        &self,
+        request: &AuthorizationRequest,
+        callback: &AuthorizationCallback,
+    ) -> XrpcResult<OAuthSession> {
+        if callback.state != request.state {
+            return Err(authorization_failed(
+                Some("invalid_state".to_string()),
+                Some("The callback does not belong to this authorization request".to_string()),
+            ));
+        }
+        let issuer_matches = match &callback.iss {
+            Some(iss) => iss == &request.metadata.issuer,
+            None => {
+                !request
+                    .metadata
+                    .authorization_response_iss_parameter_supported
+            }
+        };
+        if !issuer_matches {
+            return Err(authorization_failed(
+                Some("invalid_issuer".to_string()),
+                Some("The callback does not come from the authorization server".to_string()),
+            ));
+        }
+
+        let token_endpoint = &request.metadata.token_endpoint;
+        let params = [
+            ("client_id", self.config.client_id.as_str()),
+            ("grant_type", "authorization_code"),
+            ("code", callback.code.as_str()),
+            ("redirect_uri", self.config.redirect_uri.as_str()),
+            ("code_verifier", request.code_verifier.as_str()),
+        ];
+        let token: TokenResponse = self
+            .post_form(token_endpoint, &params, &request.dpop_key)
+            .await?;
+        let pds = self.verify_subject(&token.sub, request).await?;
+        self.session_from_token(
+            token,
+            &pds,
+            &request.metadata.issuer,
+            token_endpoint,
+            &request.dpop_key,
+        )
+    }
+
+    /// Trades the refresh token for new tokens. Refresh tokens are single use, so the old
+    /// session must be replaced by the returned one.
+    pub async fn refresh(&self, session: &OAuthSession) -> XrpcResult<OAuthSession> {
+        let refresh_token = session
+            .refresh_token
+            .as_deref()
+            .ok_or(XrpcError::NoSession)?;
+        let params = [
+            ("client_id", self.config.client_id.as_str()),
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ];
+        let token: TokenResponse = self
+            .post_form(&session.token_endpoint, &params, &session.dpop_key)
+            .await?;
+        if token.sub != session.did {
+            return Err(XrpcError::Deserialization(format!(
+                "Refreshed token is for {} instead of {}",
+                token.sub, session.did
+            )));
+        }
+        info!("OAuth session for {} refreshed.", session.did);
+        self.session_from_token(
+            token,
+            &session.pds,
+            &session.issuer,
+            &session.token_endpoint,
+            &session.dpop_key,
+        )
+    }
+
+    /// Checks that the token's account is the one the login was for and that the authorization
+    /// server speaks for it. Returns the account's PDS.
+    async fn verify_subject(
+        &self,
+        sub: &Did,
+        request: &AuthorizationRequest,
+    ) -> XrpcResult<String> {
+        if let Some(expected_did) = &request.expected_did {
+            if sub != expected_did {
+                return Err(XrpcError::Identity(format!(
+                    "The token is for {} instead of {}",
+                    sub, expected_did
+                )));
+            }
+        }
+        let document = self.identity.dids().resolve(sub).await?;
+        let pds = document
+            .pds_endpoint()
+            .ok_or_else(|| XrpcError::Identity(format!("{} has no PDS", sub)))?
+            .trim_end_matches('/')
+            .to_string();
+        let issuer = self.authorization_server(&pds).await?;
+        if issuer.trim_end_matches('/') != request.metadata.issuer.trim_end_matches('/') {
+            return Err(XrpcError::Identity(format!(
+                "{} is on {}, which delegates to {}, not {}",
+                sub, pds, issuer, request.metadata.issuer
+            )));
+        }
+        Ok(pds)
+    }
+
+    fn session_from_token(
+        &self,
+        token: TokenResponse,
+        pds: &str,
+        issuer: &str,
+        token_endpoint: &str,
+        dpop_key: &DpopKey,
+    ) -> XrpcResult<OAuthSession> {
+        if !token.token_type.eq_ignore_ascii_case("DPoP") {
+            return Err(XrpcError::Deserialization(format!(
+                "Expected a DPoP token, got {}",
+                token.token_type
+            )));
+        }
+        if !token.scope.split(' ').any(|scope| scope == "atproto") {
+            return Err(XrpcError::Deserialization(format!(
+                "Token scope {:?} lacks atproto",
+                token.scope
+            )));
+        }
+
+        Ok(OAuthSession {
+            did: token.sub,
+            pds: pds.to_string(),
+            issuer: issuer.to_string(),
+            token_endpoint: token_endpoint.to_string(),
+            access_token: token.access_token,
+            refresh_token: token.refresh_token,
+            expires_at: token
+                .expires_in
+                .map(|expires_in| Utc::now() + Duration::seconds(expires_in)),
+            scope: token.scope,
+            dpop_key: dpop_key.clone(),
+        })
+    }
+
+    async fn get_json<R: DeserializeOwned>(&self, url: &str) -> XrpcResult<R> {
+        let request = HttpRequest {
+            method: HttpMethod::Get,
+            url: url.to_string(),
+            headers: HeaderMap::new(),
+            body: None,
+            stream: None,
+        };
+        let response = self.client.transport().send(request).await?;
+        decode_response(response, self.client.config())
+    }
+
+    async fn post_form<R: DeserializeOwned>(
+        &self,
+        url: &str,
+        params: &[(&str, &str)],
+        dpop_key: &DpopKey,
+    ) -> XrpcResult<R> {
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(params)
+            .finish();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        let request = HttpRequest {
+            method: HttpMethod::Post,
+            url: url.to_string(),
+            headers,
+            body: Some(body.into_bytes()),
+            stream: None,
+        };
+        let response = dpop_key
+            .send(self.client.transport(), &request, None)
+            .await?;
+        decode_response(response, self.client.config())
+    }
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The authorization failed in the browser or the callback can't be trusted. There is no HTTP
+/// response to go with it, so it is reported like an HTTP 401.
+fn authorization_failed(error: Option<String>, message: Option<String>) -> XrpcError {
+    XrpcError::Auth {
+        kind: AuthErrorKind::Other,
+        response: XrpcErrorResponse {
+            status: 401,
+            error,
+            message,
+            rate_limit: None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loopback_client_id_carries_redirect_uri_and_scope() {
+        let config = OAuthClientConfig::loopback("http://127.0.0.1:8080/callback", DEFAULT_SCOPE);
+        assert_eq!(
+            config.client_id,
+            "http://localhost?redirect_uri=http%3A%2F%2F127.0.0.1%3A8080%2Fcallback&scope=atproto+transition%3Ageneric"
+        );
+    }
+
+    #[test]
+    fn test_parses_the_authorization_callback() {
+        let callback =
+            AuthorizationCallback::from_query("code=abc&state=xyz&iss=https%3A%2F%2Fbsky.social")
+                .unwrap();
+        assert_eq!(callback.code, "abc");
+        assert_eq!(callback.state, "xyz");
+        assert_eq!(callback.iss.as_deref(), Some("https://bsky.social"));
+
+        let denied = AuthorizationCallback::from_query(
+            "error=access_denied&error_description=Access+denied&state=xyz",
+        )
+        .unwrap_err();
+        assert_eq!(denied.error_name(), Some("access_denied"));
+        assert!(AuthorizationCallback::from_query("state=xyz").is_err());
+    }
+}
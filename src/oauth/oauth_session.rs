@@ -0,0 +1,59 @@
+use super::DpopKey;
+use crate::syntax::Did;
+use crate::xrpc::XrpcClient;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/*
+export interface TokenResponse {
+  access_token: string
+  token_type: 'DPoP'
+  expires_in?: number
+  refresh_token?: string
+  scope: string
+  sub: string
+}
+*/
+#[derive(Debug, Deserialize)]
+pub(crate) struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: Option<i64>,
+    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub scope: String,
+    pub sub: Did,
+}
+
+/// An OAuth session: DPoP-bound tokens for the account `did` on its PDS.
+///
+/// The tokens only work together with `dpop_key`, so the session is `Serialize` as a whole,
+/// key included, and has to be stored as carefully as a password.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuthSession {
+    pub did: Did,
+    /// The PDS the tokens are for.
+    pub pds: String,
+    pub issuer: String,
+    pub token_endpoint: String,
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub scope: String,
+    pub dpop_key: DpopKey,
+}
+
+impl OAuthSession {
+    /// A client for the session's PDS that sends the access token with DPoP proofs. Pass
+    /// `access_token` to the `xrpc` calls as the access token.
+    pub fn client(&self, client: &XrpcClient) -> XrpcClient {
+        client.with_host(&self.pds).with_dpop(self.dpop_key.clone())
+    }
+
+    /// Whether the access token expires within the next 5 minutes.
+    pub fn needs_refresh(&self) -> bool {
+        const BUFFER_TIME: i64 = 300; // 5 minutes in seconds
+        self.expires_at
+            .is_some_and(|expires_at| expires_at - Utc::now() <= Duration::seconds(BUFFER_TIME))
+    }
+}
@@ -0,0 +1,240 @@
+use crate::xrpc::CreateSessionResponse;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+
+/// What happened to an agent's session. Stores and listeners are told about every change, so
+/// that persisted sessions always hold the latest tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// A new session was created by logging in.
+    Created,
+    /// A stored session was validated with the server and taken into use.
+    Resumed,
+    /// The tokens were rotated by `refreshSession`.
+    Refreshed,
+    /// The session can no longer be refreshed and was dropped.
+    Expired,
+    /// The session was revoked with `deleteSession` and dropped.
+    LoggedOut,
+}
+
+/// Persists a session between runs.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn load(&self) -> Result<Option<CreateSessionResponse>>;
+    async fn save(&self, session: &CreateSessionResponse) -> Result<()>;
+    async fn clear(&self) -> Result<()>;
+}
+
+/// Keeps the session in memory, e.g. to share it between agents or in tests.
+#[derive(Default)]
+pub struct MemorySessionStore {
+    session: Mutex<Option<CreateSessionResponse>>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn load(&self) -> Result<Option<CreateSessionResponse>> {
+        Ok(self.session.lock().unwrap().clone())
+    }
+
+    async fn save(&self, session: &CreateSessionResponse) -> Result<()> {
+        *self.session.lock().unwrap() = Some(session.clone());
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        *self.session.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+/// Keeps the session in a JSON file.
+///
+/// The file holds live credentials, so it is written atomically and, on Unix, readable only by
+/// its owner.
+pub struct FileSessionStore {
+    path: PathBuf,
+}
+
+impl FileSessionStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it into place, so readers
+/// never see a half-written file. On Unix the temporary file is created readable only by its
+/// owner, before anything is written to it.
+pub(crate) async fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Can't create directory {}", parent.display()))?;
+    }
+
+    let temp_path = temp_path(path);
+    if let Err(err) = write_new_file(&temp_path, contents).await {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        return Err(err).with_context(|| format!("Can't write {}", temp_path.display()));
+    }
+    if let Err(err) = tokio::fs::rename(&temp_path, path).await {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        return Err(err).with_context(|| format!("Can't write {}", path.display()));
+    }
+    Ok(())
+}
+
+/// A temporary path next to `path` that no other save, in this process or another, uses.
+fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        name,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+async fn write_new_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await
+}
+
+#[async_trait]
+impl SessionStore for FileSessionStore {
+    async fn load(&self) -> Result<Option<CreateSessionResponse>> {
+        let contents = match tokio::fs::read(&self.path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Can't read {}", self.path.display()))
+            }
+        };
+        let session = serde_json::from_slice(&contents)
+            .with_context(|| format!("Invalid session file {}", self.path.display()))?;
+        Ok(Some(session))
+    }
+
+    async fn save(&self, session: &CreateSessionResponse) -> Result<()> {
+        let contents = serde_json::to_vec_pretty(session)?;
+        write_private_file(&self.path, &contents).await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        match tokio::fs::remove_file(&self.path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("Can't remove {}", self.path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_session() -> CreateSessionResponse {
+        CreateSessionResponse {
+            did: "did:plc:testuser".parse().unwrap(),
+            handle: "test.bsky.social".parse().unwrap(),
+            email: "test@example.com".to_string(),
+            email_confirmed: true,
+            access_jwt: "access".to_string(),
+            refresh_jwt: "refresh".to_string(),
+            did_doc: None,
+            active: None,
+            status: None,
+            email_auth_factor: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_store_round_trip() {
+        let dir = std::env::temp_dir().join(format!("rustysky-store-{}", std::process::id()));
+        let store = FileSessionStore::new(dir.join("nested").join("session.json"));
+
+        assert!(store.load().await.unwrap().is_none());
+        store.save(&test_session()).await.unwrap();
+        let loaded = store.load().await.unwrap().unwrap();
+        assert_eq!(loaded.did, "did:plc:testuser");
+        assert_eq!(loaded.refresh_jwt, "refresh");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(store.path())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        store.clear().await.unwrap();
+        assert!(store.load().await.unwrap().is_none());
+        store.clear().await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_saves_do_not_clash() {
+        let dir = std::env::temp_dir().join(format!("rustysky-saves-{}", std::process::id()));
+        let path = dir.join("session.json");
+        let saves = (0..8).map(|i| {
+            let path = path.clone();
+            tokio::spawn(async move {
+                let mut session = test_session();
+                session.refresh_jwt = format!("refresh-{}", i);
+                FileSessionStore::new(path).save(&session).await
+            })
+        });
+        for save in futures::future::join_all(saves).await {
+            save.unwrap().unwrap();
+        }
+
+        let loaded = FileSessionStore::new(&path).load().await.unwrap().unwrap();
+        assert!(loaded.refresh_jwt.starts_with("refresh-"));
+        // Only the session file is left, no temporary files.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_round_trip() {
+        let store = MemorySessionStore::new();
+        store.save(&test_session()).await.unwrap();
+        assert_eq!(
+            store.load().await.unwrap().unwrap().handle,
+            "test.bsky.social"
+        );
+        store.clear().await.unwrap();
+        assert!(store.load().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,108 @@
+use super::{Did, Handle, SyntaxError};
+use std::fmt;
+use std::str::FromStr;
+
+/// A DID or a handle, e.g. the authority of an AT URI or the `actor` of a query.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AtIdentifier {
+    Did(Did),
+    Handle(Handle),
+}
+
+impl AtIdentifier {
+    pub fn new(identifier: &str) -> Result<Self, SyntaxError> {
+        if identifier.starts_with("did:") {
+            Did::new(identifier).map(AtIdentifier::Did)
+        } else {
+            Handle::new(identifier).map(AtIdentifier::Handle)
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            AtIdentifier::Did(did) => did.as_str(),
+            AtIdentifier::Handle(handle) => handle.as_str(),
+        }
+    }
+
+    pub fn did(&self) -> Option<&Did> {
+        match self {
+            AtIdentifier::Did(did) => Some(did),
+            AtIdentifier::Handle(_) => None,
+        }
+    }
+
+    pub fn handle(&self) -> Option<&Handle> {
+        match self {
+            AtIdentifier::Did(_) => None,
+            AtIdentifier::Handle(handle) => Some(handle),
+        }
+    }
+}
+
+impl From<Did> for AtIdentifier {
+    fn from(did: Did) -> Self {
+        AtIdentifier::Did(did)
+    }
+}
+
+impl From<Handle> for AtIdentifier {
+    fn from(handle: Handle) -> Self {
+        AtIdentifier::Handle(handle)
+    }
+}
+
+impl fmt::Display for AtIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AtIdentifier {
+    type Err = SyntaxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<&str> for AtIdentifier {
+    type Error = SyntaxError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Self::new(s)
+    }
+}
+
+impl std::ops::Deref for AtIdentifier {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for AtIdentifier {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq<&str> for AtIdentifier {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl serde::Serialize for AtIdentifier {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for AtIdentifier {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Self::new(&value).map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,415 @@
+use super::{string_type, AtIdentifier, Did, Handle, Nsid, RecordKey, SyntaxError};
+use url::Url;
+
+const KIND: &str = "AT URI";
+const MAX_LENGTH: usize = 8 * 1024;
+const BSKY_APP: &str = "https://bsky.app";
+
+/// The collections that have a page of their own on bsky.app, and the path segment of it.
+const WEB_PAGES: &[(&str, &str)] = &[
+    ("app.bsky.feed.post", "post"),
+    ("app.bsky.feed.generator", "feed"),
+    ("app.bsky.graph.list", "lists"),
+];
+const STARTER_PACK: &str = "app.bsky.graph.starterpack";
+const PROFILE: &str = "app.bsky.actor.profile";
+
+/// An `at://` URI of a repo, a collection or a record, e.g.
+/// `at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3k4duaz5vfs2b`, optionally with
+/// a `?query` and a `#/fragment`. The authority is a DID or a handle; handles are normalized
+/// to lowercase.
+///
+/// ```
+/// use rustysky::syntax::AtUri;
+///
+/// let uri: AtUri = "at://bsky.app/app.bsky.feed.post/3k4duaz5vfs2b".parse().unwrap();
+/// assert_eq!(uri.collection().unwrap(), "app.bsky.feed.post");
+/// assert_eq!(uri.rkey().unwrap(), "3k4duaz5vfs2b");
+/// assert_eq!(
+///     uri.to_web_url().unwrap(),
+///     "https://bsky.app/profile/bsky.app/post/3k4duaz5vfs2b"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AtUri(String);
+
+string_type!(AtUri);
+
+struct Parts<'a> {
+    authority: &'a str,
+    collection: Option<&'a str>,
+    rkey: Option<&'a str>,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+impl AtUri {
+    pub fn new(uri: &str) -> Result<Self, SyntaxError> {
+        let parts = split(uri)?;
+        let authority = AtIdentifier::new(parts.authority).map_err(|err| invalid_part(uri, err))?;
+        if let Some(collection) = parts.collection {
+            Nsid::new(collection).map_err(|err| invalid_part(uri, err))?;
+        }
+        if let Some(rkey) = parts.rkey {
+            RecordKey::new(rkey).map_err(|err| invalid_part(uri, err))?;
+        }
+        if let Some(query) = parts.query {
+            check_suffix(uri, query, "query")?;
+        }
+        if let Some(fragment) = parts.fragment {
+            if !fragment.starts_with('/') {
+                return Err(SyntaxError::new(
+                    KIND,
+                    uri,
+                    "the fragment must start with /",
+                ));
+            }
+            check_suffix(uri, fragment, "fragment")?;
+        }
+        Ok(Self(assemble(&Parts {
+            authority: authority.as_str(),
+            ..parts
+        })))
+    }
+
+    /// The URI of a whole repo.
+    pub fn for_repo(authority: impl Into<AtIdentifier>) -> Self {
+        Self(format!("at://{}", authority.into()))
+    }
+
+    /// The URI of a collection in a repo.
+    pub fn for_collection(authority: impl Into<AtIdentifier>, collection: &Nsid) -> Self {
+        Self(format!("at://{}/{}", authority.into(), collection))
+    }
+
+    /// The URI of a record.
+    pub fn for_record(
+        authority: impl Into<AtIdentifier>,
+        collection: &Nsid,
+        rkey: &RecordKey,
+    ) -> Self {
+        Self(format!("at://{}/{}/{}", authority.into(), collection, rkey))
+    }
+
+    /// This URI with `query` (without the `?`) instead of its current query.
+    pub fn with_query(&self, query: &str) -> Result<Self, SyntaxError> {
+        let parts = self.parts();
+        Self::new(&assemble(&Parts {
+            query: Some(query),
+            ..parts
+        }))
+    }
+
+    /// This URI with `fragment` (without the `#`) instead of its current fragment.
+    pub fn with_fragment(&self, fragment: &str) -> Result<Self, SyntaxError> {
+        let parts = self.parts();
+        Self::new(&assemble(&Parts {
+            fragment: Some(fragment),
+            ..parts
+        }))
+    }
+
+    fn parts(&self) -> Parts<'_> {
+        split(&self.0).expect("validated when constructed")
+    }
+
+    /// The repo, as a DID or a handle.
+    pub fn authority(&self) -> AtIdentifier {
+        AtIdentifier::new(self.parts().authority).expect("validated when constructed")
+    }
+
+    /// The DID of the repo, unless the URI names it by handle.
+    pub fn did(&self) -> Option<Did> {
+        self.authority().did().cloned()
+    }
+
+    pub fn handle(&self) -> Option<Handle> {
+        self.authority().handle().cloned()
+    }
+
+    pub fn collection(&self) -> Option<Nsid> {
+        let collection = self.parts().collection?;
+        Some(Nsid::new(collection).expect("validated when constructed"))
+    }
+
+    pub fn rkey(&self) -> Option<RecordKey> {
+        let rkey = self.parts().rkey?;
+        Some(RecordKey::new(rkey).expect("validated when constructed"))
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.parts().query
+    }
+
+    pub fn fragment(&self) -> Option<&str> {
+        self.parts().fragment
+    }
+
+    /// The page of the repo or record on bsky.app, if it has one: profiles, posts, feeds,
+    /// lists and starter packs.
+    pub fn to_web_url(&self) -> Option<String> {
+        let parts = self.parts();
+        match (parts.collection, parts.rkey) {
+            (None, None) | (Some(PROFILE), Some("self")) => {
+                Some(format!("{}/profile/{}", BSKY_APP, parts.authority))
+            }
+            (Some(STARTER_PACK), Some(rkey)) => Some(format!(
+                "{}/starter-pack/{}/{}",
+                BSKY_APP, parts.authority, rkey
+            )),
+            (Some(collection), Some(rkey)) => {
+                let (_, page) = WEB_PAGES.iter().find(|(nsid, _)| *nsid == collection)?;
+                Some(format!(
+                    "{}/profile/{}/{}/{}",
+                    BSKY_APP, parts.authority, page, rkey
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses the URL of a profile, post, feed, list or starter pack on bsky.app, e.g.
+    /// `https://bsky.app/profile/bsky.app/post/3k4duaz5vfs2b`.
+    pub fn from_web_url(url: &str) -> Result<Self, SyntaxError> {
+        const KIND: &str = "bsky.app URL";
+        let parsed = Url::parse(url).map_err(|err| SyntaxError::new(KIND, url, err.to_string()))?;
+        if parsed.host_str() != Some("bsky.app") {
+            return Err(SyntaxError::new(KIND, url, "must be on bsky.app"));
+        }
+        let segments: Vec<&str> = parsed
+            .path_segments()
+            .map(|segments| segments.filter(|segment| !segment.is_empty()).collect())
+            .unwrap_or_default();
+        let uri = match segments.as_slice() {
+            ["profile", authority] => format!("at://{}", authority),
+            ["profile", authority, page, rkey] => {
+                let Some((collection, _)) = WEB_PAGES.iter().find(|(_, name)| name == page) else {
+                    return Err(SyntaxError::new(KIND, url, "not a post, feed or list"));
+                };
+                format!("at://{}/{}/{}", authority, collection, rkey)
+            }
+            ["starter-pack", authority, rkey] => {
+                format!("at://{}/{}/{}", authority, STARTER_PACK, rkey)
+            }
+            _ => {
+                return Err(SyntaxError::new(
+                    KIND,
+                    url,
+                    "not a profile, post, feed, list or starter pack",
+                ))
+            }
+        };
+        Self::new(&uri).map_err(|err| SyntaxError::new(KIND, url, err.reason()))
+    }
+}
+
+fn split(uri: &str) -> Result<Parts<'_>, SyntaxError> {
+    if uri.len() > MAX_LENGTH {
+        return Err(SyntaxError::new(
+            KIND,
+            uri,
+            format!("can be at most {} bytes long", MAX_LENGTH),
+        ));
+    }
+    let Some(rest) = uri.strip_prefix("at://") else {
+        return Err(SyntaxError::new(KIND, uri, "must start with at://"));
+    };
+    let (rest, fragment) = match rest.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (rest, None),
+    };
+    let (path, query) = match rest.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (rest, None),
+    };
+    let mut segments = path.split('/');
+    let authority = segments.next().unwrap_or_default();
+    let collection = segments.next();
+    let rkey = segments.next();
+    if segments.next().is_some() {
+        return Err(SyntaxError::new(
+            KIND,
+            uri,
+            "can have at most an authority, a collection and a record key",
+        ));
+    }
+    Ok(Parts {
+        authority,
+        collection,
+        rkey,
+        query,
+        fragment,
+    })
+}
+
+fn assemble(parts: &Parts) -> String {
+    let mut uri = format!("at://{}", parts.authority);
+    for segment in [parts.collection, parts.rkey].into_iter().flatten() {
+        uri.push('/');
+        uri.push_str(segment);
+    }
+    if let Some(query) = parts.query {
+        uri.push('?');
+        uri.push_str(query);
+    }
+    if let Some(fragment) = parts.fragment {
+        uri.push('#');
+        uri.push_str(fragment);
+    }
+    uri
+}
+
+/// Queries and fragments may contain anything that is allowed in a URL.
+fn check_suffix(uri: &str, suffix: &str, what: &str) -> Result<(), SyntaxError> {
+    if suffix.chars().all(|c| c.is_ascii_graphic() && c != '#') {
+        Ok(())
+    } else {
+        Err(SyntaxError::new(
+            KIND,
+            uri,
+            format!("the {} may only contain printable ASCII", what),
+        ))
+    }
+}
+
+fn invalid_part(uri: &str, err: SyntaxError) -> SyntaxError {
+    SyntaxError::new(KIND, uri, format!("{} {}", err.kind(), err.reason()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_uris() {
+        for uri in [
+            "at://did:plc:z72i7hdynmk6r22z27h6tvur",
+            "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post",
+            "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3k4duaz5vfs2b",
+            "at://bsky.app/app.bsky.actor.profile/self",
+            "at://bsky.app/app.bsky.feed.post/3k4duaz5vfs2b?cid=bafy#/text",
+            "at://bsky.app#/profile",
+        ] {
+            assert!(AtUri::new(uri).is_ok(), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn test_invalid_uris() {
+        for uri in [
+            "did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post",
+            "https://bsky.app/profile/bsky.app",
+            "at://",
+            "at://bsky/app.bsky.feed.post",
+            "at://bsky.app/app.bsky.feed.post/",
+            "at://bsky.app/app.bsky/3k4duaz5vfs2b",
+            "at://bsky.app/app.bsky.feed.post/3k4duaz5vfs2b/extra",
+            "at://bsky.app/app.bsky.feed.post/a b",
+            "at://bsky.app#fragment",
+            "at://bsky.app?a b",
+        ] {
+            assert!(AtUri::new(uri).is_err(), "{}", uri);
+        }
+        let err = AtUri::new("at://bsky.app/app.bsky/x").unwrap_err();
+        assert_eq!(err.reason(), "NSID must have at least 3 segments");
+    }
+
+    #[test]
+    fn test_components() {
+        let uri = AtUri::new("at://Alice.Test/app.bsky.feed.post/3k4duaz5vfs2b?x=1#/text").unwrap();
+        assert_eq!(
+            uri,
+            "at://alice.test/app.bsky.feed.post/3k4duaz5vfs2b?x=1#/text"
+        );
+        assert_eq!(uri.handle().unwrap(), "alice.test");
+        assert_eq!(uri.did(), None);
+        assert_eq!(uri.collection().unwrap(), "app.bsky.feed.post");
+        assert_eq!(uri.rkey().unwrap(), "3k4duaz5vfs2b");
+        assert_eq!(uri.query(), Some("x=1"));
+        assert_eq!(uri.fragment(), Some("/text"));
+
+        let repo = AtUri::new("at://did:plc:alice").unwrap();
+        assert_eq!(repo.did().unwrap(), "did:plc:alice");
+        assert_eq!(repo.collection(), None);
+        assert_eq!(repo.rkey(), None);
+    }
+
+    #[test]
+    fn test_building() {
+        let did = Did::new("did:plc:alice").unwrap();
+        let posts = Nsid::new("app.bsky.feed.post").unwrap();
+        let rkey = RecordKey::new("3k4duaz5vfs2b").unwrap();
+        assert_eq!(AtUri::for_repo(did.clone()), "at://did:plc:alice");
+        assert_eq!(
+            AtUri::for_collection(did.clone(), &posts),
+            "at://did:plc:alice/app.bsky.feed.post"
+        );
+        let record = AtUri::for_record(did, &posts, &rkey);
+        assert_eq!(
+            record,
+            "at://did:plc:alice/app.bsky.feed.post/3k4duaz5vfs2b"
+        );
+        assert_eq!(
+            record
+                .with_query("a=b")
+                .unwrap()
+                .with_fragment("/c")
+                .unwrap(),
+            "at://did:plc:alice/app.bsky.feed.post/3k4duaz5vfs2b?a=b#/c"
+        );
+        assert!(record.with_fragment("c").is_err());
+    }
+
+    #[test]
+    fn test_web_urls() {
+        for (uri, url) in [
+            ("at://bsky.app", "https://bsky.app/profile/bsky.app"),
+            (
+                "at://did:plc:alice/app.bsky.feed.post/3k4duaz5vfs2b",
+                "https://bsky.app/profile/did:plc:alice/post/3k4duaz5vfs2b",
+            ),
+            (
+                "at://bsky.app/app.bsky.feed.generator/whats-hot",
+                "https://bsky.app/profile/bsky.app/feed/whats-hot",
+            ),
+            (
+                "at://bsky.app/app.bsky.graph.list/3k4duaz5vfs2b",
+                "https://bsky.app/profile/bsky.app/lists/3k4duaz5vfs2b",
+            ),
+            (
+                "at://bsky.app/app.bsky.graph.starterpack/3k4duaz5vfs2b",
+                "https://bsky.app/starter-pack/bsky.app/3k4duaz5vfs2b",
+            ),
+        ] {
+            let uri = AtUri::new(uri).unwrap();
+            assert_eq!(uri.to_web_url().as_deref(), Some(url));
+            assert_eq!(AtUri::from_web_url(url).unwrap(), uri);
+        }
+
+        assert_eq!(
+            AtUri::new("at://bsky.app/app.bsky.actor.profile/self")
+                .unwrap()
+                .to_web_url()
+                .as_deref(),
+            Some("https://bsky.app/profile/bsky.app")
+        );
+        assert_eq!(
+            AtUri::new("at://bsky.app/app.bsky.feed.like/3k4duaz5vfs2b")
+                .unwrap()
+                .to_web_url(),
+            None
+        );
+        assert_eq!(
+            AtUri::from_web_url("https://bsky.app/profile/bsky.app/post/3k4duaz5vfs2b/").unwrap(),
+            "at://bsky.app/app.bsky.feed.post/3k4duaz5vfs2b"
+        );
+        for url in [
+            "https://example.com/profile/bsky.app",
+            "https://bsky.app/search?q=rust",
+            "https://bsky.app/profile/bsky.app/likes/3k4duaz5vfs2b",
+            "https://bsky.app/profile/not a handle",
+        ] {
+            assert!(AtUri::from_web_url(url).is_err(), "{}", url);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use super::{string_type, SyntaxError};
+
+const KIND: &str = "DID";
+const MAX_LENGTH: usize = 2048;
+
+/// A decentralized identifier, e.g. `did:plc:z72i7hdynmk6r22z27h6tvur`. Only the generic DID
+/// syntax is checked, not whether atproto supports the method.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Did(String);
+
+string_type!(Did);
+
+impl Did {
+    pub fn new(did: &str) -> Result<Self, SyntaxError> {
+        if did.len() > MAX_LENGTH {
+            return Err(SyntaxError::new(
+                KIND,
+                did,
+                format!("can be at most {} characters long", MAX_LENGTH),
+            ));
+        }
+        let Some(rest) = did.strip_prefix("did:") else {
+            return Err(SyntaxError::new(KIND, did, "must start with did:"));
+        };
+        let Some((method, identifier)) = rest.split_once(':') else {
+            return Err(SyntaxError::new(
+                KIND,
+                did,
+                "must have a method and an identifier",
+            ));
+        };
+        if method.is_empty() || !method.chars().all(|c| c.is_ascii_lowercase()) {
+            return Err(SyntaxError::new(
+                KIND,
+                did,
+                "the method must be lowercase letters",
+            ));
+        }
+        if identifier.is_empty() {
+            return Err(SyntaxError::new(KIND, did, "the identifier is empty"));
+        }
+        if !identifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._:%-".contains(c))
+        {
+            return Err(SyntaxError::new(
+                KIND,
+                did,
+                "the identifier may only contain letters, digits and ._:%-",
+            ));
+        }
+        if identifier.ends_with([':', '%']) {
+            return Err(SyntaxError::new(
+                KIND,
+                did,
+                "can't end with a colon or percent sign",
+            ));
+        }
+        Ok(Self(did.to_string()))
+    }
+
+    /// The DID method, e.g. `plc` or `web`.
+    pub fn method(&self) -> &str {
+        self.0[4..].split(':').next().unwrap_or_default()
+    }
+
+    /// Everything after the method.
+    pub fn identifier(&self) -> &str {
+        &self.0[5 + self.method().len()..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_dids() {
+        for did in [
+            "did:plc:z72i7hdynmk6r22z27h6tvur",
+            "did:web:example.com",
+            "did:web:localhost%3A8080",
+            "did:method:val:two",
+            "did:m:v",
+            "did:method:-:_:.",
+        ] {
+            assert!(Did::new(did).is_ok(), "{}", did);
+        }
+        let did = Did::new("did:web:example.com").unwrap();
+        assert_eq!(did.method(), "web");
+        assert_eq!(did.identifier(), "example.com");
+    }
+
+    #[test]
+    fn test_invalid_dids() {
+        for did in [
+            "",
+            "did",
+            "did:",
+            "did:plc",
+            "did:plc:",
+            "DID:plc:abc",
+            "did:PLC:abc",
+            "did:plc:abc:",
+            "did:plc:abc%",
+            "did:plc:ab/c",
+            "did:plc:ab c",
+            "did:plc:ab#c",
+        ] {
+            assert!(Did::new(did).is_err(), "{}", did);
+        }
+        assert!(Did::new(&format!("did:plc:{}", "a".repeat(2048))).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use super::{check_domain_segment, string_type, SyntaxError};
+
+const KIND: &str = "handle";
+const MAX_LENGTH: usize = 253;
+
+/// A handle, e.g. `alice.bsky.social`. Handles are case-insensitive and normalized to
+/// lowercase when parsed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Handle(String);
+
+string_type!(Handle);
+
+impl Handle {
+    /// What servers show for handles that don't check out.
+    pub const INVALID: &'static str = "handle.invalid";
+
+    pub fn new(handle: &str) -> Result<Self, SyntaxError> {
+        if handle.len() > MAX_LENGTH {
+            return Err(SyntaxError::new(
+                KIND,
+                handle,
+                format!("can be at most {} characters long", MAX_LENGTH),
+            ));
+        }
+        let segments: Vec<&str> = handle.split('.').collect();
+        if segments.len() < 2 {
+            return Err(SyntaxError::new(
+                KIND,
+                handle,
+                "must be a domain name with at least two segments",
+            ));
+        }
+        for segment in &segments {
+            check_domain_segment(KIND, handle, segment)?;
+        }
+        if segments[segments.len() - 1].starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(SyntaxError::new(
+                KIND,
+                handle,
+                "the top-level domain can't start with a digit",
+            ));
+        }
+        Ok(Self(handle.to_ascii_lowercase()))
+    }
+
+    /// Whether this is `handle.invalid`.
+    pub fn is_invalid(&self) -> bool {
+        self.0 == Self::INVALID
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_handles() {
+        for handle in [
+            "jay.bsky.social",
+            "8.cn",
+            "name.t--t",
+            "XX.LCS.MIT.EDU",
+            "a.co",
+            "xn--notarealidn.com",
+            "john.test",
+            "handle.invalid",
+        ] {
+            assert!(Handle::new(handle).is_ok(), "{}", handle);
+        }
+        assert_eq!(Handle::new("Alice.Test").unwrap(), "alice.test");
+        assert!(Handle::new("handle.invalid").unwrap().is_invalid());
+    }
+
+    #[test]
+    fn test_invalid_handles() {
+        for handle in [
+            "jo@hn.test",
+            "💩.test",
+            "john..test",
+            "xn--bcher-.tld",
+            "john.0",
+            "cn.8",
+            "www.masełkowski.pl.com",
+            "org",
+            "name.org.",
+            ".name.org",
+            "-name.org",
+            "name-.org",
+        ] {
+            assert!(Handle::new(handle).is_err(), "{}", handle);
+        }
+        let long = format!("{}.com", vec!["a".repeat(63); 4].join("."));
+        assert!(Handle::new(&long).is_err());
+        assert!(Handle::new(&format!("{}.com", "a".repeat(64))).is_err());
+    }
+}
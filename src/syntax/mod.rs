@@ -0,0 +1,225 @@
+//! Validated atproto identifiers, following the syntax specs at <https://atproto.com/specs>.
+//!
+//! Each type wraps the identifier as a `String` that is known to be well-formed. They parse
+//! with `FromStr`/`TryFrom`, serialize as plain strings, reject malformed input when
+//! deserialized, and deref to `&str` so that they can be passed wherever a string is expected.
+
+mod at_identifier;
+mod at_uri;
+mod did;
+mod handle;
+mod nsid;
+mod record_key;
+mod tid;
+
+pub use at_identifier::AtIdentifier;
+pub use at_uri::AtUri;
+pub use did::Did;
+pub use handle::Handle;
+pub use nsid::Nsid;
+pub use record_key::RecordKey;
+pub use tid::{Tid, TidGenerator, MAX_CLOCK_ID};
+
+use std::fmt;
+
+/// Why a string is not a valid identifier of some kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    kind: &'static str,
+    value: String,
+    reason: String,
+}
+
+impl SyntaxError {
+    pub(crate) fn new(kind: &'static str, value: &str, reason: impl Into<String>) -> Self {
+        Self {
+            kind,
+            value: value.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    /// The kind of identifier, e.g. `handle`.
+    pub fn kind(&self) -> &str {
+        self.kind
+    }
+
+    /// The rejected input.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MAX_SHOWN: usize = 80;
+        if self.value.chars().count() > MAX_SHOWN {
+            let shown: String = self.value.chars().take(MAX_SHOWN).collect();
+            write!(f, "Invalid {} \"{}...\": {}", self.kind, shown, self.reason)
+        } else {
+            write!(
+                f,
+                "Invalid {} \"{}\": {}",
+                self.kind, self.value, self.reason
+            )
+        }
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+/// Checks one dot-separated segment of a domain name: 1 to 63 ASCII letters, digits and
+/// hyphens, not starting or ending with a hyphen.
+fn check_domain_segment(kind: &'static str, value: &str, segment: &str) -> Result<(), SyntaxError> {
+    if segment.is_empty() || segment.len() > 63 {
+        return Err(SyntaxError::new(
+            kind,
+            value,
+            "each segment must be 1 to 63 characters long",
+        ));
+    }
+    if !segment
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(SyntaxError::new(
+            kind,
+            value,
+            "only ASCII letters, digits, hyphens and dots are allowed",
+        ));
+    }
+    if segment.starts_with('-') || segment.ends_with('-') {
+        return Err(SyntaxError::new(
+            kind,
+            value,
+            "segments can't start or end with a hyphen",
+        ));
+    }
+    Ok(())
+}
+
+/// The conversions and trait impls every identifier type shares. The type provides
+/// `fn new(&str) -> Result<Self, SyntaxError>`.
+macro_rules! string_type {
+    ($name:ident) => {
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = $crate::syntax::SyntaxError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Self::new(s)
+            }
+        }
+
+        impl TryFrom<&str> for $name {
+            type Error = $crate::syntax::SyntaxError;
+
+            fn try_from(s: &str) -> Result<Self, Self::Error> {
+                Self::new(s)
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = $crate::syntax::SyntaxError;
+
+            fn try_from(s: String) -> Result<Self, Self::Error> {
+                Self::new(&s)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl std::ops::Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+
+        impl PartialEq<String> for $name {
+            fn eq(&self, other: &String) -> bool {
+                &self.0 == other
+            }
+        }
+
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.0)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let value = String::deserialize(deserializer)?;
+                Self::new(&value).map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+use string_type;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string_type_conversions() {
+        let did: Did = "did:plc:alice".parse().unwrap();
+        assert_eq!(did, "did:plc:alice");
+        assert_eq!(did.to_string(), "did:plc:alice");
+        assert!(did.starts_with("did:"), "derefs to str");
+        assert_eq!(
+            serde_json::to_value(&did).unwrap(),
+            serde_json::json!("did:plc:alice")
+        );
+        assert_eq!(
+            serde_json::from_value::<Did>(serde_json::json!("did:plc:alice")).unwrap(),
+            did
+        );
+
+        let err = serde_json::from_value::<Did>(serde_json::json!("alice")).unwrap_err();
+        assert!(
+            err.to_string().starts_with("Invalid DID \"alice\""),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use super::{check_domain_segment, string_type, SyntaxError};
+
+const KIND: &str = "NSID";
+const MAX_LENGTH: usize = 317;
+const MAX_AUTHORITY_LENGTH: usize = 253;
+
+/// A namespaced identifier of a lexicon, e.g. `app.bsky.feed.post`: a reversed domain name
+/// followed by a name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Nsid(String);
+
+string_type!(Nsid);
+
+impl Nsid {
+    pub fn new(nsid: &str) -> Result<Self, SyntaxError> {
+        if nsid.len() > MAX_LENGTH {
+            return Err(SyntaxError::new(
+                KIND,
+                nsid,
+                format!("can be at most {} characters long", MAX_LENGTH),
+            ));
+        }
+        let Some((authority, name)) = nsid.rsplit_once('.') else {
+            return Err(SyntaxError::new(
+                KIND,
+                nsid,
+                "must have at least 3 segments",
+            ));
+        };
+        let segments: Vec<&str> = authority.split('.').collect();
+        if segments.len() < 2 {
+            return Err(SyntaxError::new(
+                KIND,
+                nsid,
+                "must have at least 3 segments",
+            ));
+        }
+        if authority.len() > MAX_AUTHORITY_LENGTH {
+            return Err(SyntaxError::new(
+                KIND,
+                nsid,
+                format!(
+                    "the domain authority can be at most {} characters long",
+                    MAX_AUTHORITY_LENGTH
+                ),
+            ));
+        }
+        for segment in &segments {
+            check_domain_segment(KIND, nsid, segment)?;
+        }
+        if segments[0].starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(SyntaxError::new(
+                KIND,
+                nsid,
+                "the first segment can't start with a digit",
+            ));
+        }
+        if name.is_empty()
+            || name.len() > 63
+            || !name.chars().all(|c| c.is_ascii_alphanumeric())
+            || name.starts_with(|c: char| c.is_ascii_digit())
+        {
+            return Err(SyntaxError::new(
+                KIND,
+                nsid,
+                "the name must be 1 to 63 letters and digits, starting with a letter",
+            ));
+        }
+        Ok(Self(nsid.to_string()))
+    }
+
+    /// The domain authority in the usual order, e.g. `bsky.app` for `app.bsky.feed.post`.
+    pub fn authority(&self) -> String {
+        let (authority, _) = self.0.rsplit_once('.').unwrap_or_default();
+        authority.rsplit('.').collect::<Vec<_>>().join(".")
+    }
+
+    /// The last segment, e.g. `post` for `app.bsky.feed.post`.
+    pub fn name(&self) -> &str {
+        self.0.rsplit('.').next().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_nsids() {
+        for nsid in [
+            "com.example.fooBar",
+            "net.users.bob.ping",
+            "a-0.b-1.c",
+            "a.b.c",
+            "cn.8.lex.stuff",
+            "com.atproto.repo.createRecord",
+        ] {
+            assert!(Nsid::new(nsid).is_ok(), "{}", nsid);
+        }
+        let nsid = Nsid::new("app.bsky.feed.post").unwrap();
+        assert_eq!(nsid.authority(), "feed.bsky.app");
+        assert_eq!(nsid.name(), "post");
+    }
+
+    #[test]
+    fn test_invalid_nsids() {
+        for nsid in [
+            "com.example",
+            "com.example.foo-bar",
+            "com.example.3",
+            "com.exa💩ple.thing",
+            "com..example.thing",
+            "com.example.*",
+            "1com.example.thing",
+            "-com.example.thing",
+            "com.example.",
+            "",
+        ] {
+            assert!(Nsid::new(nsid).is_err(), "{}", nsid);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use super::{string_type, SyntaxError};
+
+const KIND: &str = "record key";
+const MAX_LENGTH: usize = 512;
+
+/// The key of a record in a collection, e.g. `3jzfcijpj2z2a` or `self`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecordKey(String);
+
+string_type!(RecordKey);
+
+impl RecordKey {
+    pub fn new(rkey: &str) -> Result<Self, SyntaxError> {
+        if rkey.is_empty() || rkey.len() > MAX_LENGTH {
+            return Err(SyntaxError::new(
+                KIND,
+                rkey,
+                format!("must be 1 to {} characters long", MAX_LENGTH),
+            ));
+        }
+        if rkey == "." || rkey == ".." {
+            return Err(SyntaxError::new(KIND, rkey, "can't be . or .."));
+        }
+        if !rkey
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._:~-".contains(c))
+        {
+            return Err(SyntaxError::new(
+                KIND,
+                rkey,
+                "may only contain letters, digits and ._:~-",
+            ));
+        }
+        Ok(Self(rkey.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_keys() {
+        for rkey in [
+            "3jui7kd54zh2y",
+            "self",
+            "example.com",
+            "~1.2-3_",
+            "dHJ1ZQ",
+            "pre:fix",
+        ] {
+            assert!(RecordKey::new(rkey).is_ok(), "{}", rkey);
+        }
+        for rkey in [
+            "",
+            ".",
+            "..",
+            "alpha/beta",
+            "#extra",
+            "@handle",
+            "any space",
+            "a%20b",
+        ] {
+            assert!(RecordKey::new(rkey).is_err(), "{}", rkey);
+        }
+        assert!(RecordKey::new(&"a".repeat(513)).is_err());
+    }
+}
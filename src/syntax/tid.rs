@@ -0,0 +1,233 @@
+use super::{string_type, RecordKey, SyntaxError};
+use chrono::{DateTime, Utc};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const KIND: &str = "TID";
+const LENGTH: usize = 13;
+pub(crate) const ALPHABET: &str = "234567abcdefghijklmnopqrstuvwxyz";
+const CLOCK_ID_BITS: u32 = 10;
+/// The largest clock id, which takes the low 10 bits of a TID.
+pub const MAX_CLOCK_ID: u16 = (1 << CLOCK_ID_BITS) - 1;
+
+/// A timestamp identifier, e.g. `3jzfcijpj2z2a`: 13 characters of base32-sortable, the record
+/// key of most records and the revision of repo commits.
+///
+/// A TID encodes a 64-bit number: a zero bit, 53 bits of microseconds since the Unix epoch and
+/// a 10-bit clock id. TIDs sort as strings in the order they were created, so records can be
+/// ordered by their keys without fetching them.
+///
+/// ```
+/// use rustysky::syntax::Tid;
+///
+/// let first = Tid::now();
+/// let second = Tid::now();
+/// assert!(first < second);
+///
+/// let tid = Tid::from_parts(1_700_000_000_000_000, 7);
+/// assert_eq!(tid.timestamp_micros(), 1_700_000_000_000_000);
+/// assert_eq!(tid.clock_id(), 7);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tid(String);
+
+string_type!(Tid);
+
+impl Tid {
+    pub fn new(tid: &str) -> Result<Self, SyntaxError> {
+        if tid.len() != LENGTH {
+            return Err(SyntaxError::new(
+                KIND,
+                tid,
+                format!("must be {} characters long", LENGTH),
+            ));
+        }
+        if !tid.chars().all(|c| ALPHABET.contains(c)) {
+            return Err(SyntaxError::new(
+                KIND,
+                tid,
+                "may only contain 234567 and lowercase letters",
+            ));
+        }
+        // The top bit of the 64-bit value is always 0.
+        if !tid.starts_with(|c: char| "234567abcdefghij".contains(c)) {
+            return Err(SyntaxError::new(KIND, tid, "must start with 2-7 or a-j"));
+        }
+        Ok(Self(tid.to_string()))
+    }
+
+    /// A new TID for the current time from the process-wide [`TidGenerator`], which has a
+    /// random clock id. TIDs from this function are strictly increasing, across threads too.
+    pub fn now() -> Self {
+        static GENERATOR: OnceLock<TidGenerator> = OnceLock::new();
+        GENERATOR
+            .get_or_init(|| TidGenerator::new(rand::random::<u16>() & MAX_CLOCK_ID))
+            .next()
+    }
+
+    /// The TID of `timestamp_micros` since the Unix epoch and `clock_id`. Only the low 53 bits
+    /// of the timestamp and the low 10 bits of the clock id are used.
+    pub fn from_parts(timestamp_micros: u64, clock_id: u16) -> Self {
+        let timestamp = timestamp_micros & ((1 << 53) - 1);
+        Self::from_u64((timestamp << CLOCK_ID_BITS) | u64::from(clock_id & MAX_CLOCK_ID))
+    }
+
+    fn from_u64(value: u64) -> Self {
+        let alphabet = ALPHABET.as_bytes();
+        let tid = (0..LENGTH)
+            .rev()
+            .map(|i| alphabet[((value >> (i * 5)) & 0x1f) as usize] as char)
+            .collect();
+        Self(tid)
+    }
+
+    /// The 64-bit number the TID encodes.
+    pub fn as_u64(&self) -> u64 {
+        self.0.bytes().fold(0, |value, c| {
+            let digit = ALPHABET.bytes().position(|a| a == c).expect("validated") as u64;
+            (value << 5) | digit
+        })
+    }
+
+    /// Microseconds since the Unix epoch.
+    pub fn timestamp_micros(&self) -> u64 {
+        self.as_u64() >> CLOCK_ID_BITS
+    }
+
+    /// When the TID was created, to the microsecond.
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        let micros = i64::try_from(self.timestamp_micros()).unwrap_or(i64::MAX);
+        DateTime::from_timestamp_micros(micros).unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+
+    pub fn clock_id(&self) -> u16 {
+        (self.as_u64() & u64::from(MAX_CLOCK_ID)) as u16
+    }
+}
+
+impl From<Tid> for RecordKey {
+    fn from(tid: Tid) -> Self {
+        RecordKey::new(tid.as_str()).expect("every TID is a valid record key")
+    }
+}
+
+/// Generates strictly increasing TIDs tagged with one clock id. When the clock hasn't moved
+/// since the last TID, or has gone backwards, the timestamp is bumped by a microsecond instead.
+/// It can be shared between threads; most callers want [`Tid::now`] instead.
+#[derive(Debug)]
+pub struct TidGenerator {
+    clock_id: u16,
+    last: AtomicU64,
+}
+
+impl TidGenerator {
+    pub fn new(clock_id: u16) -> Self {
+        Self {
+            clock_id: clock_id & MAX_CLOCK_ID,
+            last: AtomicU64::new(0),
+        }
+    }
+
+    pub fn clock_id(&self) -> u16 {
+        self.clock_id
+    }
+
+    pub fn next(&self) -> Tid {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_micros() as u64);
+        let previous = self
+            .last
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last + 1))
+            })
+            .expect("the update always succeeds");
+        Tid::from_parts(now.max(previous + 1), self.clock_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tids() {
+        for tid in [
+            "3jzfcijpj2z2a",
+            "7777777777777",
+            "3zzzzzzzzzzzz",
+            "2222222222222",
+        ] {
+            assert!(Tid::new(tid).is_ok(), "{}", tid);
+        }
+        for tid in [
+            "3jzfcijpj2z21",
+            "0000000000000",
+            "3JZFCIJPJ2Z2A",
+            "3jzfcijpj2z2aa",
+            "3jzfcijpj2z2",
+            "zzzzzzzzzzzzz",
+            "kjzfcijpj2z2a",
+        ] {
+            assert!(Tid::new(tid).is_err(), "{}", tid);
+        }
+    }
+
+    #[test]
+    fn test_parts() {
+        let tid = Tid::new("3jzfcijpj2z2a").unwrap();
+        assert_eq!(Tid::from_parts(tid.timestamp_micros(), tid.clock_id()), tid);
+        assert_eq!(
+            tid.timestamp().timestamp(),
+            tid.timestamp_micros() as i64 / 1_000_000
+        );
+
+        let tid = Tid::from_parts(1_700_000_000_123_456, 1023);
+        assert!(Tid::new(&tid).is_ok());
+        assert_eq!(tid.timestamp_micros(), 1_700_000_000_123_456);
+        assert_eq!(tid.clock_id(), 1023);
+        assert_eq!(
+            tid.timestamp().to_rfc3339(),
+            "2023-11-14T22:13:20.123456+00:00"
+        );
+
+        assert_eq!(Tid::from_parts(0, 0), "2222222222222");
+        assert_eq!(Tid::from_parts(u64::MAX, u16::MAX), "bzzzzzzzzzzzz");
+        assert!(Tid::from_parts(1, 0) > Tid::from_parts(0, MAX_CLOCK_ID));
+    }
+
+    #[test]
+    fn test_generator() {
+        let generator = TidGenerator::new(5);
+        let first = generator.next();
+        let second = generator.next();
+        assert!(first < second);
+        assert_eq!(second.clock_id(), 5);
+        let age = Utc::now() - second.timestamp();
+        assert!(age.num_seconds().abs() < 5);
+        let _: RecordKey = second.into();
+    }
+
+    #[test]
+    fn test_monotonic_across_threads() {
+        let generator = std::sync::Arc::new(TidGenerator::new(0));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let generator = generator.clone();
+                std::thread::spawn(move || (0..1000).map(|_| generator.next()).collect::<Vec<_>>())
+            })
+            .collect();
+        let mut tids: Vec<Tid> = handles
+            .into_iter()
+            .flat_map(|handle| {
+                let tids = handle.join().unwrap();
+                assert!(tids.windows(2).all(|pair| pair[0] < pair[1]));
+                tids
+            })
+            .collect();
+        tids.sort();
+        tids.dedup();
+        assert_eq!(tids.len(), 4000);
+    }
+}
@@ -1,12 +1,39 @@
+use crate::xrpc::{RateLimiter, RetryPolicy};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Clone)]
 pub struct BlueskyConfiguration {
     pub request_content_type: String,
     pub xrpc_host: String,
     pub xrpc_connection_pooling: bool,
+    /// Total time allowed for a request, from connecting to reading the whole response.
+    pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    pub user_agent: String,
+    /// Proxy URL for all requests, e.g. `http://localhost:8080` or `socks5://localhost:1080`.
+    pub proxy: Option<String>,
+    /// Headers sent with every request, e.g. `atproto-accept-labelers`.
+    pub default_headers: Vec<(String, String)>,
+    /// Logs the headers and raw body of every response at debug level.
+    pub http_debug_logging: bool,
+    pub retry_policy: RetryPolicy,
+    /// The rate limiting settings. Every client built from the configuration gets a limiter of
+    /// its own with these settings, which its clones share since they draw on the same budget.
+    pub rate_limiter: Arc<RateLimiter>,
 }
 pub fn get_default_configuration() -> BlueskyConfiguration {
     BlueskyConfiguration {
         request_content_type: "application/json".to_string(),
         xrpc_host: "https://bsky.social".to_string(),
         xrpc_connection_pooling: true,
+        timeout: Some(Duration::from_secs(30)),
+        connect_timeout: Some(Duration::from_secs(10)),
+        user_agent: format!("rustysky/{}", env!("CARGO_PKG_VERSION")),
+        proxy: None,
+        default_headers: Vec::new(),
+        http_debug_logging: false,
+        retry_policy: RetryPolicy::default(),
+        rate_limiter: Arc::new(RateLimiter::default()),
     }
 }
@@ -1,136 +1,477 @@
-use anyhow::Result;
+use super::rate_limit::RateLimitInfo;
+use super::transport::{
+    HttpMethod, HttpRequest, HttpResponse, HttpTransport, ReqwestTransport, StreamBody,
+};
+use super::xrpc_error::{XrpcError, XrpcResult};
+use crate::oauth::DpopKey;
+use crate::types::BlueskyConfiguration;
 use log::{debug, info};
-use reqwest::Client;
+use rand::Rng;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Mutex,
-};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
 
-static HTTP_DEBUG_LOGGING: AtomicBool = AtomicBool::new(false);
+/// Controls how failed XRPC requests are retried.
+///
+/// Delays grow exponentially from `initial_backoff` by `backoff_multiplier` per attempt and are
+/// capped at `max_backoff`. `jitter` is the fraction of each delay that is randomized (0.0 to
+/// 1.0), so that many clients failing at once don't all come back at the same moment.
+///
+/// Requests that are not idempotent, like `createRecord`, are only replayed when the server
+/// told us it did not process them (HTTP 429), unless `retry_non_idempotent` is set.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one. 1 disables retries.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub backoff_multiplier: f64,
+    pub jitter: f64,
+    pub retry_transport_errors: bool,
+    pub retry_server_errors: bool,
+    pub retry_rate_limited: bool,
+    pub retry_non_idempotent: bool,
+}
 
-pub fn set_http_debug_logging(value: bool) {
-    HTTP_DEBUG_LOGGING.store(value, Ordering::Relaxed);
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            backoff_multiplier: 2.0,
+            jitter: 0.5,
+            retry_transport_errors: true,
+            retry_server_errors: true,
+            retry_rate_limited: true,
+            retry_non_idempotent: false,
+        }
+    }
 }
 
-static mut CLIENT: Option<Client> = None;
-static CLIENT_LOCK: Mutex<()> = Mutex::new(());
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
 
-pub fn get_client(use_connection_pooling: bool) -> Client {
-    if !use_connection_pooling {
-        return Client::new();
+    /// The delay before retrying after the given (1-based) failed attempt.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(63) as i32;
+        // The fields are public, so a negative or NaN multiplier or jitter is treated as 0.
+        let multiplier = self.backoff_multiplier.max(0.0);
+        let delay = self.initial_backoff.as_secs_f64() * multiplier.powi(exponent);
+        let delay = delay.min(self.max_backoff.as_secs_f64());
+        let jitter = if self.jitter.is_nan() {
+            0.0
+        } else {
+            self.jitter.clamp(0.0, 1.0)
+        };
+        let factor = if jitter > 0.0 {
+            1.0 - jitter * rand::thread_rng().gen::<f64>()
+        } else {
+            1.0
+        };
+        Duration::try_from_secs_f64(delay * factor).unwrap_or(self.max_backoff)
     }
-    let _guard = CLIENT_LOCK.lock().unwrap();
-    unsafe {
-        if CLIENT.is_none() {
-            CLIENT = Some(reqwest::Client::new());
+
+    pub fn should_retry(&self, err: &XrpcError, idempotent: bool) -> bool {
+        match err {
+            XrpcError::RateLimited(_) => self.retry_rate_limited,
+            _ if !idempotent && !self.retry_non_idempotent => false,
+            XrpcError::Transport(_) => self.retry_transport_errors,
+            XrpcError::Server(_) => self.retry_server_errors,
+            _ => false,
         }
-        CLIENT.as_ref().unwrap().clone()
     }
 }
 
-pub fn clear_client() {
-    let _guard = CLIENT_LOCK.lock().unwrap();
-    unsafe {
-        CLIENT = None;
-    }
+/// An XRPC client built from a `BlueskyConfiguration`.
+///
+/// Cloning is cheap and clones share the transport, configuration and rate limit state. Clients
+/// built separately share nothing, not even when built from clones of one configuration, so one
+/// process can talk to many hosts or accounts side by side.
+///
+/// With a DPoP key, authenticated calls send their token as an OAuth DPoP-bound token with a
+/// signed proof instead of as a bearer token, see `oauth::OAuthSession::client`.
+#[derive(Clone)]
+pub struct XrpcClient {
+    transport: Arc<dyn HttpTransport>,
+    config: Arc<BlueskyConfiguration>,
+    dpop: Option<DpopKey>,
 }
 
-pub async fn post<T: Serialize, R: DeserializeOwned>(
-    url: String,
-    request: T,
-    use_connection_pooling: bool,
-) -> Result<R, (Option<u16>, String)> {
-    let client = get_client(use_connection_pooling);
-    let body = serde_json::to_string(&request)
-        .map_err(|err| (None, format!("Serialization error: {}", err)))?;
-    let response = client
-        .post(url)
-        .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .body(body)
-        .send()
+impl XrpcClient {
+    /// A client that sends its requests with `reqwest`.
+    pub fn new(config: BlueskyConfiguration) -> XrpcResult<Self> {
+        let transport = ReqwestTransport::new(&config)?;
+        Ok(Self::with_transport(config, Arc::new(transport)))
+    }
+
+    /// A client that sends its requests through `transport`, e.g. a `MockTransport` in tests.
+    /// It gets a limiter of its own with the settings of `config.rate_limiter`.
+    pub fn with_transport(
+        mut config: BlueskyConfiguration,
+        transport: Arc<dyn HttpTransport>,
+    ) -> Self {
+        config.rate_limiter = Arc::new(config.rate_limiter.fresh());
+        Self {
+            transport,
+            config: Arc::new(config),
+            dpop: None,
+        }
+    }
+
+    /// A client for another host, e.g. the PDS an account lives on. It shares the transport
+    /// and settings, but tracks the other host's rate limits separately.
+    pub fn with_host(&self, host: &str) -> Self {
+        let mut config = (*self.config).clone();
+        config.xrpc_host = host.trim_end_matches('/').to_string();
+        config.rate_limiter = Arc::new(self.config.rate_limiter.fresh());
+        Self {
+            transport: self.transport.clone(),
+            config: Arc::new(config),
+            dpop: self.dpop.clone(),
+        }
+    }
+
+    /// Sends tokens as DPoP-bound tokens with proofs signed by `key`.
+    pub fn with_dpop(mut self, key: DpopKey) -> Self {
+        self.dpop = Some(key);
+        self
+    }
+
+    pub fn config(&self) -> &BlueskyConfiguration {
+        &self.config
+    }
+
+    pub(crate) fn shared_config(&self) -> Arc<BlueskyConfiguration> {
+        self.config.clone()
+    }
+
+    pub(crate) fn transport(&self) -> &dyn HttpTransport {
+        self.transport.as_ref()
+    }
+
+    /// The most recent rate limit state reported by the server.
+    pub fn rate_limit(&self) -> Option<RateLimitInfo> {
+        self.config.rate_limiter.latest()
+    }
+
+    async fn send_with_retry<R, F, Fut>(&self, idempotent: bool, send: F) -> XrpcResult<R>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = XrpcResult<R>>,
+    {
+        let config = &self.config;
+        let policy = &config.retry_policy;
+        let mut attempt = 1;
+        let mut waited_for_reset = false;
+        loop {
+            config.rate_limiter.throttle().await;
+            let err = match send().await {
+                Err(err) => err,
+                result => return result,
+            };
+
+            if let (XrpcError::RateLimited(_), false) = (&err, waited_for_reset) {
+                if let Some(wait) = config.rate_limiter.wait_on_limit(err.rate_limit()) {
+                    // Waiting for the reset does not count as an attempt, but we only do it once.
+                    info!("{}, waiting {:?} for the rate limit to reset", err, wait);
+                    tokio::time::sleep(wait).await;
+                    waited_for_reset = true;
+                    continue;
+                }
+            }
+
+            if attempt >= policy.max_attempts || !policy.should_retry(&err, idempotent) {
+                return Err(err);
+            }
+            let delay = policy.backoff(attempt);
+            info!(
+                "{} (attempt {} of {}), retrying in {:?}",
+                err, attempt, policy.max_attempts, delay
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Sends the request with `token` as a bearer token, or as a DPoP-bound token if the
+    /// client has a DPoP key.
+    async fn send_once(
+        &self,
+        request: &HttpRequest,
+        token: Option<&str>,
+    ) -> XrpcResult<HttpResponse> {
+        if let Some(dpop) = &self.dpop {
+            return dpop.send(self.transport(), request, token).await;
+        }
+        let mut request = request.clone();
+        if let Some(token) = token {
+            request.headers.insert(AUTHORIZATION, bearer(token)?);
+        }
+        self.transport.send(request).await
+    }
+
+    async fn send<R: DeserializeOwned>(
+        &self,
+        request: HttpRequest,
+        token: Option<&str>,
+        idempotent: bool,
+    ) -> XrpcResult<R> {
+        self.send_with_retry(idempotent, || async {
+            let response = self.send_once(&request, token).await?;
+            handle_response::<R>(response, &self.config)
+        })
         .await
-        .map_err(|err| (None, format!("Request error: {}", err)))?;
-    handle_response::<R>(response).await
+    }
+
+    pub(crate) async fn post<T: Serialize, R: DeserializeOwned>(
+        &self,
+        url: String,
+        request: T,
+        idempotent: bool,
+    ) -> XrpcResult<R> {
+        let body = serde_json::to_vec(&request)
+            .map_err(|err| XrpcError::Serialization(err.to_string()))?;
+        let request = HttpRequest {
+            method: HttpMethod::Post,
+            url,
+            headers: json_headers(),
+            body: Some(body),
+            stream: None,
+        };
+        self.send(request, None, idempotent).await
+    }
+
+    pub(crate) async fn post_auth<T: Serialize, R: DeserializeOwned>(
+        &self,
+        url: String,
+        access_jwt: &str,
+        request: T,
+        idempotent: bool,
+    ) -> XrpcResult<R> {
+        let body = serde_json::to_vec(&request)
+            .map_err(|err| XrpcError::Serialization(err.to_string()))?;
+        info!("{}", String::from_utf8_lossy(&body));
+        let request = HttpRequest {
+            method: HttpMethod::Post,
+            url,
+            headers: json_headers(),
+            body: Some(body),
+            stream: None,
+        };
+        self.send(request, Some(access_jwt), idempotent).await
+    }
+
+    /// Posts a non-JSON body, e.g. a blob, streaming it from its source.
+    pub(crate) async fn post_stream<R: DeserializeOwned>(
+        &self,
+        url: String,
+        access_jwt: &str,
+        content_type: &str,
+        body: StreamBody,
+        idempotent: bool,
+    ) -> XrpcResult<R> {
+        let mut headers = HeaderMap::new();
+        let content_type = HeaderValue::from_str(content_type)
+            .map_err(|err| XrpcError::Serialization(err.to_string()))?;
+        headers.insert(CONTENT_TYPE, content_type);
+        let request = HttpRequest {
+            method: HttpMethod::Post,
+            url,
+            headers,
+            body: None,
+            stream: Some(body),
+        };
+        self.send(request, Some(access_jwt), idempotent).await
+    }
+
+    pub(crate) async fn post_refresh<R: DeserializeOwned>(
+        &self,
+        url: String,
+        refresh_jwt: &str,
+    ) -> XrpcResult<R> {
+        let request = HttpRequest {
+            method: HttpMethod::Post,
+            url,
+            headers: json_headers(),
+            body: None,
+            stream: None,
+        };
+        // Refresh tokens rotate on use, so a refresh is never blindly replayed.
+        self.send(request, Some(refresh_jwt), false).await
+    }
+
+    pub(crate) async fn get<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        auth: Option<&str>,
+    ) -> XrpcResult<T> {
+        let request = HttpRequest {
+            method: HttpMethod::Get,
+            url: url.to_string(),
+            headers: HeaderMap::new(),
+            body: None,
+            stream: None,
+        };
+        self.send(request, auth, true).await
+    }
 }
 
-pub async fn post_auth<T: Serialize, R: DeserializeOwned>(
-    url: String,
-    access_jwt: &str,
-    request: T,
-    use_connection_pooling: bool,
-) -> Result<R, (Option<u16>, String)> {
-    let client = get_client(use_connection_pooling);
-    let body = serde_json::to_string(&request)
-        .map_err(|err| (None, format!("Serialization error: {}", err)))?;
-    info!("{}", body);
-    let response = client
-        .post(url)
-        .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .header("Authorization", format!("Bearer {}", access_jwt))
-        .body(body)
-        .send()
-        .await
-        .map_err(|err| (None, format!("Request error: {}", err)))?;
-    handle_response::<R>(response).await
+fn bearer(token: &str) -> XrpcResult<HeaderValue> {
+    HeaderValue::from_str(&format!("Bearer {}", token))
+        .map_err(|err| XrpcError::Serialization(err.to_string()))
 }
 
-pub async fn post_refresh<R: DeserializeOwned>(
-    url: String,
-    refresh_jwt: &str,
-    use_connection_pooling: bool,
-) -> Result<R, (Option<u16>, String)> {
-    let client = get_client(use_connection_pooling);
-    let response = client
-        .post(url)
-        .header("Authorization", format!("Bearer {}", refresh_jwt))
-        .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .send()
-        .await
-        .map_err(|err| (None, format!("Request error: {}", err)))?;
-    handle_response::<R>(response).await
+fn json_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers
 }
 
-pub async fn get<T: DeserializeOwned>(
-    url: &str,
-    auth: &str,
-    use_connection_pooling: bool,
-) -> Result<T, (Option<u16>, String)> {
-    let client = get_client(use_connection_pooling);
-    let response = client
-        .get(url)
-        .header("Authorization", format!("Bearer {}", auth))
-        .send()
-        .await
-        .map_err(|err| (None, format!("Request error: {}", err)))?;
-    handle_response::<T>(response).await
+fn handle_response<R: DeserializeOwned>(
+    response: HttpResponse,
+    config: &BlueskyConfiguration,
+) -> XrpcResult<R> {
+    let rate_limit = RateLimitInfo::from_headers(&response.headers);
+    config.rate_limiter.update(rate_limit.clone());
+    decode_response(response, config).map_err(|err| err.with_rate_limit(rate_limit))
 }
 
-async fn handle_response<R: DeserializeOwned>(
-    response: reqwest::Response,
-) -> Result<R, (Option<u16>, String)> {
-    if !response.status().is_success() {
-        return Err((
-            Some(response.status().as_u16()),
-            format!("HTTP error with status code: {}", response.status()),
-        ));
+/// `handle_response` for responses from other hosts than the PDS, e.g. a PLC directory, whose
+/// rate limits must not throttle the PDS client.
+pub(crate) fn decode_response<R: DeserializeOwned>(
+    response: HttpResponse,
+    config: &BlueskyConfiguration,
+) -> XrpcResult<R> {
+    if !response.is_success() {
+        // The body carries the lexicon error envelope, so keep it even when it is not JSON.
+        let body = String::from_utf8_lossy(&response.body);
+        return Err(XrpcError::from_response(response.status, &body));
     }
 
-    if HTTP_DEBUG_LOGGING.load(Ordering::Relaxed) {
-        let headers = response.headers().clone();
-        let raw_json = response
-            .text()
-            .await
-            .map_err(|err| (None, format!("Failed to get response text: {}", err)))?;
-        debug!("Response Headers:\n{:#?}", headers);
-        debug!("Raw JSON Response: {}", raw_json);
-        serde_json::from_str::<R>(&raw_json)
-            .map_err(|err| (None, format!("Deserialization error: {}", err)))
+    if config.http_debug_logging {
+        debug!("Response Headers:\n{:#?}", response.headers);
+        debug!(
+            "Raw JSON Response: {}",
+            String::from_utf8_lossy(&response.body)
+        );
+    }
+    // Procedures without output, like deleteSession, answer with an empty body.
+    let body: &[u8] = if response.body.is_empty() {
+        b"null"
     } else {
-        response
-            .json::<R>()
-            .await
-            .map_err(|err| (None, format!("Deserialization error: {}", err)))
+        &response.body
+    };
+    serde_json::from_slice::<R>(body).map_err(|err| XrpcError::Deserialization(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::get_default_configuration;
+
+    #[test]
+    fn test_invalid_configuration_is_reported() {
+        let mut config = get_default_configuration();
+        config.default_headers = vec![("bad header".to_string(), "value".to_string())];
+        assert!(matches!(
+            XrpcClient::new(config),
+            Err(XrpcError::Configuration(_))
+        ));
+
+        let mut config = get_default_configuration();
+        config.proxy = Some("not a url".to_string());
+        assert!(matches!(
+            XrpcClient::new(config),
+            Err(XrpcError::Configuration(_))
+        ));
+    }
+
+    #[test]
+    fn test_clients_do_not_share_state() {
+        let mut config = get_default_configuration();
+        config.xrpc_host = "https://pds.example.com".to_string();
+        let first = XrpcClient::new(config.clone()).unwrap();
+        let second = XrpcClient::new(get_default_configuration()).unwrap();
+        let third = XrpcClient::new(config).unwrap();
+        let clone = first.clone();
+
+        assert_eq!(clone.config().xrpc_host, "https://pds.example.com");
+        assert_eq!(second.config().xrpc_host, "https://bsky.social");
+        assert!(Arc::ptr_eq(&first.config, &clone.config));
+        assert!(!Arc::ptr_eq(
+            &first.config().rate_limiter,
+            &second.config().rate_limiter
+        ));
+        assert!(!Arc::ptr_eq(
+            &first.config().rate_limiter,
+            &third.config().rate_limiter
+        ));
+        assert!(Arc::ptr_eq(
+            &first.config().rate_limiter,
+            &clone.config().rate_limiter
+        ));
+    }
+
+    #[test]
+    fn test_backoff_grows_exponentially_up_to_the_cap() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(350),
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn test_jitter_only_shortens_the_delay() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            jitter: 0.5,
+            ..RetryPolicy::default()
+        };
+        for _ in 0..100 {
+            let delay = policy.backoff(1);
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn test_invalid_policies_do_not_panic() {
+        for value in [-2.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let policy = RetryPolicy {
+                initial_backoff: Duration::from_millis(100),
+                max_backoff: Duration::from_millis(350),
+                backoff_multiplier: value,
+                jitter: value,
+                ..RetryPolicy::default()
+            };
+            for attempt in 1..5 {
+                assert!(policy.backoff(attempt) <= Duration::from_millis(350));
+            }
+        }
+    }
+
+    #[test]
+    fn test_non_idempotent_requests_only_retry_rate_limits() {
+        let policy = RetryPolicy::default();
+        let server = XrpcError::from_response(503, "");
+        let rate_limited = XrpcError::from_response(429, "");
+        assert!(policy.should_retry(&server, true));
+        assert!(!policy.should_retry(&server, false));
+        assert!(policy.should_retry(&rate_limited, false));
+        assert!(!policy.should_retry(&XrpcError::from_response(400, ""), true));
     }
 }
@@ -1,70 +1,249 @@
 mod http_client;
+mod rate_limit;
+mod transport;
+mod xrpc_apply_writes;
+mod xrpc_blob;
+mod xrpc_embed;
+mod xrpc_error;
 mod xrpc_post;
+mod xrpc_repo;
 mod xrpc_session;
 mod xrpc_types;
 
-pub use http_client::{clear_client, set_http_debug_logging};
-pub use xrpc_post::{CreatePostRequest, Post, ReplyRef, SelfLabel, SelfLabels, StrongRef};
-pub use xrpc_session::{CreateSessionRequest, CreateSessionResponse};
-pub use xrpc_types::ProfileViewDetailedResponse;
+pub(crate) use http_client::decode_response;
+pub use http_client::{RetryPolicy, XrpcClient};
+pub use rate_limit::{RateLimitInfo, RateLimiter};
+pub use transport::{
+    ByteStream, HttpMethod, HttpRequest, HttpResponse, HttpTransport, MockTransport,
+    ReqwestTransport, StreamBody,
+};
+pub use xrpc_apply_writes::{
+    AppliedWrites, ApplyWrites, ApplyWritesResponse, WriteOp, WriteResult, MAX_WRITES_PER_CALL,
+};
+pub use xrpc_blob::{BlobRef, BlobUpload, UploadBlobResponse, DEFAULT_MAX_BLOB_SIZE};
+pub use xrpc_embed::{
+    AspectRatio, Caption, Embed, External, ExternalEmbed, Image, ImagesEmbed, ImagesEmbedBuilder,
+    Media, RecordEmbed, RecordWithMediaEmbed, VideoEmbed, MAX_IMAGES, MAX_IMAGE_SIZE,
+    MAX_VIDEO_SIZE,
+};
+pub use xrpc_error::{AuthErrorKind, XrpcError, XrpcErrorBody, XrpcErrorResponse, XrpcResult};
+pub use xrpc_post::{Post, ReplyRef, SelfLabel, SelfLabels, StrongRef};
+pub use xrpc_repo::{
+    CommitMeta, CreatePostRequest, CreateRecordRequest, CreateRecordResponse, DeleteRecordRequest,
+    DeleteRecordResponse, GetRecordResponse, ListRecordsResponse, ListedRecord, PutRecordRequest,
+    PutRecordResponse, Record,
+};
+pub use xrpc_session::{
+    CreateSessionRequest, CreateSessionResponse, GetSessionResponse, RefreshSessionResponse,
+};
+pub use xrpc_types::{ProfileViewDetailedResponse, ResolveHandleResponse};
 
-use http_client::{get, post, post_auth, post_refresh};
-use xrpc_session::RefreshSessionResponse;
-
-use crate::types::BlueskyConfiguration;
-use anyhow::Result;
+use crate::dag_cbor::Cid;
+use crate::syntax::{AtUri, RecordKey, SyntaxError};
+use serde::de::DeserializeOwned;
 
 const XRPC_ENDPOINT: &str = "/xrpc/";
 
-fn create_url(host: &str, endpoint: &str) -> String {
-    format!("{}{}{}", host, XRPC_ENDPOINT, endpoint)
+fn create_url(client: &XrpcClient, endpoint: &str) -> String {
+    format!("{}{}{}", client.config().xrpc_host, XRPC_ENDPOINT, endpoint)
+}
+
+/// The URL of a query with its parameters encoded.
+fn query_url(client: &XrpcClient, endpoint: &str, params: &[(&str, &str)]) -> String {
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    format!("{}?{}", create_url(client, endpoint), query)
 }
 
 pub async fn create_session(
     request: &CreateSessionRequest,
-    config: &BlueskyConfiguration,
-) -> Result<CreateSessionResponse, (Option<u16>, String)> {
-    let url = create_url(&config.xrpc_host, "com.atproto.server.createSession");
-    post(url, request, config.xrpc_connection_pooling).await
+    client: &XrpcClient,
+) -> XrpcResult<CreateSessionResponse> {
+    let url = create_url(client, "com.atproto.server.createSession");
+    client.post(url, request, true).await
 }
 
 pub async fn refresh_session(
     refresh_jwt: &str,
-    config: &BlueskyConfiguration,
-) -> Result<RefreshSessionResponse, (Option<u16>, String)> {
-    let url = create_url(&config.xrpc_host, "com.atproto.server.refreshSession");
-    post_refresh(url, refresh_jwt, config.xrpc_connection_pooling).await
+    client: &XrpcClient,
+) -> XrpcResult<RefreshSessionResponse> {
+    let url = create_url(client, "com.atproto.server.refreshSession");
+    client.post_refresh(url, refresh_jwt).await
 }
 
-pub async fn get_profile(
-    session: &mut CreateSessionResponse,
-    config: &BlueskyConfiguration,
-) -> Result<ProfileViewDetailedResponse, (Option<u16>, String)> {
-    if session.session_needs_refresh() {
-        refresh_session(&session.refresh_jwt, config)
-            .await
-            .map(|refreshed_session| session.update_from_refresh(&refreshed_session))?;
-    }
+/// Revokes the session the refresh token belongs to. Both tokens stop working, so the session
+/// has to be discarded afterwards.
+pub async fn delete_session(refresh_jwt: &str, client: &XrpcClient) -> XrpcResult<()> {
+    let url = create_url(client, "com.atproto.server.deleteSession");
+    client.post_refresh(url, refresh_jwt).await
+}
 
-    let url = format!(
-        "{}{}app.bsky.actor.getProfile?actor={}",
-        config.xrpc_host, XRPC_ENDPOINT, session.did
+/// Resolves a handle to a DID with the host's `resolveHandle`. Prefer
+/// `identity::HandleResolver`, which resolves handles without trusting the host and only falls
+/// back to this.
+pub async fn resolve_handle(
+    handle: &str,
+    client: &XrpcClient,
+) -> XrpcResult<ResolveHandleResponse> {
+    let url = query_url(
+        client,
+        "com.atproto.identity.resolveHandle",
+        &[("handle", handle)],
     );
+    client.get(&url, None).await
+}
 
-    get(&url, &session.access_jwt, config.xrpc_connection_pooling).await
+/// Fetches the session the access token belongs to, to check that a stored session is still
+/// valid.
+pub async fn get_session(access_jwt: &str, client: &XrpcClient) -> XrpcResult<GetSessionResponse> {
+    let url = create_url(client, "com.atproto.server.getSession");
+    client.get(&url, Some(access_jwt)).await
+}
+
+/// Fetches a profile. The session's own profile is `get_profile(&session.did, ...)`.
+///
+/// Authenticated calls take the bare access token and never refresh it; use
+/// `bsky_agent::BskyAgent` to have sessions refreshed for you.
+pub async fn get_profile(
+    actor: &str,
+    access_jwt: &str,
+    client: &XrpcClient,
+) -> XrpcResult<ProfileViewDetailedResponse> {
+    let url = query_url(client, "app.bsky.actor.getProfile", &[("actor", actor)]);
+
+    client.get(&url, Some(access_jwt)).await
 }
 
 pub async fn create_post(
     post_request: &CreatePostRequest,
-    session: &mut CreateSessionResponse,
-    config: &BlueskyConfiguration,
-) -> Result<StrongRef, (Option<u16>, String)> {
-    let url = create_url(&config.xrpc_host, "com.atproto.repo.createRecord");
-    post_auth(
-        url,
-        &session.access_jwt,
-        post_request,
-        config.xrpc_connection_pooling,
-    )
-    .await
+    access_jwt: &str,
+    client: &XrpcClient,
+) -> XrpcResult<StrongRef> {
+    let response = create_record(post_request, access_jwt, client).await?;
+    Ok(response.strong_ref())
+}
+
+pub async fn create_record<R: Record>(
+    request: &CreateRecordRequest<R>,
+    access_jwt: &str,
+    client: &XrpcClient,
+) -> XrpcResult<CreateRecordResponse> {
+    let url = create_url(client, "com.atproto.repo.createRecord");
+    client.post_auth(url, access_jwt, request, false).await
+}
+
+/// Creates or replaces a record. Writing the same record again is harmless, so unlike
+/// `create_record` this is retried on transient errors.
+pub async fn put_record<R: Record>(
+    request: &PutRecordRequest<R>,
+    access_jwt: &str,
+    client: &XrpcClient,
+) -> XrpcResult<PutRecordResponse> {
+    let url = create_url(client, "com.atproto.repo.putRecord");
+    client.post_auth(url, access_jwt, request, true).await
+}
+
+pub async fn delete_record(
+    request: &DeleteRecordRequest,
+    access_jwt: &str,
+    client: &XrpcClient,
+) -> XrpcResult<DeleteRecordResponse> {
+    let url = create_url(client, "com.atproto.repo.deleteRecord");
+    client.post_auth(url, access_jwt, request, true).await
+}
+
+/// Applies one batch of at most `MAX_WRITES_PER_CALL` writes in a single commit. Use
+/// `bsky_agent::BskyAgent::apply_writes` for larger batches.
+pub async fn apply_writes(
+    batch: &ApplyWrites,
+    access_jwt: &str,
+    client: &XrpcClient,
+) -> XrpcResult<ApplyWritesResponse> {
+    let url = create_url(client, "com.atproto.repo.applyWrites");
+    client
+        .post_auth(url, access_jwt, batch, batch.is_idempotent())
+        .await
+}
+
+/// Uploads a blob to the repo's PDS, for records to reference. The blob is only kept if a record
+/// references it soon after, so upload it right before writing the record.
+pub async fn upload_blob(
+    upload: &BlobUpload,
+    access_jwt: &str,
+    client: &XrpcClient,
+) -> XrpcResult<BlobRef> {
+    upload.check_size()?;
+    let url = create_url(client, "com.atproto.repo.uploadBlob");
+    let response: UploadBlobResponse = client
+        .post_stream(
+            url,
+            access_jwt,
+            upload.mime_type(),
+            upload.body(),
+            upload.is_replayable(),
+        )
+        .await?;
+    Ok(response.blob)
+}
+
+/// Fetches a record of type `R` from any repo. `cid` asks for a specific version. Records are
+/// public, so the access token is optional.
+pub async fn get_record<R: Record>(
+    repo: &str,
+    rkey: &RecordKey,
+    cid: Option<&Cid>,
+    access_jwt: Option<&str>,
+    client: &XrpcClient,
+) -> XrpcResult<GetRecordResponse<R>> {
+    fetch_record(repo, R::NSID, rkey, cid, access_jwt, client).await
+}
+
+/// Fetches the record `uri` points to, of whatever collection, e.g. as a `serde_json::Value`.
+pub async fn get_record_at<T: DeserializeOwned>(
+    uri: &AtUri,
+    cid: Option<&Cid>,
+    access_jwt: Option<&str>,
+    client: &XrpcClient,
+) -> XrpcResult<GetRecordResponse<T>> {
+    let (Some(collection), Some(rkey)) = (uri.collection(), uri.rkey()) else {
+        return Err(SyntaxError::new("AT URI", uri, "does not point to a record").into());
+    };
+    let repo = uri.authority().to_string();
+    fetch_record(&repo, &collection, &rkey, cid, access_jwt, client).await
+}
+
+async fn fetch_record<T: DeserializeOwned>(
+    repo: &str,
+    collection: &str,
+    rkey: &RecordKey,
+    cid: Option<&Cid>,
+    access_jwt: Option<&str>,
+    client: &XrpcClient,
+) -> XrpcResult<GetRecordResponse<T>> {
+    let cid = cid.map(Cid::to_string);
+    let mut params = vec![("repo", repo), ("collection", collection), ("rkey", rkey)];
+    params.extend(cid.as_deref().map(|cid| ("cid", cid)));
+    let url = query_url(client, "com.atproto.repo.getRecord", &params);
+    client.get(&url, access_jwt).await
+}
+
+/// Lists a page of the records of type `R` in a repo, newest first unless `reverse` is set.
+pub async fn list_records<R: Record>(
+    repo: &str,
+    limit: Option<u32>,
+    cursor: Option<&str>,
+    reverse: bool,
+    access_jwt: Option<&str>,
+    client: &XrpcClient,
+) -> XrpcResult<ListRecordsResponse<R>> {
+    let limit = limit.map(|limit| limit.to_string());
+    let mut params = vec![("repo", repo), ("collection", R::NSID)];
+    params.extend(limit.as_deref().map(|limit| ("limit", limit)));
+    params.extend(cursor.map(|cursor| ("cursor", cursor)));
+    if reverse {
+        params.push(("reverse", "true"));
+    }
+    let url = query_url(client, "com.atproto.repo.listRecords", &params);
+    client.get(&url, access_jwt).await
 }
@@ -0,0 +1,194 @@
+use chrono::{DateTime, TimeZone, Utc};
+use log::info;
+use reqwest::header::HeaderMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The rate limit state a PDS reports in its `ratelimit-*` response headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitInfo {
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset: DateTime<Utc>,
+    /// The raw policy, e.g. `3000;w=300` for 3000 points per 300 seconds.
+    pub policy: Option<String>,
+}
+
+impl RateLimitInfo {
+    /// Parses the `ratelimit-*` headers. Returns `None` unless limit, remaining and reset are all
+    /// present and well-formed.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let limit = header("ratelimit-limit")?.trim().parse().ok()?;
+        let remaining = header("ratelimit-remaining")?.trim().parse().ok()?;
+        let reset_epoch: i64 = header("ratelimit-reset")?.trim().parse().ok()?;
+        let reset = Utc.timestamp_opt(reset_epoch, 0).single()?;
+        Some(Self {
+            limit,
+            remaining,
+            reset,
+            policy: header("ratelimit-policy").map(str::to_string),
+        })
+    }
+
+    /// The window length from the policy's `w=` parameter.
+    pub fn window(&self) -> Option<Duration> {
+        self.policy
+            .as_deref()?
+            .split(';')
+            .find_map(|part| part.trim().strip_prefix("w="))
+            .and_then(|seconds| seconds.parse().ok())
+            .map(Duration::from_secs)
+    }
+
+    /// How long until the window resets, zero if it already has.
+    pub fn time_until_reset(&self) -> Duration {
+        (self.reset - Utc::now()).to_std().unwrap_or(Duration::ZERO)
+    }
+}
+
+/// Tracks the rate limit budget reported by the server and, when enabled, spaces out requests
+/// before the budget runs out and waits out HTTP 429s instead of failing.
+///
+/// The default limiter only records what the server reports; see `with_low_water_mark` and
+/// `with_wait_on_limit` to turn on throttling.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    low_water_mark: Option<u32>,
+    max_wait_on_limit: Option<Duration>,
+    latest: Mutex<Option<RateLimitInfo>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Once the remaining budget drops to `remaining` points, spread the rest of the budget
+    /// evenly over what is left of the window.
+    pub fn with_low_water_mark(mut self, remaining: u32) -> Self {
+        self.low_water_mark = Some(remaining);
+        self
+    }
+
+    /// On HTTP 429, sleep until the window resets and try again, as long as that is no longer
+    /// than `max_wait`.
+    pub fn with_wait_on_limit(mut self, max_wait: Duration) -> Self {
+        self.max_wait_on_limit = Some(max_wait);
+        self
+    }
+
+    /// A limiter with the same settings that has not seen any responses yet, for another host.
+    pub(crate) fn fresh(&self) -> Self {
+        Self {
+            low_water_mark: self.low_water_mark,
+            max_wait_on_limit: self.max_wait_on_limit,
+            latest: Mutex::new(None),
+        }
+    }
+
+    /// The most recent rate limit state reported by the server.
+    pub fn latest(&self) -> Option<RateLimitInfo> {
+        self.latest.lock().unwrap().clone()
+    }
+
+    pub fn update(&self, info: Option<RateLimitInfo>) {
+        if let Some(info) = info {
+            *self.latest.lock().unwrap() = Some(info);
+        }
+    }
+
+    /// The delay to apply before the next request, if the budget is running low.
+    pub fn delay_before_request(&self) -> Option<Duration> {
+        let low_water_mark = self.low_water_mark?;
+        let latest = self.latest.lock().unwrap();
+        let info = latest.as_ref()?;
+        if info.remaining > low_water_mark {
+            return None;
+        }
+        let until_reset = info.time_until_reset();
+        if until_reset.is_zero() {
+            return None;
+        }
+        Some(until_reset / (info.remaining + 1))
+    }
+
+    /// Waits out a low budget before sending a request.
+    pub async fn throttle(&self) {
+        if let Some(delay) = self.delay_before_request() {
+            info!("Rate limit budget is low, delaying request by {:?}", delay);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// How long to wait before retrying a request that was answered with HTTP 429, if waiting is
+    /// enabled and the reset is close enough.
+    pub fn wait_on_limit(&self, info: Option<&RateLimitInfo>) -> Option<Duration> {
+        let max_wait = self.max_wait_on_limit?;
+        let wait = info?.time_until_reset();
+        if wait <= max_wait {
+            Some(wait)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(remaining: u32, reset: DateTime<Utc>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("ratelimit-limit", "3000".parse().unwrap());
+        headers.insert(
+            "ratelimit-remaining",
+            remaining.to_string().parse().unwrap(),
+        );
+        headers.insert(
+            "ratelimit-reset",
+            reset.timestamp().to_string().parse().unwrap(),
+        );
+        headers.insert("ratelimit-policy", "3000;w=300".parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_parses_rate_limit_headers() {
+        let reset = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let info = RateLimitInfo::from_headers(&headers(2999, reset)).unwrap();
+        assert_eq!(info.limit, 3000);
+        assert_eq!(info.remaining, 2999);
+        assert_eq!(info.reset, reset);
+        assert_eq!(info.window(), Some(Duration::from_secs(300)));
+        assert!(RateLimitInfo::from_headers(&HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn test_throttles_only_below_the_low_water_mark() {
+        let reset = Utc::now() + chrono::Duration::seconds(100);
+        let limiter = RateLimiter::new().with_low_water_mark(10);
+
+        limiter.update(RateLimitInfo::from_headers(&headers(500, reset)));
+        assert_eq!(limiter.delay_before_request(), None);
+
+        limiter.update(RateLimitInfo::from_headers(&headers(4, reset)));
+        let delay = limiter.delay_before_request().unwrap();
+        assert!(delay > Duration::from_secs(15) && delay <= Duration::from_secs(20));
+
+        let tracking_only = RateLimiter::new();
+        tracking_only.update(RateLimitInfo::from_headers(&headers(0, reset)));
+        assert_eq!(tracking_only.delay_before_request(), None);
+    }
+
+    #[test]
+    fn test_waits_on_limit_only_up_to_max_wait() {
+        let reset = Utc::now() + chrono::Duration::seconds(60);
+        let info = RateLimitInfo::from_headers(&headers(0, reset));
+        let patient = RateLimiter::new().with_wait_on_limit(Duration::from_secs(120));
+        let impatient = RateLimiter::new().with_wait_on_limit(Duration::from_secs(10));
+        assert!(patient.wait_on_limit(info.as_ref()).is_some());
+        assert!(impatient.wait_on_limit(info.as_ref()).is_none());
+        assert!(RateLimiter::new().wait_on_limit(info.as_ref()).is_none());
+    }
+}
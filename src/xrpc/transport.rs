@@ -0,0 +1,419 @@
+use super::xrpc_error::{XrpcError, XrpcResult};
+use crate::types::BlueskyConfiguration;
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpMethod {
+    Get,
+    Post,
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: HttpMethod,
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: Option<Vec<u8>>,
+    /// A body that is streamed instead of `body`, for uploads too large to hold in memory.
+    pub stream: Option<StreamBody>,
+}
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = std::io::Result<Vec<u8>>> + Send + Sync>>;
+
+/// A request body that is read from a stream while it is sent. The stream is opened anew for
+/// every attempt, so the request can be retried as long as its source can be read again.
+#[derive(Clone)]
+pub struct StreamBody {
+    open: Arc<dyn Fn() -> XrpcResult<ByteStream> + Send + Sync>,
+}
+
+impl StreamBody {
+    pub fn new<F>(open: F) -> Self
+    where
+        F: Fn() -> XrpcResult<ByteStream> + Send + Sync + 'static,
+    {
+        Self {
+            open: Arc::new(open),
+        }
+    }
+
+    pub fn open(&self) -> XrpcResult<ByteStream> {
+        (self.open)()
+    }
+
+    /// Reads the whole stream into memory.
+    pub async fn collect(&self) -> XrpcResult<Vec<u8>> {
+        let mut stream = self.open()?;
+        let mut body = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            body.extend(chunk.map_err(|err| stream_error(&err))?);
+        }
+        Ok(body)
+    }
+}
+
+/// A stream error that carries an `XrpcError`, e.g. `BlobTooLarge`, so that it comes out of
+/// the transport as that error instead of as a retryable `Transport` error.
+pub(crate) fn stream_error_from(err: XrpcError) -> std::io::Error {
+    std::io::Error::other(err)
+}
+
+/// The `XrpcError` a stream failed with, if it carries one, or else an `Io` error.
+fn stream_error(err: &std::io::Error) -> XrpcError {
+    err.get_ref()
+        .and_then(|inner| inner.downcast_ref::<XrpcError>())
+        .cloned()
+        .unwrap_or_else(|| XrpcError::Io(err.to_string()))
+}
+
+/// Looks for a failed body stream among the causes of a reqwest error.
+fn body_stream_error(err: &reqwest::Error) -> Option<XrpcError> {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(cause) = source {
+        if let Some(err) = cause.downcast_ref::<XrpcError>() {
+            return Some(err.clone());
+        }
+        if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+            if let Some(err) = err
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<XrpcError>())
+            {
+                return Some(err.clone());
+            }
+        }
+        source = cause.source();
+    }
+    None
+}
+
+impl fmt::Debug for StreamBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StreamBody")
+    }
+}
+
+impl HttpRequest {
+    /// The XRPC method this request calls, e.g. `com.atproto.server.createSession`.
+    pub fn nsid(&self) -> Option<&str> {
+        let path = self.url.split('?').next()?;
+        path.split_once("/xrpc/").map(|(_, nsid)| nsid)
+    }
+
+    /// What `MockTransport` matches the request by: the NSID for XRPC calls, otherwise the URL
+    /// without the query.
+    fn route(&self) -> &str {
+        self.nsid()
+            .unwrap_or_else(|| self.url.split('?').next().unwrap_or_default())
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    /// The body parsed as JSON, `Value::Null` if there is none.
+    pub fn json(&self) -> Value {
+        self.body
+            .as_deref()
+            .and_then(|body| serde_json::from_slice(body).ok())
+            .unwrap_or(Value::Null)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn json(status: u16, body: Value) -> Self {
+        Self::new(status, body.to_string()).with_header("content-type", "application/json")
+    }
+
+    /// Panics on invalid header names or values, which is fine for scripted responses.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(
+            HeaderName::from_bytes(name.as_bytes()).expect("invalid header name"),
+            HeaderValue::from_str(value).expect("invalid header value"),
+        );
+        self
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Sends HTTP requests for an `XrpcClient`. The default is `ReqwestTransport`; tests can use
+/// `MockTransport` to script server responses without any network.
+#[async_trait]
+pub trait HttpTransport: Send + Sync {
+    /// Sends the request. Only failures to get any response at all are errors; non-2xx
+    /// responses are returned as they are.
+    async fn send(&self, request: HttpRequest) -> XrpcResult<HttpResponse>;
+
+    /// Sends the request like `send`, but fails rather than read a response body larger than
+    /// `max_size` bytes, for responses from hosts we don't trust. The default checks the body
+    /// after reading it; `ReqwestTransport` stops reading as soon as it is over.
+    async fn send_limited(&self, request: HttpRequest, max_size: u64) -> XrpcResult<HttpResponse> {
+        let response = self.send(request).await?;
+        check_response_size(&response.body, max_size)?;
+        Ok(response)
+    }
+}
+
+fn check_response_size(body: &[u8], max_size: u64) -> XrpcResult<()> {
+    if body.len() as u64 > max_size {
+        return Err(response_too_large(max_size));
+    }
+    Ok(())
+}
+
+fn response_too_large(max_size: u64) -> XrpcError {
+    XrpcError::Deserialization(format!(
+        "the response is larger than the limit of {} bytes",
+        max_size
+    ))
+}
+
+pub struct ReqwestTransport {
+    http: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(config: &BlueskyConfiguration) -> XrpcResult<Self> {
+        let mut default_headers = HeaderMap::new();
+        for (name, value) in &config.default_headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|err| XrpcError::Configuration(err.to_string()))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|err| XrpcError::Configuration(err.to_string()))?;
+            default_headers.insert(name, value);
+        }
+
+        let mut builder = reqwest::Client::builder()
+            .user_agent(config.user_agent.as_str())
+            .default_headers(default_headers);
+        if let Some(timeout) = config.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(connect_timeout) = config.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(proxy) = &config.proxy {
+            let proxy = reqwest::Proxy::all(proxy.as_str())
+                .map_err(|err| XrpcError::Configuration(err.to_string()))?;
+            builder = builder.proxy(proxy);
+        }
+        if !config.xrpc_connection_pooling {
+            builder = builder.pool_max_idle_per_host(0);
+        }
+
+        let http = builder
+            .build()
+            .map_err(|err| XrpcError::Configuration(err.to_string()))?;
+        Ok(Self { http })
+    }
+}
+
+impl ReqwestTransport {
+    async fn send_with_limit(
+        &self,
+        request: HttpRequest,
+        max_size: Option<u64>,
+    ) -> XrpcResult<HttpResponse> {
+        let builder = match request.method {
+            HttpMethod::Get => self.http.get(&request.url),
+            HttpMethod::Post => self.http.post(&request.url),
+        };
+        let builder = match (request.body, request.stream) {
+            (_, Some(stream)) => builder
+                .headers(request.headers)
+                .body(reqwest::Body::wrap_stream(stream.open()?)),
+            (Some(body), None) => builder.headers(request.headers).body(body),
+            (None, None) => builder.headers(request.headers),
+        };
+        let response = builder.send().await.map_err(|err| {
+            body_stream_error(&err).unwrap_or_else(|| XrpcError::Transport(err.to_string()))
+        })?;
+        let status = response.status().as_u16();
+        let headers = response.headers().clone();
+        let Some(max_size) = max_size else {
+            let body = response
+                .bytes()
+                .await
+                .map_err(|err| XrpcError::Transport(err.to_string()))?;
+            return Ok(HttpResponse {
+                status,
+                headers,
+                body: body.to_vec(),
+            });
+        };
+
+        // Refuse what announces itself as too large, and cut off what turns out to be.
+        if response
+            .content_length()
+            .is_some_and(|length| length > max_size)
+        {
+            return Err(response_too_large(max_size));
+        }
+        let mut response = response;
+        let mut body = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|err| XrpcError::Transport(err.to_string()))?
+        {
+            body.extend_from_slice(&chunk);
+            check_response_size(&body, max_size)?;
+        }
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+#[async_trait]
+impl HttpTransport for ReqwestTransport {
+    async fn send(&self, request: HttpRequest) -> XrpcResult<HttpResponse> {
+        self.send_with_limit(request, None).await
+    }
+
+    async fn send_limited(&self, request: HttpRequest, max_size: u64) -> XrpcResult<HttpResponse> {
+        self.send_with_limit(request, Some(max_size)).await
+    }
+}
+
+struct MockRoute {
+    nsid: String,
+    once: VecDeque<XrpcResult<HttpResponse>>,
+    always: Option<HttpResponse>,
+}
+
+/// An in-memory transport that answers XRPC calls with scripted responses and records every
+/// request it receives.
+///
+/// Responses are matched by NSID, or by the URL without the query for requests that are not
+/// XRPC calls, like `https://example.com/.well-known/atproto-did`. One-off responses queued
+/// with `respond_once` are used first, in order, then the response set with `respond`. Calls
+/// without a scripted response get the `MethodNotImplemented` error a PDS would return.
+///
+/// ```
+/// use rustysky::xrpc::{HttpResponse, MockTransport};
+/// use serde_json::json;
+///
+/// let mock = MockTransport::new();
+/// mock.respond_once("app.bsky.actor.getProfile", HttpResponse::new(502, "Bad Gateway"));
+/// mock.respond(
+///     "app.bsky.actor.getProfile",
+///     HttpResponse::json(200, json!({"did": "did:plc:abc", "handle": "abc.test"})),
+/// );
+/// ```
+#[derive(Default)]
+pub struct MockTransport {
+    routes: Mutex<Vec<MockRoute>>,
+    requests: Mutex<Vec<HttpRequest>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_route<F: FnOnce(&mut MockRoute)>(&self, nsid: &str, update: F) {
+        let mut routes = self.routes.lock().unwrap();
+        match routes.iter_mut().find(|route| route.nsid == nsid) {
+            Some(route) => update(route),
+            None => {
+                let mut route = MockRoute {
+                    nsid: nsid.to_string(),
+                    once: VecDeque::new(),
+                    always: None,
+                };
+                update(&mut route);
+                routes.push(route);
+            }
+        }
+    }
+
+    /// Answers every call to `nsid` with `response`, once the one-off responses are used up.
+    pub fn respond(&self, nsid: &str, response: HttpResponse) -> &Self {
+        self.with_route(nsid, |route| route.always = Some(response));
+        self
+    }
+
+    /// Answers the next call to `nsid` with `response`.
+    pub fn respond_once(&self, nsid: &str, response: HttpResponse) -> &Self {
+        self.with_route(nsid, |route| route.once.push_back(Ok(response)));
+        self
+    }
+
+    /// Fails the next call to `nsid` as if the network had failed.
+    pub fn fail_once(&self, nsid: &str, err: XrpcError) -> &Self {
+        self.with_route(nsid, |route| route.once.push_back(Err(err)));
+        self
+    }
+
+    /// Every request received so far, in order.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The requests received so far for `nsid` (or URL), in order.
+    pub fn requests_to(&self, nsid: &str) -> Vec<HttpRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|request| request.route() == nsid)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl HttpTransport for MockTransport {
+    async fn send(&self, mut request: HttpRequest) -> XrpcResult<HttpResponse> {
+        // Streamed bodies are recorded like any other, so that tests can look at them.
+        if let Some(stream) = request.stream.take() {
+            request.body = Some(stream.collect().await?);
+        }
+        let nsid = request.route().to_string();
+        self.requests.lock().unwrap().push(request);
+
+        let mut routes = self.routes.lock().unwrap();
+        let scripted = routes
+            .iter_mut()
+            .find(|route| route.nsid == nsid)
+            .and_then(|route| {
+                route
+                    .once
+                    .pop_front()
+                    .or_else(|| route.always.clone().map(Ok))
+            });
+        scripted.unwrap_or_else(|| {
+            Ok(HttpResponse::json(
+                501,
+                serde_json::json!({
+                    "error": "MethodNotImplemented",
+                    "message": format!("No mock response for {}", nsid),
+                }),
+            ))
+        })
+    }
+}
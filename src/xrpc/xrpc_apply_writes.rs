@@ -0,0 +1,281 @@
+use super::xrpc_repo::{CommitMeta, Record};
+use super::{StrongRef, XrpcError, XrpcResult};
+use crate::dag_cbor::Cid;
+use crate::syntax::{AtUri, Did, Nsid, RecordKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The most writes the reference PDS accepts in one `applyWrites` call.
+pub const MAX_WRITES_PER_CALL: usize = 200;
+
+/// One operation of an `applyWrites` batch.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "$type")]
+pub enum WriteOp {
+    #[serde(rename = "com.atproto.repo.applyWrites#create")]
+    Create {
+        collection: Nsid,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rkey: Option<RecordKey>,
+        value: Value,
+    },
+    #[serde(rename = "com.atproto.repo.applyWrites#update")]
+    Update {
+        collection: Nsid,
+        rkey: RecordKey,
+        value: Value,
+    },
+    #[serde(rename = "com.atproto.repo.applyWrites#delete")]
+    Delete { collection: Nsid, rkey: RecordKey },
+}
+
+/// A batch of creates, updates and deletes in one repo, the input of
+/// `com.atproto.repo.applyWrites`.
+///
+/// Each call commits atomically. `BskyAgent::apply_writes` splits batches larger than
+/// [`MAX_WRITES_PER_CALL`] into several calls, and so several commits.
+///
+/// ```
+/// use rustysky::xrpc::{ApplyWrites, Post};
+///
+/// let did = "did:plc:alice".parse().unwrap();
+/// let mut batch = ApplyWrites::new(&did);
+/// batch
+///     .delete::<Post>("3k4duaz5vfs2b".parse().unwrap())
+///     .delete::<Post>("3k4duaz5vfs2c".parse().unwrap());
+/// assert_eq!(batch.len(), 2);
+/// ```
+#[derive(Debug, Clone, Serialize)]
+pub struct ApplyWrites {
+    pub repo: Did,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validate: Option<bool>,
+    pub writes: Vec<WriteOp>,
+    /// Fails the batch with `InvalidSwap` unless the repo is at this commit. When the batch is
+    /// split, each later call is swapped against the commit of the one before it.
+    #[serde(rename = "swapCommit", skip_serializing_if = "Option::is_none")]
+    pub swap_commit: Option<Cid>,
+}
+
+impl ApplyWrites {
+    pub fn new(repo: &Did) -> Self {
+        Self {
+            repo: repo.clone(),
+            validate: None,
+            writes: Vec::new(),
+            swap_commit: None,
+        }
+    }
+
+    pub fn with_validate(mut self, validate: bool) -> Self {
+        self.validate = Some(validate);
+        self
+    }
+
+    pub fn with_swap_commit(mut self, commit: Cid) -> Self {
+        self.swap_commit = Some(commit);
+        self
+    }
+
+    /// Creates `record` under a key the server picks.
+    pub fn create<R: Record>(&mut self, record: &R) -> XrpcResult<&mut Self> {
+        let value = to_value(record)?;
+        Ok(self.push(WriteOp::Create {
+            collection: R::nsid(),
+            rkey: None,
+            value,
+        }))
+    }
+
+    pub fn create_with_rkey<R: Record>(
+        &mut self,
+        rkey: RecordKey,
+        record: &R,
+    ) -> XrpcResult<&mut Self> {
+        let value = to_value(record)?;
+        Ok(self.push(WriteOp::Create {
+            collection: R::nsid(),
+            rkey: Some(rkey),
+            value,
+        }))
+    }
+
+    /// Replaces the record at `rkey`.
+    pub fn update<R: Record>(&mut self, rkey: RecordKey, record: &R) -> XrpcResult<&mut Self> {
+        let value = to_value(record)?;
+        Ok(self.push(WriteOp::Update {
+            collection: R::nsid(),
+            rkey,
+            value,
+        }))
+    }
+
+    pub fn delete<R: Record>(&mut self, rkey: RecordKey) -> &mut Self {
+        self.push(WriteOp::Delete {
+            collection: R::nsid(),
+            rkey,
+        })
+    }
+
+    pub fn push(&mut self, write: WriteOp) -> &mut Self {
+        self.writes.push(write);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Creates aren't safe to replay, since the server may pick a new key for them, but a
+    /// batch of only updates and deletes is.
+    pub(crate) fn is_idempotent(&self) -> bool {
+        !self
+            .writes
+            .iter()
+            .any(|write| matches!(write, WriteOp::Create { .. }))
+    }
+
+    /// The batch split into batches of at most `size` writes.
+    pub(crate) fn chunks(&self, size: usize) -> Vec<ApplyWrites> {
+        self.writes
+            .chunks(size)
+            .map(|writes| ApplyWrites {
+                repo: self.repo.clone(),
+                validate: self.validate,
+                writes: writes.to_vec(),
+                swap_commit: None,
+            })
+            .collect()
+    }
+}
+
+fn to_value<R: Record>(record: &R) -> XrpcResult<Value> {
+    serde_json::to_value(record).map_err(|err| XrpcError::Serialization(err.to_string()))
+}
+
+/// The result of one write, in the same position as the write in the batch.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "$type")]
+pub enum WriteResult {
+    #[serde(rename = "com.atproto.repo.applyWrites#createResult")]
+    Create {
+        uri: AtUri,
+        cid: Cid,
+        #[serde(rename = "validationStatus")]
+        validation_status: Option<String>,
+    },
+    #[serde(rename = "com.atproto.repo.applyWrites#updateResult")]
+    Update {
+        uri: AtUri,
+        cid: Cid,
+        #[serde(rename = "validationStatus")]
+        validation_status: Option<String>,
+    },
+    #[serde(rename = "com.atproto.repo.applyWrites#deleteResult")]
+    Delete {},
+    /// A result type this crate doesn't know yet. The write was still applied.
+    #[serde(other)]
+    Unknown,
+}
+
+impl WriteResult {
+    /// The written record, unless it was deleted or its result is of an unknown type.
+    pub fn strong_ref(&self) -> Option<StrongRef> {
+        match self {
+            WriteResult::Create { uri, cid, .. } | WriteResult::Update { uri, cid, .. } => {
+                Some(StrongRef {
+                    uri: uri.clone(),
+                    cid: cid.clone(),
+                })
+            }
+            WriteResult::Delete {} | WriteResult::Unknown => None,
+        }
+    }
+}
+
+/// The output of one `com.atproto.repo.applyWrites` call.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApplyWritesResponse {
+    pub commit: Option<CommitMeta>,
+    /// Optional in the lexicon, and left out by some PDSes.
+    pub results: Option<Vec<WriteResult>>,
+}
+
+/// The outcome of a whole batch: one commit per call, and one result per write.
+#[derive(Debug, Clone, Default)]
+pub struct AppliedWrites {
+    pub commits: Vec<CommitMeta>,
+    /// `results[i]` is the result of `writes[i]`, or `None` if the server committed the write
+    /// without reporting its result.
+    pub results: Vec<Option<WriteResult>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xrpc::Post;
+    use serde_json::json;
+
+    #[test]
+    fn test_serializes_writes_as_a_union() {
+        let did = "did:plc:alice".parse().unwrap();
+        let post = Post::new("hello", "did:plc:alice", None, None, None, None).unwrap();
+        let mut batch = ApplyWrites::new(&did);
+        batch
+            .create(&post)
+            .unwrap()
+            .delete::<Post>("3k2a".parse().unwrap());
+
+        let json = serde_json::to_value(&batch).unwrap();
+        assert_eq!(json["repo"], "did:plc:alice");
+        assert_eq!(
+            json["writes"][0]["$type"],
+            "com.atproto.repo.applyWrites#create"
+        );
+        assert_eq!(json["writes"][0]["collection"], "app.bsky.feed.post");
+        assert_eq!(json["writes"][0]["value"]["text"], "hello");
+        assert!(json["writes"][0].get("rkey").is_none());
+        assert_eq!(
+            json["writes"][1],
+            json!({
+                "$type": "com.atproto.repo.applyWrites#delete",
+                "collection": "app.bsky.feed.post",
+                "rkey": "3k2a"
+            })
+        );
+        assert!(!batch.is_idempotent());
+    }
+
+    #[test]
+    fn test_unknown_results_can_be_read() {
+        let response: ApplyWritesResponse = serde_json::from_value(json!({
+            "results": [
+                { "$type": "com.atproto.repo.applyWrites#deleteResult" },
+                { "$type": "com.atproto.repo.applyWrites#moveResult", "from": "3k2a" }
+            ]
+        }))
+        .unwrap();
+        let results = response.results.unwrap();
+        assert_eq!(results, [WriteResult::Delete {}, WriteResult::Unknown]);
+        assert_eq!(results[1].strong_ref(), None);
+    }
+
+    #[test]
+    fn test_chunks() {
+        let did = "did:plc:alice".parse().unwrap();
+        let mut batch = ApplyWrites::new(&did).with_validate(false);
+        for i in 0..450 {
+            batch.delete::<Post>(format!("rkey{}", i).parse().unwrap());
+        }
+        let chunks = batch.chunks(MAX_WRITES_PER_CALL);
+        let sizes: Vec<usize> = chunks.iter().map(ApplyWrites::len).collect();
+        assert_eq!(sizes, [200, 200, 50]);
+        assert_eq!(chunks[2].validate, Some(false));
+        assert_eq!(chunks[2].writes[0], batch.writes[400]);
+        assert!(batch.is_idempotent());
+    }
+}
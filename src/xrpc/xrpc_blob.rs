@@ -0,0 +1,436 @@
+use super::transport::{stream_error_from, ByteStream, StreamBody};
+use super::{XrpcError, XrpcResult};
+use crate::dag_cbor::Cid;
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The reference PDS's default limit on the size of one blob, 5 MiB. Lexicons set lower limits
+/// for some uses, e.g. 1 MB for the images of a post.
+pub const DEFAULT_MAX_BLOB_SIZE: u64 = 5 * 1024 * 1024;
+
+const FALLBACK_MIME_TYPE: &str = "application/octet-stream";
+/// Enough of the start of a file to recognize all formats `sniff_mime_type` knows.
+const SNIFF_LEN: usize = 16;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A reference to an uploaded blob, as records embed it:
+/// `{"$type": "blob", "ref": {"$link": cid}, "mimeType": ..., "size": ...}`.
+///
+/// ```
+/// use rustysky::dag_cbor::Cid;
+/// use rustysky::xrpc::BlobRef;
+///
+/// let blob = BlobRef::new(Cid::for_raw(b"GIF89a"), "image/gif", 6);
+/// let json = serde_json::to_value(&blob).unwrap();
+/// assert_eq!(json["$type"], "blob");
+/// assert_eq!(json["ref"]["$link"], blob.cid.to_string());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "RawBlobRef", try_from = "RawBlobRef")]
+pub struct BlobRef {
+    /// The CID of the blob's bytes, with the raw codec.
+    pub cid: Cid,
+    pub mime_type: String,
+    pub size: u64,
+}
+
+impl BlobRef {
+    pub fn new(cid: Cid, mime_type: &str, size: u64) -> Self {
+        Self {
+            cid,
+            mime_type: mime_type.to_string(),
+            size,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawBlobRef {
+    #[serde(rename = "$type")]
+    blob_type: String,
+    #[serde(rename = "ref")]
+    link: Link,
+    #[serde(rename = "mimeType")]
+    mime_type: String,
+    size: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Link {
+    #[serde(rename = "$link")]
+    cid: Cid,
+}
+
+impl From<BlobRef> for RawBlobRef {
+    fn from(blob: BlobRef) -> Self {
+        Self {
+            blob_type: "blob".to_string(),
+            link: Link { cid: blob.cid },
+            mime_type: blob.mime_type,
+            size: blob.size,
+        }
+    }
+}
+
+impl TryFrom<RawBlobRef> for BlobRef {
+    type Error = String;
+
+    fn try_from(raw: RawBlobRef) -> Result<Self, Self::Error> {
+        if raw.blob_type != "blob" {
+            return Err(format!("expected a blob, got $type {}", raw.blob_type));
+        }
+        Ok(Self {
+            cid: raw.link.cid,
+            mime_type: raw.mime_type,
+            size: raw.size,
+        })
+    }
+}
+
+/// The output of `com.atproto.repo.uploadBlob`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadBlobResponse {
+    pub blob: BlobRef,
+}
+
+/// A blob to upload with `com.atproto.repo.uploadBlob`.
+///
+/// Files and readers are streamed, so they are never held in memory as a whole. The MIME type
+/// is sniffed from the first bytes, falling back to the file extension; set it with
+/// `with_mime_type` when neither gets it right. Blobs larger than the size limit are refused
+/// before anything is sent, or, if their size isn't known up front, as soon as they outgrow it.
+///
+/// An upload can be sent again, e.g. to retry it, except one read from a reader, since the
+/// reader is used up by the first attempt. Prefer files where that matters.
+///
+/// ```
+/// use rustysky::xrpc::BlobUpload;
+///
+/// let upload = BlobUpload::from_bytes(b"\x89PNG\r\n\x1a\n...".to_vec());
+/// assert_eq!(upload.mime_type(), "image/png");
+/// assert_eq!(upload.size(), Some(11));
+/// ```
+#[derive(Debug, Clone)]
+pub struct BlobUpload {
+    source: Source,
+    mime_type: String,
+    size: Option<u64>,
+    max_size: u64,
+}
+
+#[derive(Clone)]
+enum Source {
+    Bytes(Arc<Vec<u8>>),
+    File(PathBuf),
+    Reader(Arc<Mutex<Option<ByteStream>>>),
+}
+
+impl std::fmt::Debug for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            Source::File(path) => write!(f, "File({})", path.display()),
+            Source::Reader(_) => f.write_str("Reader"),
+        }
+    }
+}
+
+impl BlobUpload {
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        let mime_type = sniff_mime_type(&bytes).unwrap_or(FALLBACK_MIME_TYPE);
+        Self {
+            mime_type: mime_type.to_string(),
+            size: Some(bytes.len() as u64),
+            source: Source::Bytes(Arc::new(bytes)),
+            max_size: DEFAULT_MAX_BLOB_SIZE,
+        }
+    }
+
+    /// Uploads the file at `path`. Its size and type are read now, its contents while they are
+    /// sent.
+    pub async fn from_file(path: impl AsRef<Path>) -> XrpcResult<Self> {
+        let path = path.as_ref();
+        let unreadable = |err: io::Error| read_error(&path.display().to_string(), err);
+        let mut file = tokio::fs::File::open(path).await.map_err(unreadable)?;
+        let size = file.metadata().await.map_err(unreadable)?.len();
+        let head = read_head(&mut file).await.map_err(unreadable)?;
+        let mime_type = sniff_mime_type(&head)
+            .or_else(|| mime_type_from_extension(path))
+            .unwrap_or(FALLBACK_MIME_TYPE);
+        Ok(Self {
+            source: Source::File(path.to_path_buf()),
+            mime_type: mime_type.to_string(),
+            size: Some(size),
+            max_size: DEFAULT_MAX_BLOB_SIZE,
+        })
+    }
+
+    /// Uploads whatever `reader` yields. The first few bytes are read now to sniff the type.
+    pub async fn from_reader<R>(mut reader: R) -> XrpcResult<Self>
+    where
+        R: AsyncRead + Unpin + Send + Sync + 'static,
+    {
+        let head = read_head(&mut reader)
+            .await
+            .map_err(|err| read_error("the reader", err))?;
+        let mime_type = sniff_mime_type(&head).unwrap_or(FALLBACK_MIME_TYPE);
+        let stream: ByteStream = Box::pin(stream::iter([Ok(head)]).chain(read_chunks(reader)));
+        Ok(Self {
+            source: Source::Reader(Arc::new(Mutex::new(Some(stream)))),
+            mime_type: mime_type.to_string(),
+            size: None,
+            max_size: DEFAULT_MAX_BLOB_SIZE,
+        })
+    }
+
+    pub fn with_mime_type(mut self, mime_type: &str) -> Self {
+        self.mime_type = mime_type.to_string();
+        self
+    }
+
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    pub fn mime_type(&self) -> &str {
+        &self.mime_type
+    }
+
+    /// The size in bytes, unless the blob comes from a reader.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Fails with `BlobTooLarge` if the blob is known to be over the limit.
+    pub fn check_size(&self) -> XrpcResult<()> {
+        match self.size {
+            Some(size) if size > self.max_size => Err(XrpcError::BlobTooLarge {
+                size: Some(size),
+                max_size: self.max_size,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Uploading the same bytes again gives the same blob, so an upload can be retried as long
+    /// as its source can be read again.
+    pub(crate) fn is_replayable(&self) -> bool {
+        !matches!(self.source, Source::Reader(_))
+    }
+
+    /// The body of the upload, opened anew for every attempt.
+    pub(crate) fn body(&self) -> StreamBody {
+        let source = self.source.clone();
+        let max_size = self.max_size;
+        StreamBody::new(move || {
+            let (stream, name): (ByteStream, String) = match &source {
+                Source::Bytes(bytes) => (
+                    Box::pin(stream::iter([Ok(bytes.to_vec())])),
+                    "the blob".to_string(),
+                ),
+                Source::File(path) => {
+                    // Opened once the body is polled, so not on the runtime's threads.
+                    let open = tokio::fs::File::open(path.clone());
+                    let stream = stream::once(open).map_ok(read_chunks).try_flatten();
+                    (Box::pin(stream), path.display().to_string())
+                }
+                Source::Reader(reader) => {
+                    let stream = reader.lock().unwrap().take().ok_or_else(|| {
+                        XrpcError::Io(
+                            "a blob read from a reader can only be uploaded once".to_string(),
+                        )
+                    })?;
+                    (stream, "the reader".to_string())
+                }
+            };
+            // Read errors are not worth retrying, so they fail the upload as `Io`.
+            let stream = stream.map_err(move |err| match err.get_ref() {
+                Some(inner) if inner.is::<XrpcError>() => err,
+                _ => stream_error_from(read_error(&name, err)),
+            });
+            Ok(limit_size(Box::pin(stream), max_size))
+        })
+    }
+}
+
+fn read_error(source: &str, err: io::Error) -> XrpcError {
+    XrpcError::Io(format!("could not read {}: {}", source, err))
+}
+
+/// Reads up to `SNIFF_LEN` bytes, fewer only at the end of the input.
+async fn read_head<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut head = vec![0; SNIFF_LEN];
+    let mut filled = 0;
+    while filled < SNIFF_LEN {
+        match reader.read(&mut head[filled..]).await? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    head.truncate(filled);
+    Ok(head)
+}
+
+fn read_chunks<R>(reader: R) -> impl Stream<Item = io::Result<Vec<u8>>> + Send + Sync
+where
+    R: AsyncRead + Unpin + Send + Sync + 'static,
+{
+    stream::try_unfold(reader, |mut reader| async move {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        chunk.truncate(read);
+        Ok(Some((chunk, reader)))
+    })
+}
+
+/// Fails the stream once it yields more than `max_size` bytes, so that a blob of unknown size is
+/// cut off instead of sent in full only to be rejected.
+fn limit_size(stream: ByteStream, max_size: u64) -> ByteStream {
+    let mut total = 0u64;
+    Box::pin(stream.and_then(move |chunk| {
+        total += chunk.len() as u64;
+        let result = if total > max_size {
+            Err(stream_error_from(XrpcError::BlobTooLarge {
+                size: None,
+                max_size,
+            }))
+        } else {
+            Ok(chunk)
+        };
+        futures::future::ready(result)
+    }))
+}
+
+/// Recognizes the media formats Bluesky accepts by their magic numbers.
+fn sniff_mime_type(head: &[u8]) -> Option<&'static str> {
+    let mime_type = match head {
+        [0xff, 0xd8, 0xff, ..] => "image/jpeg",
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => "image/png",
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => "image/gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        [_, _, _, _, b'f', b't', b'y', b'p', brand @ ..] => match brand.get(..4)? {
+            b"avif" | b"avis" => "image/avif",
+            b"heic" | b"heix" | b"mif1" => "image/heic",
+            b"qt  " => "video/quicktime",
+            _ => "video/mp4",
+        },
+        [0x1a, 0x45, 0xdf, 0xa3, ..] => "video/webm",
+        _ => return None,
+    };
+    Some(mime_type)
+}
+
+fn mime_type_from_extension(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let mime_type = match extension.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "heic" | "heif" => "image/heic",
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "webm" => "video/webm",
+        _ => return None,
+    };
+    Some(mime_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dag_cbor;
+    use serde_json::json;
+
+    #[test]
+    fn test_sniff_mime_type() {
+        for (head, expected) in [
+            (&b"\xff\xd8\xff\xe0\x00\x10JFIF"[..], Some("image/jpeg")),
+            (b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR", Some("image/png")),
+            (b"GIF87a", Some("image/gif")),
+            (b"RIFF\x24\x00\x00\x00WEBPVP8 ", Some("image/webp")),
+            (b"\x00\x00\x00\x20ftypisom", Some("video/mp4")),
+            (b"\x00\x00\x00\x18ftypheic", Some("image/heic")),
+            (b"\x00\x00\x00\x1cftypavif", Some("image/avif")),
+            (b"\x00\x00\x00\x14ftypqt  ", Some("video/quicktime")),
+            (b"\x1a\x45\xdf\xa3", Some("video/webm")),
+            (b"RIFF\x24\x00\x00\x00WAVE", None),
+            (b"hello", None),
+            (b"", None),
+        ] {
+            assert_eq!(sniff_mime_type(head), expected, "{:?}", head);
+        }
+    }
+
+    #[test]
+    fn test_mime_type_from_extension() {
+        assert_eq!(
+            mime_type_from_extension(Path::new("cat.JPG")),
+            Some("image/jpeg")
+        );
+        assert_eq!(
+            mime_type_from_extension(Path::new("dir.png/clip.mov")),
+            Some("video/quicktime")
+        );
+        assert_eq!(mime_type_from_extension(Path::new("notes.txt")), None);
+        assert_eq!(mime_type_from_extension(Path::new("README")), None);
+    }
+
+    #[test]
+    fn test_blob_ref_data_model() {
+        let cid = Cid::for_raw(b"GIF89a");
+        let blob = BlobRef::new(cid.clone(), "image/gif", 6);
+        let json = serde_json::to_value(&blob).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "$type": "blob",
+                "ref": { "$link": cid.to_string() },
+                "mimeType": "image/gif",
+                "size": 6
+            })
+        );
+        assert_eq!(serde_json::from_value::<BlobRef>(json).unwrap(), blob);
+
+        // In DAG-CBOR the ref is a CID link rather than a string.
+        let cbor = dag_cbor::to_dag_cbor(&blob).unwrap();
+        assert!(cbor.windows(2).any(|bytes| bytes == [0xd8, 42]));
+        assert_eq!(dag_cbor::from_dag_cbor::<BlobRef>(&cbor).unwrap(), blob);
+
+        let not_a_blob = json!({
+            "$type": "image",
+            "ref": { "$link": cid.to_string() },
+            "mimeType": "image/gif",
+            "size": 6
+        });
+        assert!(serde_json::from_value::<BlobRef>(not_a_blob).is_err());
+    }
+
+    #[test]
+    fn test_check_size() {
+        let upload = BlobUpload::from_bytes(vec![0; 100]);
+        assert_eq!(upload.mime_type(), FALLBACK_MIME_TYPE);
+        assert!(upload.check_size().is_ok());
+        let upload = upload.with_max_size(99);
+        assert!(matches!(
+            upload.check_size(),
+            Err(XrpcError::BlobTooLarge {
+                size: Some(100),
+                max_size: 99
+            })
+        ));
+    }
+}
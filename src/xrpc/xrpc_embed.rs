@@ -0,0 +1,453 @@
+use super::xrpc_blob::{BlobRef, BlobUpload};
+use super::{StrongRef, XrpcError, XrpcResult};
+use crate::bsky_agent::BskyAgent;
+use serde::{Deserialize, Serialize};
+
+/// The most images one post can carry.
+pub const MAX_IMAGES: usize = 4;
+/// The size limit of each image of `app.bsky.embed.images`, 1 MB.
+pub const MAX_IMAGE_SIZE: u64 = 1_000_000;
+/// The size limit of the video of `app.bsky.embed.video`, 100 MB.
+pub const MAX_VIDEO_SIZE: u64 = 100_000_000;
+
+/// The media or record a post embeds, the `embed` union of `app.bsky.feed.post`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "$type")]
+pub enum Embed {
+    #[serde(rename = "app.bsky.embed.images")]
+    Images(ImagesEmbed),
+    #[serde(rename = "app.bsky.embed.video")]
+    Video(VideoEmbed),
+    #[serde(rename = "app.bsky.embed.external")]
+    External(ExternalEmbed),
+    #[serde(rename = "app.bsky.embed.record")]
+    Record(RecordEmbed),
+    #[serde(rename = "app.bsky.embed.recordWithMedia")]
+    RecordWithMedia(RecordWithMediaEmbed),
+    /// An embed type this crate doesn't know yet. It is kept so that such posts can be read,
+    /// but can't be written back.
+    #[serde(other, skip_serializing)]
+    Unsupported,
+}
+
+impl Embed {
+    /// Quotes a post, or shows a feed generator, list or starter pack.
+    ///
+    /// ```
+    /// use rustysky::xrpc::{Embed, StrongRef};
+    ///
+    /// let quoted = StrongRef {
+    ///     uri: "at://did:plc:alice/app.bsky.feed.post/3k4duaz5vfs2b".parse().unwrap(),
+    ///     cid: "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm".parse().unwrap(),
+    /// };
+    /// let json = serde_json::to_value(Embed::quote(quoted)).unwrap();
+    /// assert_eq!(json["$type"], "app.bsky.embed.record");
+    /// assert_eq!(json["record"]["uri"], "at://did:plc:alice/app.bsky.feed.post/3k4duaz5vfs2b");
+    /// ```
+    pub fn quote(record: StrongRef) -> Self {
+        Embed::Record(RecordEmbed { record })
+    }
+
+    /// Quotes a record together with images, a video or a link card.
+    pub fn quote_with_media(record: StrongRef, media: Embed) -> XrpcResult<Self> {
+        Ok(Embed::RecordWithMedia(RecordWithMediaEmbed {
+            record: RecordEmbed { record },
+            media: Media::try_from(media)?,
+        }))
+    }
+}
+
+/// `app.bsky.embed.images`: up to [`MAX_IMAGES`] images, shown in order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImagesEmbed {
+    pub images: Vec<Image>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Image {
+    pub image: BlobRef,
+    /// The alt text, for people who can't see the image. May be empty, but shouldn't be.
+    pub alt: String,
+    /// Lets clients lay the image out before it has loaded.
+    #[serde(rename = "aspectRatio", skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<AspectRatio>,
+}
+
+/// The width and height of an image, or just their ratio; both must be at least 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AspectRatio {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AspectRatio {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+}
+
+/// `app.bsky.embed.external`: a card for a link, see `link_card::LinkCardBuilder`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExternalEmbed {
+    pub external: External,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct External {
+    pub uri: String,
+    pub title: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumb: Option<BlobRef>,
+}
+
+/// `app.bsky.embed.video`: a video blob of at most [`MAX_VIDEO_SIZE`] bytes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VideoEmbed {
+    pub video: BlobRef,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captions: Option<Vec<Caption>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alt: Option<String>,
+    #[serde(rename = "aspectRatio", skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<AspectRatio>,
+}
+
+impl VideoEmbed {
+    pub fn new(video: BlobRef) -> Self {
+        Self {
+            video,
+            captions: None,
+            alt: None,
+            aspect_ratio: None,
+        }
+    }
+
+    pub fn with_alt(mut self, alt: &str) -> Self {
+        self.alt = Some(alt.to_string());
+        self
+    }
+
+    pub fn with_aspect_ratio(mut self, aspect_ratio: AspectRatio) -> Self {
+        self.aspect_ratio = Some(aspect_ratio);
+        self
+    }
+}
+
+/// A WebVTT captions file of a video.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Caption {
+    /// The language, e.g. `en`.
+    pub lang: String,
+    pub file: BlobRef,
+}
+
+/// `app.bsky.embed.record`: a quoted post, or a feed generator, list or starter pack.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordEmbed {
+    pub record: StrongRef,
+}
+
+/// `app.bsky.embed.recordWithMedia`: a quote together with images, a video or a link card.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordWithMediaEmbed {
+    pub record: RecordEmbed,
+    pub media: Media,
+}
+
+/// The embeds that can go along with a quote.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "$type")]
+pub enum Media {
+    #[serde(rename = "app.bsky.embed.images")]
+    Images(ImagesEmbed),
+    #[serde(rename = "app.bsky.embed.video")]
+    Video(VideoEmbed),
+    #[serde(rename = "app.bsky.embed.external")]
+    External(ExternalEmbed),
+    #[serde(other, skip_serializing)]
+    Unsupported,
+}
+
+impl TryFrom<Embed> for Media {
+    type Error = XrpcError;
+
+    fn try_from(embed: Embed) -> Result<Self, Self::Error> {
+        match embed {
+            Embed::Images(images) => Ok(Media::Images(images)),
+            Embed::Video(video) => Ok(Media::Video(video)),
+            Embed::External(external) => Ok(Media::External(external)),
+            _ => Err(XrpcError::InvalidRecord(
+                "a quote can only go along with images, a video or a link card".to_string(),
+            )),
+        }
+    }
+}
+
+impl From<Media> for Embed {
+    fn from(media: Media) -> Self {
+        match media {
+            Media::Images(images) => Embed::Images(images),
+            Media::Video(video) => Embed::Video(video),
+            Media::External(external) => Embed::External(external),
+            Media::Unsupported => Embed::Unsupported,
+        }
+    }
+}
+
+/// Collects the images of a post, then uploads them and assembles the `app.bsky.embed.images`
+/// embed.
+///
+/// ```no_run
+/// # async fn example(agent: rustysky::bsky_agent::BskyAgent) -> anyhow::Result<()> {
+/// use rustysky::xrpc::{AspectRatio, BlobUpload, ImagesEmbedBuilder, Post};
+///
+/// let embed = ImagesEmbedBuilder::new()
+///     .image(BlobUpload::from_file("cat.jpg").await?, "A cat on a keyboard")
+///     .image_with_aspect_ratio(
+///         BlobUpload::from_file("dog.png").await?,
+///         "A dog",
+///         AspectRatio::new(4, 3),
+///     )
+///     .upload(&agent)
+///     .await?;
+/// let did = agent.session().unwrap().did;
+/// let post = Post::new("Pets", &did, None, None, None, None)?.with_embed(embed);
+/// agent.create_post(post).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ImagesEmbedBuilder {
+    images: Vec<PendingImage>,
+}
+
+#[derive(Debug, Clone)]
+struct PendingImage {
+    upload: BlobUpload,
+    alt: String,
+    aspect_ratio: Option<AspectRatio>,
+}
+
+impl ImagesEmbedBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn image(self, upload: BlobUpload, alt: &str) -> Self {
+        self.push(upload, alt, None)
+    }
+
+    pub fn image_with_aspect_ratio(
+        self,
+        upload: BlobUpload,
+        alt: &str,
+        aspect_ratio: AspectRatio,
+    ) -> Self {
+        self.push(upload, alt, Some(aspect_ratio))
+    }
+
+    fn push(mut self, upload: BlobUpload, alt: &str, aspect_ratio: Option<AspectRatio>) -> Self {
+        let max_size = upload.max_size().min(MAX_IMAGE_SIZE);
+        self.images.push(PendingImage {
+            upload: upload.with_max_size(max_size),
+            alt: alt.to_string(),
+            aspect_ratio,
+        });
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    /// Checks the images against the lexicon, so that nothing is uploaded for a post that
+    /// can't be created.
+    pub fn validate(&self) -> XrpcResult<()> {
+        if self.images.is_empty() || self.images.len() > MAX_IMAGES {
+            return Err(XrpcError::InvalidRecord(format!(
+                "a post has 1 to {} images, not {}",
+                MAX_IMAGES,
+                self.images.len()
+            )));
+        }
+        for image in &self.images {
+            if !image.upload.mime_type().starts_with("image/") {
+                return Err(XrpcError::InvalidRecord(format!(
+                    "{} is not an image type",
+                    image.upload.mime_type()
+                )));
+            }
+            if let Some(AspectRatio { width, height }) = image.aspect_ratio {
+                if width == 0 || height == 0 {
+                    return Err(XrpcError::InvalidRecord(format!(
+                        "the aspect ratio {}:{} is empty",
+                        width, height
+                    )));
+                }
+            }
+            image.upload.check_size()?;
+        }
+        Ok(())
+    }
+
+    /// Uploads the images one after another and returns the embed for the post.
+    pub async fn upload(&self, agent: &BskyAgent) -> XrpcResult<Embed> {
+        self.validate()?;
+        let mut images = Vec::with_capacity(self.images.len());
+        for pending in &self.images {
+            images.push(Image {
+                image: agent.upload_blob(&pending.upload).await?,
+                alt: pending.alt.clone(),
+                aspect_ratio: pending.aspect_ratio,
+            });
+        }
+        Ok(Embed::Images(ImagesEmbed { images }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dag_cbor::Cid;
+    use serde_json::json;
+
+    #[test]
+    fn test_images_embed_data_model() {
+        let blob = BlobRef::new(Cid::for_raw(b"GIF89a"), "image/gif", 6);
+        let embed = Embed::Images(ImagesEmbed {
+            images: vec![Image {
+                image: blob.clone(),
+                alt: "A gif".to_string(),
+                aspect_ratio: Some(AspectRatio::new(16, 9)),
+            }],
+        });
+        let json = serde_json::to_value(&embed).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "$type": "app.bsky.embed.images",
+                "images": [{
+                    "image": serde_json::to_value(&blob).unwrap(),
+                    "alt": "A gif",
+                    "aspectRatio": { "width": 16, "height": 9 }
+                }]
+            })
+        );
+        assert_eq!(serde_json::from_value::<Embed>(json).unwrap(), embed);
+    }
+
+    #[test]
+    fn test_external_embed_data_model() {
+        let json = json!({
+            "$type": "app.bsky.embed.external",
+            "external": {
+                "uri": "https://atproto.com/",
+                "title": "AT Protocol",
+                "description": ""
+            }
+        });
+        let embed: Embed = serde_json::from_value(json.clone()).unwrap();
+        let Embed::External(ExternalEmbed { external }) = &embed else {
+            panic!("not an external embed: {:?}", embed);
+        };
+        assert_eq!(external.title, "AT Protocol");
+        assert_eq!(external.thumb, None);
+        assert_eq!(serde_json::to_value(&embed).unwrap(), json);
+    }
+
+    fn strong_ref() -> StrongRef {
+        StrongRef {
+            uri: "at://did:plc:alice/app.bsky.feed.post/3k4duaz5vfs2b"
+                .parse()
+                .unwrap(),
+            cid: Cid::for_raw(b"post"),
+        }
+    }
+
+    #[test]
+    fn test_record_with_media_data_model() {
+        let blob = BlobRef::new(Cid::for_raw(b"video"), "video/mp4", 5);
+        let video = Embed::Video(VideoEmbed::new(blob.clone()).with_alt("A clip"));
+        let embed = Embed::quote_with_media(strong_ref(), video).unwrap();
+        let json = serde_json::to_value(&embed).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "$type": "app.bsky.embed.recordWithMedia",
+                "record": {
+                    "record": {
+                        "uri": "at://did:plc:alice/app.bsky.feed.post/3k4duaz5vfs2b",
+                        "cid": Cid::for_raw(b"post").to_string()
+                    }
+                },
+                "media": {
+                    "$type": "app.bsky.embed.video",
+                    "video": serde_json::to_value(&blob).unwrap(),
+                    "alt": "A clip"
+                }
+            })
+        );
+        assert_eq!(serde_json::from_value::<Embed>(json).unwrap(), embed);
+    }
+
+    #[test]
+    fn test_only_media_goes_with_a_quote() {
+        let quote = Embed::quote(strong_ref());
+        assert!(matches!(
+            Embed::quote_with_media(strong_ref(), quote),
+            Err(XrpcError::InvalidRecord(_))
+        ));
+        let images = Embed::Images(ImagesEmbed { images: vec![] });
+        let Embed::RecordWithMedia(embed) =
+            Embed::quote_with_media(strong_ref(), images.clone()).unwrap()
+        else {
+            panic!("not a record with media");
+        };
+        assert_eq!(Embed::from(embed.media), images);
+    }
+
+    #[test]
+    fn test_unknown_embeds_can_be_read() {
+        let unknown = json!({ "$type": "app.bsky.embed.hologram", "alt": "" });
+        let embed: Embed = serde_json::from_value(unknown).unwrap();
+        assert_eq!(embed, Embed::Unsupported);
+        assert!(serde_json::to_value(&embed).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let png = || BlobUpload::from_bytes(b"\x89PNG\r\n\x1a\n".to_vec());
+        assert!(ImagesEmbedBuilder::new().validate().is_err());
+        assert!(ImagesEmbedBuilder::new()
+            .image(png(), "")
+            .validate()
+            .is_ok());
+
+        let too_many = (0..5).fold(ImagesEmbedBuilder::new(), |builder, _| {
+            builder.image(png(), "")
+        });
+        assert!(matches!(
+            too_many.validate(),
+            Err(XrpcError::InvalidRecord(_))
+        ));
+
+        let text = ImagesEmbedBuilder::new().image(BlobUpload::from_bytes(b"hi".to_vec()), "");
+        assert!(matches!(text.validate(), Err(XrpcError::InvalidRecord(_))));
+
+        let flat =
+            ImagesEmbedBuilder::new().image_with_aspect_ratio(png(), "", AspectRatio::new(4, 0));
+        assert!(matches!(flat.validate(), Err(XrpcError::InvalidRecord(_))));
+
+        let large = BlobUpload::from_bytes([&b"\x89PNG\r\n\x1a\n"[..], &[0; 1_000_000]].concat());
+        let large = ImagesEmbedBuilder::new().image(large, "");
+        assert!(matches!(
+            large.validate(),
+            Err(XrpcError::BlobTooLarge { .. })
+        ));
+    }
+}
@@ -0,0 +1,301 @@
+use super::rate_limit::RateLimitInfo;
+use crate::syntax::SyntaxError;
+use serde::Deserialize;
+use std::fmt;
+
+pub type XrpcResult<T> = Result<T, XrpcError>;
+
+/// The error envelope returned by XRPC endpoints, e.g.
+/// `{"error": "ExpiredToken", "message": "Token has expired"}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct XrpcErrorBody {
+    pub error: Option<String>,
+    /// OAuth servers send the message as `error_description`.
+    #[serde(alias = "error_description")]
+    pub message: Option<String>,
+}
+
+/// A non-2xx XRPC response, together with whatever the server told us about it.
+#[derive(Debug, Clone)]
+pub struct XrpcErrorResponse {
+    pub status: u16,
+    pub error: Option<String>,
+    pub message: Option<String>,
+    pub rate_limit: Option<RateLimitInfo>,
+}
+
+impl XrpcErrorResponse {
+    /// Builds the response from the HTTP status and the raw response body. A body that is not a
+    /// lexicon error envelope is kept as the message, so nothing the server sent gets lost.
+    pub fn from_body(status: u16, body: &str) -> Self {
+        match serde_json::from_str::<XrpcErrorBody>(body) {
+            Ok(envelope) => Self {
+                status,
+                error: envelope.error,
+                message: envelope.message,
+                rate_limit: None,
+            },
+            Err(_) => Self {
+                status,
+                error: None,
+                message: if body.trim().is_empty() {
+                    None
+                } else {
+                    Some(body.trim().to_string())
+                },
+                rate_limit: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthErrorKind {
+    ExpiredToken,
+    InvalidToken,
+    AuthFactorTokenRequired,
+    AccountTakedown,
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub enum XrpcError {
+    /// The client could not be built from its configuration, e.g. because of an invalid proxy URL.
+    Configuration(String),
+    /// The request could not be sent or the response could not be read.
+    Transport(String),
+    /// The request body could not be serialized.
+    Serialization(String),
+    /// The response body did not match the expected type.
+    Deserialization(String),
+    /// An authenticated call was made without a session.
+    NoSession,
+    /// A handle or DID could not be resolved, or did not check out.
+    Identity(String),
+    /// An identifier did not match its atproto syntax, so the request was not sent.
+    Syntax(SyntaxError),
+    /// A local file or reader, e.g. a blob to upload, could not be read. Retrying won't help.
+    Io(String),
+    /// A blob is larger than the upload limit. `size` is unknown for blobs that are streamed
+    /// from a reader, which are cut off once they outgrow the limit.
+    BlobTooLarge { size: Option<u64>, max_size: u64 },
+    /// A record broke a rule of its lexicon, e.g. a post with too many images, so it was not
+    /// sent.
+    InvalidRecord(String),
+    /// An image could not be decoded, or not made to fit, by `media::process_image`.
+    Media(String),
+    /// The server rejected our credentials.
+    Auth {
+        kind: AuthErrorKind,
+        response: XrpcErrorResponse,
+    },
+    /// HTTP 429.
+    RateLimited(XrpcErrorResponse),
+    /// HTTP 5xx.
+    Server(XrpcErrorResponse),
+    /// Any other non-2xx response, e.g. `InvalidRequest` or `RecordNotFound`.
+    Request(XrpcErrorResponse),
+}
+
+impl XrpcError {
+    /// Classifies a non-2xx response by its status code and lexicon error name.
+    pub fn from_response(status: u16, body: &str) -> Self {
+        let response = XrpcErrorResponse::from_body(status, body);
+        let auth_kind = match response.error.as_deref() {
+            Some("ExpiredToken") => Some(AuthErrorKind::ExpiredToken),
+            Some("InvalidToken") => Some(AuthErrorKind::InvalidToken),
+            Some("AuthFactorTokenRequired") => Some(AuthErrorKind::AuthFactorTokenRequired),
+            Some("AccountTakedown") => Some(AuthErrorKind::AccountTakedown),
+            _ if status == 401 => Some(AuthErrorKind::Other),
+            _ => None,
+        };
+
+        if let Some(kind) = auth_kind {
+            return XrpcError::Auth { kind, response };
+        }
+        match status {
+            429 => XrpcError::RateLimited(response),
+            500..=599 => XrpcError::Server(response),
+            _ => XrpcError::Request(response),
+        }
+    }
+
+    /// Attaches the rate limit state the server reported alongside the error.
+    pub fn with_rate_limit(mut self, rate_limit: Option<RateLimitInfo>) -> Self {
+        if let Some(response) = self.response_mut() {
+            response.rate_limit = rate_limit;
+        }
+        self
+    }
+
+    fn response_mut(&mut self) -> Option<&mut XrpcErrorResponse> {
+        match self {
+            XrpcError::Auth { response, .. }
+            | XrpcError::RateLimited(response)
+            | XrpcError::Server(response)
+            | XrpcError::Request(response) => Some(response),
+            _ => None,
+        }
+    }
+
+    /// The error response, if the server answered at all.
+    pub fn response(&self) -> Option<&XrpcErrorResponse> {
+        match self {
+            XrpcError::Auth { response, .. }
+            | XrpcError::RateLimited(response)
+            | XrpcError::Server(response)
+            | XrpcError::Request(response) => Some(response),
+            _ => None,
+        }
+    }
+
+    /// The HTTP status code, if the server answered at all.
+    pub fn status(&self) -> Option<u16> {
+        self.response().map(|response| response.status)
+    }
+
+    /// The lexicon error name, e.g. `ExpiredToken`.
+    pub fn error_name(&self) -> Option<&str> {
+        self.response()
+            .and_then(|response| response.error.as_deref())
+    }
+
+    /// The rate limit state the server reported alongside the error.
+    pub fn rate_limit(&self) -> Option<&RateLimitInfo> {
+        self.response()
+            .and_then(|response| response.rate_limit.as_ref())
+    }
+
+    pub fn auth_kind(&self) -> Option<AuthErrorKind> {
+        match self {
+            XrpcError::Auth { kind, .. } => Some(*kind),
+            _ => None,
+        }
+    }
+
+    pub fn is_expired_token(&self) -> bool {
+        self.auth_kind() == Some(AuthErrorKind::ExpiredToken)
+    }
+
+    /// A `swapRecord` or `swapCommit` precondition failed: the record or repo changed since it
+    /// was read.
+    pub fn is_invalid_swap(&self) -> bool {
+        self.error_name() == Some("InvalidSwap")
+    }
+
+    pub fn is_record_not_found(&self) -> bool {
+        self.error_name() == Some("RecordNotFound")
+    }
+
+    /// The account has email 2FA and the login needs the code that was just sent by email.
+    pub fn is_auth_factor_token_required(&self) -> bool {
+        self.auth_kind() == Some(AuthErrorKind::AuthFactorTokenRequired)
+    }
+}
+
+impl fmt::Display for XrpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XrpcError::Configuration(message) => write!(f, "Configuration error: {}", message),
+            XrpcError::Transport(message) => write!(f, "Request error: {}", message),
+            XrpcError::Serialization(message) => write!(f, "Serialization error: {}", message),
+            XrpcError::Deserialization(message) => write!(f, "Deserialization error: {}", message),
+            XrpcError::NoSession => write!(f, "Not logged in"),
+            XrpcError::Identity(message) => write!(f, "Identity error: {}", message),
+            XrpcError::Syntax(err) => err.fmt(f),
+            XrpcError::Io(message) => write!(f, "I/O error: {}", message),
+            XrpcError::BlobTooLarge {
+                size: Some(size),
+                max_size,
+            } => write!(
+                f,
+                "Blob of {} bytes is larger than the limit of {} bytes",
+                size, max_size
+            ),
+            XrpcError::BlobTooLarge {
+                size: None,
+                max_size,
+            } => write!(f, "Blob is larger than the limit of {} bytes", max_size),
+            XrpcError::InvalidRecord(message) => write!(f, "Invalid record: {}", message),
+            XrpcError::Media(message) => write!(f, "Media error: {}", message),
+            _ => {
+                let response = self.response().expect("HTTP errors carry a response");
+                write!(f, "XRPC error with status code {}", response.status)?;
+                if let Some(error) = &response.error {
+                    write!(f, " ({})", error)?;
+                }
+                if let Some(message) = &response.message {
+                    write!(f, ": {}", message)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for XrpcError {}
+
+impl From<SyntaxError> for XrpcError {
+    fn from(err: SyntaxError) -> Self {
+        XrpcError::Syntax(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_lexicon_error_envelope() {
+        let err = XrpcError::from_response(
+            400,
+            r#"{"error":"InvalidRequest","message":"Input/repo must be a string"}"#,
+        );
+        assert!(matches!(err, XrpcError::Request(_)));
+        assert_eq!(err.status(), Some(400));
+        assert_eq!(err.error_name(), Some("InvalidRequest"));
+        assert_eq!(
+            err.to_string(),
+            "XRPC error with status code 400 (InvalidRequest): Input/repo must be a string"
+        );
+    }
+
+    #[test]
+    fn test_classifies_auth_errors_by_name() {
+        let expired = XrpcError::from_response(400, r#"{"error":"ExpiredToken"}"#);
+        assert!(expired.is_expired_token());
+
+        let factor = XrpcError::from_response(
+            401,
+            r#"{"error":"AuthFactorTokenRequired","message":"A sign in code has been sent"}"#,
+        );
+        assert_eq!(
+            factor.auth_kind(),
+            Some(AuthErrorKind::AuthFactorTokenRequired)
+        );
+        assert!(factor.is_auth_factor_token_required());
+
+        let unknown = XrpcError::from_response(401, "");
+        assert_eq!(unknown.auth_kind(), Some(AuthErrorKind::Other));
+    }
+
+    #[test]
+    fn test_classifies_rate_limit_and_server_errors() {
+        assert!(matches!(
+            XrpcError::from_response(429, r#"{"error":"RateLimitExceeded"}"#),
+            XrpcError::RateLimited(_)
+        ));
+        let server = XrpcError::from_response(502, "Bad Gateway");
+        assert!(matches!(server, XrpcError::Server(_)));
+        assert_eq!(
+            server.response().unwrap().message.as_deref(),
+            Some("Bad Gateway")
+        );
+    }
+
+    #[test]
+    fn test_composes_with_anyhow() {
+        let err: anyhow::Error = XrpcError::Transport("timed out".to_string()).into();
+        assert!(err.downcast_ref::<XrpcError>().is_some());
+    }
+}
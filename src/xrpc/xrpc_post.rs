@@ -1,36 +1,24 @@
+use super::xrpc_embed::Embed;
+use crate::dag_cbor::Cid;
+use crate::identity::HandleResolver;
+use crate::syntax::{AtUri, Did};
 use chrono::{DateTime, Utc};
+use log::warn;
 use regex::Regex;
 use serde::ser::{SerializeStruct, Serializer};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct CreatePostRequest {
-    pub collection: String, // e.g. "app.bsky.feed.post" for posts
-    pub repo: String,       // the did
-    pub record: Post,
-}
-
-impl CreatePostRequest {
-    pub fn new(did: &str, post: Post) -> Self {
-        Self {
-            collection: "app.bsky.feed.post".to_string(),
-            repo: did.to_string(),
-            record: post,
-        }
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct StrongRef {
-    pub uri: String,
-    pub cid: String,
+    pub uri: AtUri,
+    pub cid: Cid,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Post {
     #[serde(rename = "$type")]
     pub record_type: String,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     pub created_utc: chrono::DateTime<Utc>,
     #[serde(rename = "createdAt")]
     pub created_at: String,
@@ -40,6 +28,8 @@ pub struct Post {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reply: Option<ReplyRef>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub embed: Option<Embed>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub langs: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tags: Option<Vec<String>>,
@@ -78,6 +68,7 @@ impl Post {
             return Err(anyhow::anyhow!("The Post's text cannot be empty"));
         }
 
+        let debug_did = Did::new(debug_did)?;
         let now = Utc::now();
         let mut facets: Vec<Facet> = Vec::new();
 
@@ -96,7 +87,7 @@ impl Post {
                     },
                     features: vec![MentionFeature {
                         feature_type: "app.bsky.richtext.facet#mention".to_string(),
-                        did: debug_did.to_string(),
+                        did: debug_did.clone(),
                     }],
                 })
                 .collect();
@@ -169,11 +160,64 @@ impl Post {
                 Some(facets)
             },
             reply,
+            embed: None,
             langs,
             tags,
             labels,
         })
     }
+
+    /// The URLs of the post's links, in the order of the text.
+    pub fn links(&self) -> Vec<&str> {
+        self.facets
+            .iter()
+            .flatten()
+            .flat_map(|facet| &facet.features)
+            .filter_map(|feature| match feature {
+                Feature::Link { features, .. } => Some(features),
+                _ => None,
+            })
+            .flatten()
+            .map(|link| link.uri.as_str())
+            .collect()
+    }
+
+    /// Attaches images or another embed, see `ImagesEmbedBuilder`.
+    pub fn with_embed(mut self, embed: Embed) -> Self {
+        self.embed = Some(embed);
+        self
+    }
+
+    /// Replaces the placeholder DID of every mention with the DID its handle resolves to.
+    /// Mentions of handles that don't resolve are dropped, so they are posted as plain text.
+    pub async fn resolve_mentions(&mut self, resolver: &HandleResolver) {
+        let Some(facets) = self.facets.as_mut() else {
+            return;
+        };
+        for facet in facets.iter_mut() {
+            let mut resolved = Vec::with_capacity(facet.features.len());
+            for mut feature in facet.features.drain(..) {
+                if let Feature::Mention { index, features } = &mut feature {
+                    let handle = &self.text[index.byteStart..index.byteEnd];
+                    match resolver.resolve(handle).await {
+                        Ok(did) => features
+                            .iter_mut()
+                            .for_each(|mention| mention.did = did.clone()),
+                        Err(err) => {
+                            warn!("Dropping the mention of {}: {}", handle, err);
+                            continue;
+                        }
+                    }
+                }
+                resolved.push(feature);
+            }
+            facet.features = resolved;
+        }
+        facets.retain(|facet| !facet.features.is_empty());
+        if facets.is_empty() {
+            self.facets = None;
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -232,7 +276,7 @@ pub struct LinkFeature {
 pub struct MentionFeature {
     #[serde(rename = "$type")]
     feature_type: String,
-    did: String,
+    did: Did,
 }
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct HashtagFeature {
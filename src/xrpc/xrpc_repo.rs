@@ -0,0 +1,218 @@
+use super::xrpc_post::{Post, StrongRef};
+use crate::dag_cbor::Cid;
+use crate::syntax::{AtUri, Did, Nsid, RecordKey, Tid};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// A record type, stored in the collection named by its NSID. The serialized record should
+/// carry the same NSID as its `$type`.
+pub trait Record: Serialize + DeserializeOwned {
+    /// The collection, e.g. `app.bsky.feed.post`.
+    const NSID: &'static str;
+
+    fn nsid() -> Nsid {
+        Nsid::new(Self::NSID).expect("Record::NSID must be a valid NSID")
+    }
+}
+
+impl Record for Post {
+    const NSID: &'static str = "app.bsky.feed.post";
+}
+
+/// The input of `com.atproto.repo.createRecord`.
+#[derive(Debug, Serialize)]
+pub struct CreateRecordRequest<R> {
+    pub repo: Did,
+    pub collection: Nsid,
+    /// The server picks a TID when this is left out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rkey: Option<RecordKey>,
+    /// `Some(false)` skips lexicon validation, `Some(true)` requires it and `None` validates
+    /// only the lexicons the server knows.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validate: Option<bool>,
+    pub record: R,
+    /// Fails the write with `InvalidSwap` unless the repo is at this commit.
+    #[serde(rename = "swapCommit", skip_serializing_if = "Option::is_none")]
+    pub swap_commit: Option<Cid>,
+}
+
+impl<R: Record> CreateRecordRequest<R> {
+    pub fn new(repo: &Did, record: R) -> Self {
+        Self {
+            repo: repo.clone(),
+            collection: R::nsid(),
+            rkey: None,
+            validate: None,
+            record,
+            swap_commit: None,
+        }
+    }
+
+    pub fn with_rkey(mut self, rkey: RecordKey) -> Self {
+        self.rkey = Some(rkey);
+        self
+    }
+
+    pub fn with_validate(mut self, validate: bool) -> Self {
+        self.validate = Some(validate);
+        self
+    }
+
+    pub fn with_swap_commit(mut self, commit: Cid) -> Self {
+        self.swap_commit = Some(commit);
+        self
+    }
+}
+
+pub type CreatePostRequest = CreateRecordRequest<Post>;
+
+/// The input of `com.atproto.repo.putRecord`, which creates or replaces the record at `rkey`.
+#[derive(Debug, Serialize)]
+pub struct PutRecordRequest<R> {
+    pub repo: Did,
+    pub collection: Nsid,
+    pub rkey: RecordKey,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validate: Option<bool>,
+    pub record: R,
+    /// Fails the write with `InvalidSwap` unless the record currently has this CID.
+    #[serde(rename = "swapRecord", skip_serializing_if = "Option::is_none")]
+    pub swap_record: Option<Cid>,
+    #[serde(rename = "swapCommit", skip_serializing_if = "Option::is_none")]
+    pub swap_commit: Option<Cid>,
+}
+
+impl<R: Record> PutRecordRequest<R> {
+    pub fn new(repo: &Did, rkey: RecordKey, record: R) -> Self {
+        Self {
+            repo: repo.clone(),
+            collection: R::nsid(),
+            rkey,
+            validate: None,
+            record,
+            swap_record: None,
+            swap_commit: None,
+        }
+    }
+
+    pub fn with_validate(mut self, validate: bool) -> Self {
+        self.validate = Some(validate);
+        self
+    }
+
+    pub fn with_swap_record(mut self, record: Cid) -> Self {
+        self.swap_record = Some(record);
+        self
+    }
+
+    pub fn with_swap_commit(mut self, commit: Cid) -> Self {
+        self.swap_commit = Some(commit);
+        self
+    }
+}
+
+/// The input of `com.atproto.repo.deleteRecord`. Deleting a record that doesn't exist succeeds.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeleteRecordRequest {
+    pub repo: Did,
+    pub collection: Nsid,
+    pub rkey: RecordKey,
+    #[serde(rename = "swapRecord", skip_serializing_if = "Option::is_none")]
+    pub swap_record: Option<Cid>,
+    #[serde(rename = "swapCommit", skip_serializing_if = "Option::is_none")]
+    pub swap_commit: Option<Cid>,
+}
+
+impl DeleteRecordRequest {
+    pub fn new(repo: &Did, collection: Nsid, rkey: RecordKey) -> Self {
+        Self {
+            repo: repo.clone(),
+            collection,
+            rkey,
+            swap_record: None,
+            swap_commit: None,
+        }
+    }
+
+    /// Deletes the record of type `R` at `rkey`.
+    pub fn of<R: Record>(repo: &Did, rkey: RecordKey) -> Self {
+        Self::new(repo, R::nsid(), rkey)
+    }
+
+    pub fn with_swap_record(mut self, record: Cid) -> Self {
+        self.swap_record = Some(record);
+        self
+    }
+
+    pub fn with_swap_commit(mut self, commit: Cid) -> Self {
+        self.swap_commit = Some(commit);
+        self
+    }
+}
+
+/// The repo commit a write ended up in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommitMeta {
+    pub cid: Cid,
+    pub rev: Tid,
+}
+
+/// The output of `com.atproto.repo.createRecord` and `com.atproto.repo.putRecord`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateRecordResponse {
+    pub uri: AtUri,
+    pub cid: Cid,
+    pub commit: Option<CommitMeta>,
+    /// `valid`, or `unknown` if the server doesn't know the record's lexicon.
+    #[serde(rename = "validationStatus")]
+    pub validation_status: Option<String>,
+}
+
+impl CreateRecordResponse {
+    pub fn strong_ref(&self) -> StrongRef {
+        StrongRef {
+            uri: self.uri.clone(),
+            cid: self.cid.clone(),
+        }
+    }
+}
+
+pub type PutRecordResponse = CreateRecordResponse;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteRecordResponse {
+    pub commit: Option<CommitMeta>,
+}
+
+/// The output of `com.atproto.repo.getRecord`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetRecordResponse<R> {
+    pub uri: AtUri,
+    pub cid: Option<Cid>,
+    pub value: R,
+}
+
+/// A record as listed by `com.atproto.repo.listRecords`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ListedRecord<R> {
+    pub uri: AtUri,
+    pub cid: Cid,
+    pub value: R,
+}
+
+impl<R> ListedRecord<R> {
+    pub fn strong_ref(&self) -> StrongRef {
+        StrongRef {
+            uri: self.uri.clone(),
+            cid: self.cid.clone(),
+        }
+    }
+}
+
+/// A page of `com.atproto.repo.listRecords`; there are more records while `cursor` is set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ListRecordsResponse<R> {
+    pub cursor: Option<String>,
+    pub records: Vec<ListedRecord<R>>,
+}
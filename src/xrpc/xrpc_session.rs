@@ -1,11 +1,16 @@
+use super::{XrpcError, XrpcResult};
+use crate::identity::DidDocument;
+use crate::syntax::{Did, Handle};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::str;
 
-#[derive(Debug, Deserialize)]
+/// A password session. It is `Serialize` so that it can be persisted and resumed later, see
+/// `session::SessionStore`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateSessionResponse {
-    pub did: String,
-    pub handle: String,
+    pub did: Did,
+    pub handle: Handle,
     pub email: String,
     #[serde(rename = "emailConfirmed")]
     pub email_confirmed: bool,
@@ -13,10 +18,35 @@ pub struct CreateSessionResponse {
     pub access_jwt: String,
     #[serde(rename = "refreshJwt")]
     pub refresh_jwt: String,
+    /// The account's DID document, if the server sends it.
+    #[serde(rename = "didDoc", default, skip_serializing_if = "Option::is_none")]
+    pub did_doc: Option<Value>,
+    /// `false` if the account is deactivated, suspended or taken down; see `status`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active: Option<bool>,
+    /// Why the account is not active, e.g. `takendown`, `suspended` or `deactivated`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// Whether signing in requires a code sent by email, see
+    /// `CreateSessionRequest::auth_factor_token`.
+    #[serde(
+        rename = "emailAuthFactor",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub email_auth_factor: Option<bool>,
 }
 
 impl CreateSessionResponse {
-    pub fn update_from_refresh(&mut self, refresh: &RefreshSessionResponse) {
+    /// Takes over the rotated tokens. Fails without changing anything if the refreshed session
+    /// is for another account.
+    pub fn update_from_refresh(&mut self, refresh: &RefreshSessionResponse) -> XrpcResult<()> {
+        if self.did != refresh.did {
+            return Err(XrpcError::Identity(format!(
+                "the refreshed session is for {}, not {}",
+                refresh.did, self.did
+            )));
+        }
         let mut updated = false;
 
         if self.access_jwt != refresh.access_jwt {
@@ -37,15 +67,57 @@ impl CreateSessionResponse {
             updated = true;
         }
 
-        if self.did != refresh.did {
-            panic!("Did mismatch between create and refresh session responses");
+        if refresh.did_doc.is_some() {
+            self.did_doc = refresh.did_doc.clone();
         }
+        self.active = refresh.active;
+        self.status = refresh.status.clone();
 
         if updated {
             log::info!("Session successfully refreshed.");
         } else {
             log::info!("No updates detected during session refresh.");
         }
+        Ok(())
+    }
+
+    /// Takes over what the server reports about a resumed session. Fails without changing
+    /// anything if the server answers for another account, e.g. because the stored session
+    /// belongs to a different one.
+    pub fn update_from_get_session(&mut self, current: &GetSessionResponse) -> XrpcResult<()> {
+        if self.did != current.did {
+            return Err(XrpcError::Identity(format!(
+                "the stored session is for {}, but the server answered for {}",
+                self.did, current.did
+            )));
+        }
+        self.handle = current.handle.clone();
+        if let Some(email) = &current.email {
+            self.email = email.clone();
+        }
+        if let Some(email_confirmed) = current.email_confirmed {
+            self.email_confirmed = email_confirmed;
+        }
+        if current.did_doc.is_some() {
+            self.did_doc = current.did_doc.clone();
+        }
+        if current.email_auth_factor.is_some() {
+            self.email_auth_factor = current.email_auth_factor;
+        }
+        self.active = current.active;
+        self.status = current.status.clone();
+        Ok(())
+    }
+
+    /// The account's DID document, if the server sent one and it is for this account.
+    pub fn did_document(&self) -> Option<DidDocument> {
+        let document: DidDocument = serde_json::from_value(self.did_doc.clone()?).ok()?;
+        (document.id == self.did).then_some(document)
+    }
+
+    /// The PDS the account lives on, according to its DID document.
+    pub fn pds_endpoint(&self) -> Option<String> {
+        self.did_document()?.pds_endpoint().map(str::to_string)
     }
 
     pub fn print_token_info(&self) {
@@ -147,21 +219,47 @@ export interface OutputSchema {
   refreshJwt: string
   handle: string
   did: string
+  didDoc?: {}
+  active?: boolean
+  status?: 'takendown' | 'suspended' | 'deactivated' | (string & {})
   [k: string]: unknown
 }
 */
 #[derive(Debug, Deserialize)]
 pub struct RefreshSessionResponse {
-    pub did: String,
-    pub handle: String,
+    pub did: Did,
+    pub handle: Handle,
     #[serde(rename = "accessJwt")]
     pub access_jwt: String,
     #[serde(rename = "refreshJwt")]
     pub refresh_jwt: String,
+    #[serde(rename = "didDoc")]
+    pub did_doc: Option<Value>,
+    pub active: Option<bool>,
+    pub status: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetSessionResponse {
+    pub did: Did,
+    pub handle: Handle,
+    pub email: Option<String>,
+    #[serde(rename = "emailConfirmed")]
+    pub email_confirmed: Option<bool>,
+    #[serde(rename = "emailAuthFactor")]
+    pub email_auth_factor: Option<bool>,
+    #[serde(rename = "didDoc")]
+    pub did_doc: Option<Value>,
+    pub active: Option<bool>,
+    pub status: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct CreateSessionRequest {
     pub identifier: String,
     pub password: String,
+    /// The sign-in code sent by email to accounts with email 2FA. Log in without it first; the
+    /// server then sends the code and answers with `AuthFactorTokenRequired`.
+    #[serde(rename = "authFactorToken", skip_serializing_if = "Option::is_none")]
+    pub auth_factor_token: Option<String>,
 }
@@ -1,10 +1,11 @@
+use crate::syntax::{Did, Handle};
 use serde::Deserialize;
 use std::str;
 
 #[derive(Debug, Deserialize)]
 pub struct ProfileViewDetailedResponse {
-    pub did: String,
-    pub handle: String,
+    pub did: Did,
+    pub handle: Handle,
     #[serde(rename = "displayName")]
     pub display_name: Option<String>,
     pub description: Option<String>,
@@ -19,3 +20,8 @@ pub struct ProfileViewDetailedResponse {
     #[serde(rename = "indexedAt")]
     pub indexed_at: Option<String>,
 }
+
+#[derive(Debug, Deserialize)]
+pub struct ResolveHandleResponse {
+    pub did: Did,
+}
@@ -0,0 +1,185 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rustysky::accounts::AccountManager;
+use rustysky::xrpc::{CreateSessionRequest, HttpResponse, MockTransport};
+use serde_json::json;
+
+mod common;
+
+use common::test_client;
+
+fn jwt(tag: &str, expires_in: i64) -> String {
+    let exp = chrono::Utc::now().timestamp() + expires_in;
+    let payload = URL_SAFE_NO_PAD.encode(json!({ "exp": exp, "tag": tag }).to_string());
+    format!("eyJhbGciOiJub25lIn0.{}.sig", payload)
+}
+
+fn session_response(did: &str, handle: &str, access_jwt: &str) -> HttpResponse {
+    HttpResponse::json(
+        200,
+        json!({
+            "did": did,
+            "handle": handle,
+            "email": "social@example.com",
+            "emailConfirmed": true,
+            "accessJwt": access_jwt,
+            "refreshJwt": jwt("refresh", 86400)
+        }),
+    )
+}
+
+fn login_request(identifier: &str) -> CreateSessionRequest {
+    CreateSessionRequest {
+        identifier: identifier.to_string(),
+        password: "app-password".to_string(),
+        auth_factor_token: None,
+    }
+}
+
+async fn add_two_accounts(accounts: &AccountManager, mock: &MockTransport) {
+    mock.respond_once(
+        "com.atproto.server.createSession",
+        session_response("did:plc:brand-a", "brand-a.bsky.social", &jwt("a", 7200)),
+    )
+    .respond_once(
+        "com.atproto.server.createSession",
+        session_response("did:plc:brand-b", "brand-b.example.com", &jwt("b", -60)),
+    );
+    accounts
+        .add("brand-a", None, &login_request("brand-a.bsky.social"))
+        .await
+        .unwrap();
+    accounts
+        .add(
+            "brand-b",
+            Some("https://pds.example.com/"),
+            &login_request("brand-b.example.com"),
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_accounts_live_on_their_own_hosts() {
+    let (client, mock) = test_client();
+    let accounts = AccountManager::in_memory(client);
+    add_two_accounts(&accounts, &mock).await;
+
+    let list = accounts.list().await;
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].name, "brand-a");
+    assert_eq!(list[0].host, "https://bsky.social");
+    assert!(list[0].current, "the first account becomes the current one");
+    assert_eq!(list[1].host, "https://pds.example.com");
+    assert_eq!(list[1].handle.as_deref(), Some("brand-b.example.com"));
+    assert!(!list[1].current);
+
+    let logins = mock.requests_to("com.atproto.server.createSession");
+    assert!(logins[1]
+        .url
+        .starts_with("https://pds.example.com/xrpc/com.atproto.server.createSession"));
+
+    mock.respond(
+        "app.bsky.actor.getProfile",
+        HttpResponse::json(
+            200,
+            json!({"did": "did:plc:brand-a", "handle": "brand-a.bsky.social"}),
+        ),
+    );
+    let agent = accounts.current_agent().await.unwrap();
+    agent.get_profile("did:plc:brand-a").await.unwrap();
+    assert!(mock.requests_to("app.bsky.actor.getProfile")[0]
+        .url
+        .starts_with("https://bsky.social/"));
+}
+
+#[tokio::test]
+async fn test_each_account_refreshes_on_its_own() {
+    let (client, mock) = test_client();
+    let accounts = AccountManager::in_memory(client);
+    add_two_accounts(&accounts, &mock).await;
+
+    let fresh = jwt("b-fresh", 7200);
+    mock.respond(
+        "com.atproto.server.refreshSession",
+        HttpResponse::json(
+            200,
+            json!({
+                "did": "did:plc:brand-b",
+                "handle": "brand-b.example.com",
+                "accessJwt": fresh,
+                "refreshJwt": jwt("b-rotated", 86400)
+            }),
+        ),
+    )
+    .respond(
+        "app.bsky.actor.getProfile",
+        HttpResponse::json(200, json!({"did": "did:plc:x", "handle": "x.test"})),
+    );
+
+    accounts
+        .agent("brand-a")
+        .await
+        .unwrap()
+        .get_profile("x.test")
+        .await
+        .unwrap();
+    accounts
+        .agent("brand-b")
+        .await
+        .unwrap()
+        .get_profile("x.test")
+        .await
+        .unwrap();
+
+    let refreshes = mock.requests_to("com.atproto.server.refreshSession");
+    assert_eq!(refreshes.len(), 1);
+    assert!(refreshes[0].url.starts_with("https://pds.example.com/"));
+    let profiles = mock.requests_to("app.bsky.actor.getProfile");
+    assert_eq!(
+        profiles[1].header("authorization"),
+        Some(format!("Bearer {}", fresh).as_str())
+    );
+}
+
+#[tokio::test]
+async fn test_switch_and_remove() {
+    let (client, mock) = test_client();
+    let accounts = AccountManager::in_memory(client);
+    add_two_accounts(&accounts, &mock).await;
+    mock.respond(
+        "com.atproto.server.deleteSession",
+        HttpResponse::new(200, ""),
+    );
+
+    accounts.switch("brand-b").await.unwrap();
+    assert_eq!(accounts.current().await.as_deref(), Some("brand-b"));
+    assert!(accounts.switch("brand-c").await.is_err());
+
+    accounts.remove("brand-b").await.unwrap();
+    assert_eq!(
+        mock.requests_to("com.atproto.server.deleteSession").len(),
+        1
+    );
+    assert_eq!(accounts.current().await, None);
+    assert_eq!(accounts.list().await.len(), 1);
+    assert!(accounts.agent("brand-b").await.is_err());
+    assert!(accounts.current_agent().await.is_err());
+}
+
+#[tokio::test]
+async fn test_accounts_are_persisted() {
+    let path = std::env::temp_dir().join(format!("rustysky-accounts-{}.json", std::process::id()));
+    let (client, mock) = test_client();
+    let accounts = AccountManager::open(client.clone(), &path).await.unwrap();
+    add_two_accounts(&accounts, &mock).await;
+    accounts.switch("brand-b").await.unwrap();
+
+    let reopened = AccountManager::open(client, &path).await.unwrap();
+    assert_eq!(reopened.current().await.as_deref(), Some("brand-b"));
+    let agent = reopened.agent("brand-b").await.unwrap();
+    assert_eq!(agent.config().xrpc_host, "https://pds.example.com");
+    assert_eq!(agent.session().unwrap().did, "did:plc:brand-b");
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,375 @@
+use rustysky::dag_cbor::Cid;
+use rustysky::types::get_default_configuration;
+use rustysky::xrpc::{
+    upload_blob, AspectRatio, BlobRef, BlobUpload, Embed, HttpResponse, ImagesEmbedBuilder, Post,
+    RetryPolicy, StrongRef, XrpcClient, XrpcError,
+};
+use serde_json::json;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use wiremock::matchers::{body_bytes, header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+mod common;
+
+use common::test_agent;
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR";
+const JPEG: &[u8] = b"\xff\xd8\xff\xe0\x00\x10JFIF";
+
+fn upload_response(bytes: &[u8], mime_type: &str) -> HttpResponse {
+    let blob = BlobRef::new(Cid::for_raw(bytes), mime_type, bytes.len() as u64);
+    HttpResponse::json(200, json!({ "blob": blob }))
+}
+
+/// A file in the temp directory that is removed again when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str, contents: &[u8]) -> Self {
+        let path = std::env::temp_dir().join(format!("rustysky-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn test_upload_bytes() {
+    let (agent, mock) = test_agent();
+    mock.respond(
+        "com.atproto.repo.uploadBlob",
+        upload_response(PNG, "image/png"),
+    );
+
+    let blob = agent
+        .upload_blob(&BlobUpload::from_bytes(PNG.to_vec()))
+        .await
+        .unwrap();
+    assert_eq!(blob.cid, Cid::for_raw(PNG));
+    assert_eq!(blob.mime_type, "image/png");
+    assert_eq!(blob.size, PNG.len() as u64);
+
+    let requests = mock.requests_to("com.atproto.repo.uploadBlob");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].header("content-type"), Some("image/png"));
+    assert_eq!(requests[0].header("authorization"), Some("Bearer access"));
+    assert_eq!(requests[0].body.as_deref(), Some(PNG));
+}
+
+#[tokio::test]
+async fn test_upload_file_falls_back_to_the_extension() {
+    let (agent, mock) = test_agent();
+    mock.respond(
+        "com.atproto.repo.uploadBlob",
+        upload_response(b"not really", "image/webp"),
+    );
+    let file = TempFile::new("upload.webp", b"not really");
+
+    let upload = BlobUpload::from_file(&file.0).await.unwrap();
+    assert_eq!(upload.mime_type(), "image/webp");
+    assert_eq!(upload.size(), Some(10));
+    agent.upload_blob(&upload).await.unwrap();
+
+    let requests = mock.requests_to("com.atproto.repo.uploadBlob");
+    assert_eq!(requests[0].header("content-type"), Some("image/webp"));
+    assert_eq!(requests[0].body.as_deref(), Some(&b"not really"[..]));
+}
+
+#[tokio::test]
+async fn test_too_large_blob_is_not_sent() {
+    let (agent, mock) = test_agent();
+    let file = TempFile::new("large.png", &[PNG, &[0; 1000]].concat());
+
+    let upload = BlobUpload::from_file(&file.0)
+        .await
+        .unwrap()
+        .with_max_size(1000);
+    let err = agent.upload_blob(&upload).await.unwrap_err();
+    assert!(matches!(
+        err,
+        XrpcError::BlobTooLarge {
+            size: Some(1016),
+            max_size: 1000
+        }
+    ));
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn test_upload_from_reader() {
+    let (agent, mock) = test_agent();
+    let contents = [PNG, &[7; 200_000]].concat();
+    mock.respond(
+        "com.atproto.repo.uploadBlob",
+        upload_response(&contents, "image/png"),
+    );
+
+    let upload = BlobUpload::from_reader(std::io::Cursor::new(contents.clone()))
+        .await
+        .unwrap();
+    assert_eq!(upload.mime_type(), "image/png");
+    assert_eq!(upload.size(), None);
+    let blob = agent.upload_blob(&upload).await.unwrap();
+    assert_eq!(blob.size, contents.len() as u64);
+    let requests = mock.requests_to("com.atproto.repo.uploadBlob");
+    assert_eq!(requests[0].body.as_deref(), Some(&contents[..]));
+
+    // The reader is used up.
+    let err = agent.upload_blob(&upload).await.unwrap_err();
+    assert!(matches!(err, XrpcError::Io(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn test_reader_is_cut_off_at_the_limit() {
+    let (agent, _mock) = test_agent();
+    let reader = tokio::io::repeat(0).take(10_000);
+    let upload = BlobUpload::from_reader(reader)
+        .await
+        .unwrap()
+        .with_max_size(5_000)
+        .with_mime_type("video/mp4");
+    assert_eq!(upload.mime_type(), "video/mp4");
+
+    let err = agent.upload_blob(&upload).await.unwrap_err();
+    assert!(
+        matches!(
+            err,
+            XrpcError::BlobTooLarge {
+                size: None,
+                max_size: 5000
+            }
+        ),
+        "{:?}",
+        err
+    );
+}
+
+/// A client that sends over HTTP to `server` and would retry transport errors.
+fn retrying_client(server: &MockServer) -> XrpcClient {
+    let mut config = get_default_configuration();
+    config.xrpc_host = server.uri();
+    config.retry_policy = RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(1),
+        ..RetryPolicy::default()
+    };
+    XrpcClient::new(config).unwrap()
+}
+
+#[tokio::test]
+async fn test_unreadable_files_are_not_retried() {
+    let server = MockServer::start().await;
+    let client = retrying_client(&server);
+
+    let missing = std::env::temp_dir().join("rustysky-does-not-exist.png");
+    let err = BlobUpload::from_file(&missing).await.unwrap_err();
+    assert!(matches!(err, XrpcError::Io(_)), "{:?}", err);
+
+    // The file goes away between preparing the upload and sending it.
+    let file = TempFile::new("vanishing.png", PNG);
+    let upload = BlobUpload::from_file(&file.0).await.unwrap();
+    std::fs::remove_file(&file.0).unwrap();
+    let err = upload_blob(&upload, "access", &client).await.unwrap_err();
+    assert!(matches!(err, XrpcError::Io(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn test_streamed_blob_over_the_limit_is_not_retried() {
+    let server = MockServer::start().await;
+    let client = retrying_client(&server);
+
+    let reader = tokio::io::repeat(0).take(500_000);
+    let upload = BlobUpload::from_reader(reader)
+        .await
+        .unwrap()
+        .with_max_size(100_000);
+    let err = upload_blob(&upload, "access", &client).await.unwrap_err();
+    assert!(
+        matches!(err, XrpcError::BlobTooLarge { size: None, .. }),
+        "{:?}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn test_streamed_file_upload_is_retried() {
+    let server = MockServer::start().await;
+    let contents = [PNG, &[1; 300_000]].concat();
+    Mock::given(method("POST"))
+        .and(path("/xrpc/com.atproto.repo.uploadBlob"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/xrpc/com.atproto.repo.uploadBlob"))
+        .and(header("content-type", "image/png"))
+        .and(body_bytes(contents.clone()))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "blob": BlobRef::new(Cid::for_raw(&contents), "image/png", contents.len() as u64)
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut config = get_default_configuration();
+    config.xrpc_host = server.uri();
+    config.retry_policy = RetryPolicy {
+        max_attempts: 2,
+        initial_backoff: Duration::from_millis(1),
+        ..RetryPolicy::default()
+    };
+    let client = XrpcClient::new(config).unwrap();
+    let file = TempFile::new("retried.bin", &contents);
+
+    let upload = BlobUpload::from_file(&file.0).await.unwrap();
+    let blob = upload_blob(&upload, "access", &client).await.unwrap();
+    assert_eq!(blob.cid, Cid::for_raw(&contents));
+}
+
+#[tokio::test]
+async fn test_post_with_images() {
+    let (agent, mock) = test_agent();
+    mock.respond_once(
+        "com.atproto.repo.uploadBlob",
+        upload_response(PNG, "image/png"),
+    )
+    .respond_once(
+        "com.atproto.repo.uploadBlob",
+        upload_response(JPEG, "image/jpeg"),
+    )
+    .respond(
+        "com.atproto.repo.createRecord",
+        HttpResponse::json(
+            200,
+            json!({
+                "uri": "at://did:plc:testuser/app.bsky.feed.post/3k4duaz5vfs2b",
+                "cid": Cid::for_raw(b"post").to_string()
+            }),
+        ),
+    );
+
+    let embed = ImagesEmbedBuilder::new()
+        .image(BlobUpload::from_bytes(PNG.to_vec()), "A tiny PNG")
+        .image_with_aspect_ratio(
+            BlobUpload::from_bytes(JPEG.to_vec()),
+            "A tiny JPEG",
+            AspectRatio::new(4, 3),
+        )
+        .upload(&agent)
+        .await
+        .unwrap();
+    let post = Post::new("Two images", "did:plc:testuser", None, None, None, None)
+        .unwrap()
+        .with_embed(embed);
+    agent.create_post(post).await.unwrap();
+
+    let uploads = mock.requests_to("com.atproto.repo.uploadBlob");
+    assert_eq!(uploads.len(), 2);
+    assert_eq!(uploads[1].header("content-type"), Some("image/jpeg"));
+    let record = &mock.requests_to("com.atproto.repo.createRecord")[0].json()["record"];
+    assert_eq!(
+        record["embed"],
+        json!({
+            "$type": "app.bsky.embed.images",
+            "images": [
+                {
+                    "image": BlobRef::new(Cid::for_raw(PNG), "image/png", PNG.len() as u64),
+                    "alt": "A tiny PNG"
+                },
+                {
+                    "image": BlobRef::new(Cid::for_raw(JPEG), "image/jpeg", JPEG.len() as u64),
+                    "alt": "A tiny JPEG",
+                    "aspectRatio": { "width": 4, "height": 3 }
+                }
+            ]
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_quote_with_images() {
+    let (agent, mock) = test_agent();
+    mock.respond(
+        "com.atproto.repo.uploadBlob",
+        upload_response(PNG, "image/png"),
+    )
+    .respond(
+        "com.atproto.repo.createRecord",
+        HttpResponse::json(
+            200,
+            json!({
+                "uri": "at://did:plc:testuser/app.bsky.feed.post/3k4duaz5vfs2c",
+                "cid": Cid::for_raw(b"quote").to_string()
+            }),
+        ),
+    );
+
+    let quoted = StrongRef {
+        uri: "at://did:plc:other/app.bsky.feed.post/3k4duaz5vfs2b"
+            .parse()
+            .unwrap(),
+        cid: Cid::for_raw(b"post"),
+    };
+    let images = ImagesEmbedBuilder::new()
+        .image(BlobUpload::from_bytes(PNG.to_vec()), "A tiny PNG")
+        .upload(&agent)
+        .await
+        .unwrap();
+    let post = Post::new("Look at this", "did:plc:testuser", None, None, None, None)
+        .unwrap()
+        .with_embed(Embed::quote_with_media(quoted, images).unwrap());
+    agent.create_post(post).await.unwrap();
+
+    let record = &mock.requests_to("com.atproto.repo.createRecord")[0].json()["record"];
+    assert_eq!(
+        record["embed"],
+        json!({
+            "$type": "app.bsky.embed.recordWithMedia",
+            "record": {
+                "record": {
+                    "uri": "at://did:plc:other/app.bsky.feed.post/3k4duaz5vfs2b",
+                    "cid": Cid::for_raw(b"post").to_string()
+                }
+            },
+            "media": {
+                "$type": "app.bsky.embed.images",
+                "images": [{
+                    "image": BlobRef::new(Cid::for_raw(PNG), "image/png", PNG.len() as u64),
+                    "alt": "A tiny PNG"
+                }]
+            }
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_images_are_not_uploaded() {
+    let (agent, mock) = test_agent();
+    let too_large = [PNG, &[0; 1_000_000]].concat();
+
+    // The first image is fine, but nothing is uploaded because of the second.
+    let err = ImagesEmbedBuilder::new()
+        .image(BlobUpload::from_bytes(PNG.to_vec()), "")
+        .image(BlobUpload::from_bytes(too_large), "")
+        .upload(&agent)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, XrpcError::BlobTooLarge { .. }), "{:?}", err);
+
+    let err = ImagesEmbedBuilder::new()
+        .image(BlobUpload::from_bytes(b"plain text".to_vec()), "")
+        .upload(&agent)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, XrpcError::InvalidRecord(_)), "{:?}", err);
+    assert!(mock.requests().is_empty());
+}
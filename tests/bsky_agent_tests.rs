@@ -1,5 +1,12 @@
 use anyhow::Result;
-use rustysky::bsky_agent::call_host;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rustysky::bsky_agent::{call_host, BskyAgent};
+use rustysky::types::get_default_configuration;
+use rustysky::xrpc::{CreateSessionResponse, Post, RetryPolicy, XrpcClient, XrpcError};
+use serde_json::json;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 
 #[test]
 fn test_call_host() -> Result<()> {
@@ -7,3 +14,134 @@ fn test_call_host() -> Result<()> {
     assert_eq!(response, "called the api over http");
     Ok(())
 }
+
+/// An unsigned JWT that expires `expires_in` seconds from now. The tag keeps tokens with the
+/// same expiry distinguishable.
+fn jwt(tag: &str, expires_in: i64) -> String {
+    let exp = chrono::Utc::now().timestamp() + expires_in;
+    let payload = URL_SAFE_NO_PAD.encode(json!({ "exp": exp, "tag": tag }).to_string());
+    format!("eyJhbGciOiJub25lIn0.{}.sig", payload)
+}
+
+fn test_client(server: &MockServer) -> XrpcClient {
+    let mut config = get_default_configuration();
+    config.xrpc_host = server.uri();
+    config.retry_policy = RetryPolicy::none();
+    XrpcClient::new(config).unwrap()
+}
+
+fn test_session(access_jwt: &str) -> CreateSessionResponse {
+    CreateSessionResponse {
+        did: "did:plc:testuser".parse().unwrap(),
+        handle: "test.bsky.social".parse().unwrap(),
+        email: "test@example.com".to_string(),
+        email_confirmed: true,
+        access_jwt: access_jwt.to_string(),
+        refresh_jwt: jwt("refresh", 86400),
+        did_doc: None,
+        active: None,
+        status: None,
+        email_auth_factor: None,
+    }
+}
+
+async fn mount_refresh(server: &MockServer, new_access_jwt: &str, expected_calls: u64) {
+    Mock::given(method("POST"))
+        .and(path("/xrpc/com.atproto.server.refreshSession"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "did": "did:plc:testuser",
+            "handle": "test.bsky.social",
+            "accessJwt": new_access_jwt,
+            "refreshJwt": jwt("refreshed", 86400),
+        })))
+        .expect(expected_calls)
+        .mount(server)
+        .await;
+}
+
+async fn mount_create_record(server: &MockServer, access_jwt: &str) {
+    Mock::given(method("POST"))
+        .and(path("/xrpc/com.atproto.repo.createRecord"))
+        .and(header(
+            "Authorization",
+            format!("Bearer {}", access_jwt).as_str(),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "uri": "at://did:plc:testuser/app.bsky.feed.post/3k2a",
+            "cid": "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"
+        })))
+        .mount(server)
+        .await;
+}
+
+fn test_post() -> Post {
+    Post::new("hello", "did:plc:testuser", None, None, None, None).unwrap()
+}
+
+#[tokio::test]
+async fn test_refreshes_before_expiry() {
+    let server = MockServer::start().await;
+    let fresh = jwt("fresh", 7200);
+    mount_refresh(&server, &fresh, 1).await;
+    mount_create_record(&server, &fresh).await;
+
+    let agent = BskyAgent::with_session(test_client(&server), test_session(&jwt("stale", 60)));
+    let strong_ref = agent.create_post(test_post()).await.unwrap();
+    assert_eq!(
+        strong_ref.cid,
+        "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"
+    );
+    assert_eq!(agent.session().unwrap().access_jwt, fresh);
+}
+
+#[tokio::test]
+async fn test_refreshes_and_replays_on_expired_token() {
+    let server = MockServer::start().await;
+    let revoked = jwt("revoked", 7200);
+    let fresh = jwt("fresh", 7200);
+    Mock::given(method("POST"))
+        .and(path("/xrpc/com.atproto.repo.createRecord"))
+        .and(header(
+            "Authorization",
+            format!("Bearer {}", revoked).as_str(),
+        ))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "error": "ExpiredToken",
+            "message": "Token has expired"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    mount_refresh(&server, &fresh, 1).await;
+    mount_create_record(&server, &fresh).await;
+
+    let agent = BskyAgent::with_session(test_client(&server), test_session(&revoked));
+    agent.create_post(test_post()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_concurrent_calls_share_one_refresh() {
+    let server = MockServer::start().await;
+    let fresh = jwt("fresh", 7200);
+    mount_refresh(&server, &fresh, 1).await;
+    mount_create_record(&server, &fresh).await;
+
+    let agent = BskyAgent::with_session(test_client(&server), test_session(&jwt("stale", 60)));
+    let tasks: Vec<_> = (0..8)
+        .map(|_| {
+            let agent = agent.clone();
+            tokio::spawn(async move { agent.create_post(test_post()).await })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_authenticated_calls_require_a_session() {
+    let server = MockServer::start().await;
+    let agent = BskyAgent::new(test_client(&server));
+    let err = agent.create_post(test_post()).await.unwrap_err();
+    assert!(matches!(err, XrpcError::NoSession));
+}
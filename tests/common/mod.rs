@@ -0,0 +1,38 @@
+//! Factories shared by the integration tests. Each test binary uses only some of them.
+#![allow(dead_code)]
+
+use rustysky::bsky_agent::BskyAgent;
+use rustysky::types::get_default_configuration;
+use rustysky::xrpc::{CreateSessionResponse, MockTransport, RetryPolicy, XrpcClient};
+use std::sync::Arc;
+
+/// A client that answers from a `MockTransport`, retrying as `retry_policy` says.
+pub fn mock_client(retry_policy: RetryPolicy) -> (XrpcClient, Arc<MockTransport>) {
+    let mock = Arc::new(MockTransport::new());
+    let mut config = get_default_configuration();
+    config.retry_policy = retry_policy;
+    (XrpcClient::with_transport(config, mock.clone()), mock)
+}
+
+/// A `MockTransport` client that never retries.
+pub fn test_client() -> (XrpcClient, Arc<MockTransport>) {
+    mock_client(RetryPolicy::none())
+}
+
+/// An agent logged in as `did:plc:testuser` on a `MockTransport` client that never retries.
+pub fn test_agent() -> (BskyAgent, Arc<MockTransport>) {
+    let (client, mock) = test_client();
+    let session = CreateSessionResponse {
+        did: "did:plc:testuser".parse().unwrap(),
+        handle: "test.bsky.social".parse().unwrap(),
+        email: "test@example.com".to_string(),
+        email_confirmed: true,
+        access_jwt: "access".to_string(),
+        refresh_jwt: "refresh".to_string(),
+        did_doc: None,
+        active: None,
+        status: None,
+        email_auth_factor: None,
+    };
+    (BskyAgent::with_session(client, session), mock)
+}
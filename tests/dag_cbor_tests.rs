@@ -0,0 +1,96 @@
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use rustysky::dag_cbor::{self, Cid};
+use rustysky::xrpc::{Post, StrongRef};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// An entry of the atproto interop data model fixtures: a JSON value, its DAG-CBOR encoding
+/// and its CID.
+#[derive(Deserialize)]
+struct Fixture {
+    json: Value,
+    cbor_base64: String,
+    cid: String,
+}
+
+/// The upstream fixtures from atproto-interop-tests, see `tests/fixtures/README.md`.
+const INTEROP_FIXTURES: &str = "tests/fixtures/atproto-interop-tests/data-model-fixtures.json";
+
+fn load_fixtures(path: &Path) -> Vec<Fixture> {
+    let fixtures =
+        std::fs::read_to_string(path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    serde_json::from_str(&fixtures).unwrap()
+}
+
+/// JSON → DAG-CBOR bytes → CID, and the bytes back to the JSON.
+fn check_fixture(fixture: &Fixture) -> Result<()> {
+    let expected_cbor = STANDARD_NO_PAD.decode(fixture.cbor_base64.trim_end_matches('='))?;
+    let cbor = dag_cbor::encode(&fixture.json)?;
+    assert_eq!(cbor, expected_cbor, "{}", fixture.json);
+    assert_eq!(
+        Cid::for_dag_cbor(&cbor),
+        fixture.cid.as_str(),
+        "{}",
+        fixture.json
+    );
+    assert_eq!(Cid::for_record(&fixture.json)?, fixture.cid.as_str());
+    assert_eq!(dag_cbor::decode(&expected_cbor)?, fixture.json);
+    Ok(())
+}
+
+#[test]
+#[ignore = "the upstream fixtures are not vendored yet, see tests/fixtures/README.md"]
+fn test_interop_fixtures() -> Result<()> {
+    let fixtures = load_fixtures(&Path::new(env!("CARGO_MANIFEST_DIR")).join(INTEROP_FIXTURES));
+    assert!(!fixtures.is_empty());
+    for fixture in &fixtures {
+        check_fixture(fixture)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_regression_fixtures() -> Result<()> {
+    let path =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/dag-cbor-regressions.json");
+    for fixture in &load_fixtures(&path) {
+        check_fixture(fixture)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_invalid_json() {
+    for value in [
+        json!(1.5),
+        json!({ "n": 0.1 }),
+        json!({ "$link": "QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n" }),
+        json!({ "$link": 1 }),
+        json!({ "$bytes": "not base64!" }),
+    ] {
+        assert!(dag_cbor::encode(&value).is_err(), "{}", value);
+    }
+}
+
+#[test]
+fn test_records_round_trip() -> Result<()> {
+    let did = "did:plc:z72i7hdynmk6r22z27h6tvur";
+    let post = Post::new("Hello from DAG-CBOR", did, None, None, None, None)?;
+    let cbor = dag_cbor::to_dag_cbor(&post)?;
+    let decoded: Value = dag_cbor::from_dag_cbor(&cbor)?;
+    assert_eq!(decoded, serde_json::to_value(&post)?);
+    assert_eq!(Cid::for_record(&post)?, Cid::for_dag_cbor(&cbor));
+
+    let strong_ref = StrongRef {
+        uri: format!("at://{}/app.bsky.feed.post/3k4duaz5vfs2b", did).parse()?,
+        cid: Cid::for_record(&post)?,
+    };
+    let cbor = dag_cbor::to_dag_cbor(&strong_ref)?;
+    let decoded: StrongRef = dag_cbor::from_dag_cbor(&cbor)?;
+    assert_eq!(decoded.uri, strong_ref.uri);
+    assert_eq!(decoded.cid, strong_ref.cid);
+    Ok(())
+}
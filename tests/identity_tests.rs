@@ -0,0 +1,342 @@
+use async_trait::async_trait;
+use rustysky::identity::{DidResolver, DnsResolver, HandleResolver, IdentityResolver};
+use rustysky::xrpc::{HttpResponse, Post, XrpcError, XrpcResult};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+mod common;
+
+use common::test_client;
+
+const DOH: &str = "https://cloudflare-dns.com/dns-query";
+const RESOLVE_HANDLE: &str = "com.atproto.identity.resolveHandle";
+
+fn txt_answer(records: &[&str]) -> HttpResponse {
+    let answer: Vec<_> = records
+        .iter()
+        .map(|data| json!({"name": "_atproto.alice.test", "type": 16, "TTL": 300, "data": data}))
+        .collect();
+    HttpResponse::json(200, json!({"Status": 0, "Answer": answer}))
+}
+
+fn nxdomain() -> HttpResponse {
+    HttpResponse::json(200, json!({"Status": 3}))
+}
+
+fn did_document(did: &str, handle: &str) -> HttpResponse {
+    HttpResponse::json(
+        200,
+        json!({
+            "id": did,
+            "alsoKnownAs": [format!("at://{}", handle)],
+            "verificationMethod": [{
+                "id": format!("{}#atproto", did),
+                "type": "Multikey",
+                "controller": did,
+                "publicKeyMultibase": "zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF"
+            }],
+            "service": [{
+                "id": "#atproto_pds",
+                "type": "AtprotoPersonalDataServer",
+                "serviceEndpoint": "https://pds.example.com"
+            }]
+        }),
+    )
+}
+
+/// A DNS backend that never answers in time.
+struct HangingDns;
+
+#[async_trait]
+impl DnsResolver for HangingDns {
+    async fn txt_records(&self, _name: &str) -> XrpcResult<Vec<String>> {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Ok(Vec::new())
+    }
+}
+
+#[tokio::test]
+async fn test_resolves_with_dns_first() {
+    let (client, mock) = test_client();
+    mock.respond(DOH, txt_answer(&["\"did=did:plc:alice\""]));
+    let resolver = HandleResolver::new(client);
+
+    let did = resolver.resolve("@Alice.Test").await.unwrap();
+
+    assert_eq!(did, "did:plc:alice");
+    let queries = mock.requests_to(DOH);
+    assert_eq!(queries.len(), 1);
+    assert!(queries[0]
+        .url
+        .ends_with("?name=_atproto.alice.test&type=TXT"));
+    assert_eq!(queries[0].header("accept"), Some("application/dns-json"));
+    assert_eq!(mock.requests().len(), 1, "nothing else is asked");
+}
+
+#[tokio::test]
+async fn test_falls_back_to_well_known() {
+    let (client, mock) = test_client();
+    mock.respond(DOH, nxdomain()).respond(
+        "https://alice.test/.well-known/atproto-did",
+        HttpResponse::new(200, "did:web:alice.test\n"),
+    );
+    let resolver = HandleResolver::new(client);
+
+    assert_eq!(
+        resolver.resolve("alice.test").await.unwrap(),
+        "did:web:alice.test"
+    );
+    assert!(mock.requests_to(RESOLVE_HANDLE).is_empty());
+}
+
+#[tokio::test]
+async fn test_oversized_well_known_bodies_are_refused() {
+    let (client, mock) = test_client();
+    mock.respond(
+        "https://alice.test/.well-known/atproto-did",
+        HttpResponse::new(200, format!("did:plc:alice{}", " ".repeat(8 * 1024))),
+    );
+
+    let err = HandleResolver::new(client)
+        .resolve_well_known("alice.test")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, XrpcError::Identity(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn test_conflicting_txt_records_are_not_trusted() {
+    let (client, mock) = test_client();
+    mock.respond(
+        DOH,
+        txt_answer(&["\"did=did:plc:alice\"", "\"did=did:plc:mallory\""]),
+    )
+    .respond(
+        RESOLVE_HANDLE,
+        HttpResponse::json(200, json!({"did": "did:plc:alice"})),
+    );
+    let resolver = HandleResolver::new(client);
+
+    assert_eq!(
+        resolver.resolve("alice.test").await.unwrap(),
+        "did:plc:alice"
+    );
+    assert_eq!(mock.requests_to(RESOLVE_HANDLE).len(), 1);
+}
+
+#[tokio::test]
+async fn test_falls_back_to_resolve_handle() {
+    let (client, mock) = test_client();
+    mock.respond(DOH, nxdomain()).respond(
+        RESOLVE_HANDLE,
+        HttpResponse::json(200, json!({"did": "did:plc:alice"})),
+    );
+
+    let resolver = HandleResolver::new(client.clone());
+    assert_eq!(
+        resolver.resolve("alice.test").await.unwrap(),
+        "did:plc:alice"
+    );
+    assert!(mock.requests_to(RESOLVE_HANDLE)[0]
+        .url
+        .ends_with("resolveHandle?handle=alice.test"));
+
+    let resolver = HandleResolver::new(client).with_fallback(false);
+    let err = resolver.resolve("alice.test").await.unwrap_err();
+    assert!(matches!(err, XrpcError::Identity(_)), "{:?}", err);
+    assert_eq!(mock.requests_to(RESOLVE_HANDLE).len(), 1);
+}
+
+#[tokio::test]
+async fn test_query_parameters_are_encoded() {
+    let (client, mock) = test_client();
+    mock.respond(
+        RESOLVE_HANDLE,
+        HttpResponse::json(200, json!({"did": "did:plc:alice"})),
+    );
+
+    rustysky::xrpc::resolve_handle("alice.test&handle=mallory.test", &client)
+        .await
+        .unwrap();
+    assert!(mock.requests_to(RESOLVE_HANDLE)[0]
+        .url
+        .ends_with("resolveHandle?handle=alice.test%26handle%3Dmallory.test"));
+}
+
+#[tokio::test]
+async fn test_slow_dns_times_out() {
+    let (client, mock) = test_client();
+    mock.respond(
+        "https://alice.test/.well-known/atproto-did",
+        HttpResponse::new(200, "did:plc:alice"),
+    );
+    let resolver = HandleResolver::new(client)
+        .with_dns_resolver(Arc::new(HangingDns))
+        .with_timeout(Duration::from_millis(50));
+
+    assert_eq!(
+        resolver.resolve("alice.test").await.unwrap(),
+        "did:plc:alice"
+    );
+}
+
+#[tokio::test]
+async fn test_resolutions_are_cached() {
+    let (client, mock) = test_client();
+    mock.respond(DOH, txt_answer(&["\"did=did:plc:alice\""]));
+    let resolver = HandleResolver::new(client);
+
+    resolver.resolve("alice.test").await.unwrap();
+    resolver.clone().resolve("@alice.test").await.unwrap();
+    assert_eq!(mock.requests().len(), 1);
+
+    resolver.invalidate("alice.test");
+    resolver.resolve("alice.test").await.unwrap();
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_post_mentions_get_resolved_dids() {
+    let (client, mock) = test_client();
+    mock.respond(DOH, txt_answer(&["\"did=did:plc:alice\""]));
+    let resolver = HandleResolver::new(client);
+
+    let mut post = Post::new("hi @alice.test!", "did:plc:me", None, None, None, None).unwrap();
+    post.resolve_mentions(&resolver).await;
+
+    let record = serde_json::to_value(&post).unwrap();
+    assert_eq!(record["facets"][0]["features"][0]["did"], "did:plc:alice");
+    assert_eq!(record["facets"][0]["index"]["byteStart"], 3);
+}
+
+#[tokio::test]
+async fn test_unresolvable_mentions_are_dropped() {
+    let (client, mock) = test_client();
+    mock.respond(DOH, nxdomain());
+    let resolver = HandleResolver::new(client).with_fallback(false);
+
+    let mut post = Post::new("hi @nobody.test", "did:plc:me", None, None, None, None).unwrap();
+    post.resolve_mentions(&resolver).await;
+
+    assert!(post.facets.is_none());
+}
+
+#[tokio::test]
+async fn test_resolves_plc_and_web_documents() {
+    let (client, mock) = test_client();
+    mock.respond(
+        "https://plc.example.com/did:plc:alice",
+        did_document("did:plc:alice", "alice.test"),
+    )
+    .respond(
+        "https://bob.test/.well-known/did.json",
+        did_document("did:web:bob.test", "bob.test"),
+    );
+    let resolver = DidResolver::new(client).with_plc_directory("https://plc.example.com");
+
+    let alice = resolver.resolve("did:plc:alice").await.unwrap();
+    assert_eq!(alice.pds_endpoint(), Some("https://pds.example.com"));
+    assert_eq!(alice.handle(), Some("alice.test"));
+    resolver.resolve("did:plc:alice").await.unwrap();
+    assert_eq!(mock.requests().len(), 1, "documents are cached");
+
+    let bob = resolver.resolve("did:web:bob.test").await.unwrap();
+    assert_eq!(bob.id, "did:web:bob.test");
+}
+
+#[tokio::test]
+async fn test_plc_rate_limits_do_not_reach_the_pds_client() {
+    let (client, mock) = test_client();
+    let reset = (chrono::Utc::now().timestamp() + 300).to_string();
+    mock.respond(
+        "https://plc.directory/did:plc:alice",
+        did_document("did:plc:alice", "alice.test")
+            .with_header("ratelimit-limit", "100")
+            .with_header("ratelimit-remaining", "0")
+            .with_header("ratelimit-reset", &reset),
+    );
+
+    DidResolver::new(client.clone())
+        .resolve("did:plc:alice")
+        .await
+        .unwrap();
+    assert!(client.config().rate_limiter.latest().is_none());
+}
+
+#[tokio::test]
+async fn test_oversized_documents_are_refused() {
+    let (client, mock) = test_client();
+    let padding = "x".repeat(100 * 1024);
+    mock.respond(
+        "https://bob.test/.well-known/did.json",
+        HttpResponse::json(200, json!({"id": "did:web:bob.test", "padding": padding})),
+    );
+
+    let err = DidResolver::new(client)
+        .resolve("did:web:bob.test")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, XrpcError::Identity(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn test_rejects_missing_and_mismatched_documents() {
+    let (client, mock) = test_client();
+    mock.respond(
+        "https://plc.directory/did:plc:gone",
+        HttpResponse::json(404, json!({"message": "DID not registered: did:plc:gone"})),
+    )
+    .respond(
+        "https://plc.directory/did:plc:alice",
+        did_document("did:plc:mallory", "alice.test"),
+    );
+    let resolver = DidResolver::new(client);
+
+    for did in ["did:plc:gone", "did:plc:alice"] {
+        let err = resolver.resolve(did).await.unwrap_err();
+        assert!(matches!(err, XrpcError::Identity(_)), "{:?}", err);
+    }
+}
+
+#[tokio::test]
+async fn test_handles_are_verified_both_ways() {
+    let (client, mock) = test_client();
+    mock.respond(DOH, txt_answer(&["\"did=did:plc:alice\""]))
+        .respond(
+            "https://plc.directory/did:plc:alice",
+            did_document("did:plc:alice", "alice.test"),
+        );
+    let resolver = IdentityResolver::new(client);
+
+    let identity = resolver.resolve("@alice.test").await.unwrap();
+    assert_eq!(identity.did, "did:plc:alice");
+    assert_eq!(identity.display_handle(), "alice.test");
+    assert_eq!(identity.pds_endpoint(), Some("https://pds.example.com"));
+
+    let identity = resolver.resolve("did:plc:alice").await.unwrap();
+    assert_eq!(identity.handle.as_deref(), Some("alice.test"));
+}
+
+#[tokio::test]
+async fn test_unverified_handles_are_rejected() {
+    let (client, mock) = test_client();
+    // alice.test points at mallory, who claims alice.test; bob's document claims alice.test too.
+    mock.respond(DOH, txt_answer(&["\"did=did:plc:mallory\""]))
+        .respond(
+            "https://plc.directory/did:plc:mallory",
+            did_document("did:plc:mallory", "mallory.test"),
+        )
+        .respond(
+            "https://plc.directory/did:plc:bob",
+            did_document("did:plc:bob", "alice.test"),
+        );
+    let resolver = IdentityResolver::new(client);
+
+    let err = resolver.resolve_handle("alice.test").await.unwrap_err();
+    assert!(matches!(err, XrpcError::Identity(_)), "{:?}", err);
+
+    let bob = resolver.resolve_did("did:plc:bob").await.unwrap();
+    assert_eq!(bob.handle, None);
+    assert_eq!(bob.display_handle(), "handle.invalid");
+}
@@ -0,0 +1,265 @@
+use async_trait::async_trait;
+use rustysky::bsky_agent::BskyAgent;
+use rustysky::dag_cbor::Cid;
+use rustysky::link_card::{FetchedResource, HttpLinkFetcher, LinkCardBuilder, LinkFetcher};
+use rustysky::types::get_default_configuration;
+use rustysky::xrpc::{
+    BlobRef, Embed, External, ExternalEmbed, HttpResponse, MockTransport, Post, RetryPolicy,
+    XrpcClient, XrpcError, XrpcResult,
+};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+mod common;
+
+/// A red pixel.
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR\x00\x00\x00\x01\x00\x00\x00\x01\x08\x02\
+    \x00\x00\x00\x90wS\xde\x00\x00\x00\x0cIDATx\x9cc\xf8\xcf\xc0\x00\x00\x03\x01\x01\x00\xc9\xfe\
+    \x92\xef\x00\x00\x00\x00IEND\xaeB`\x82";
+
+const PAGE: &str = r#"<!doctype html>
+<html>
+<head>
+    <title>Fallback title</title>
+    <meta property="og:title" content="Link cards &amp; you">
+    <meta property="og:description" content="All about external embeds.">
+    <meta property="og:image" content="/images/card.png">
+</head>
+<body>Hello</body>
+</html>"#;
+
+/// A logged-in agent whose blob uploads succeed.
+fn uploading_agent() -> (BskyAgent, Arc<MockTransport>) {
+    let (agent, mock) = common::test_agent();
+    mock.respond(
+        "com.atproto.repo.uploadBlob",
+        HttpResponse::json(
+            200,
+            json!({ "blob": BlobRef::new(Cid::for_raw(PNG), "image/png", PNG.len() as u64) }),
+        ),
+    );
+    (agent, mock)
+}
+
+/// A link card builder that fetches over HTTP, from the fixture server.
+fn http_builder() -> LinkCardBuilder {
+    let mut config = get_default_configuration();
+    config.retry_policy = RetryPolicy::none();
+    LinkCardBuilder::new(XrpcClient::new(config).unwrap())
+}
+
+async fn fixture_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/articles/1"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(PAGE, "text/html; charset=utf-8"))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/images/card.png"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(PNG, "image/png"))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/feed.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "items": [] })))
+        .mount(&server)
+        .await;
+    server
+}
+
+#[tokio::test]
+async fn test_attach_card_for_first_link() {
+    let server = fixture_server().await;
+    let (agent, mock) = uploading_agent();
+    let url = format!("{}/articles/1", server.uri());
+    let mut post = Post::new(
+        &format!("Read this: {} and https://example.com", url),
+        "did:plc:testuser",
+        None,
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+    assert_eq!(post.links(), [url.as_str(), "https://example.com"]);
+
+    assert!(http_builder().attach(&mut post, &agent).await.unwrap());
+    assert_eq!(
+        post.embed,
+        Some(Embed::External(ExternalEmbed {
+            external: External {
+                uri: url,
+                title: "Link cards & you".to_string(),
+                description: "All about external embeds.".to_string(),
+                thumb: Some(BlobRef::new(
+                    Cid::for_raw(PNG),
+                    "image/png",
+                    PNG.len() as u64
+                )),
+            },
+        }))
+    );
+    let uploads = mock.requests_to("com.atproto.repo.uploadBlob");
+    assert_eq!(uploads.len(), 1);
+    // With the `media` feature the thumbnail is re-encoded first.
+    #[cfg(not(feature = "media"))]
+    assert_eq!(uploads[0].body.as_deref(), Some(PNG));
+    assert_eq!(
+        serde_json::to_value(&post).unwrap()["embed"]["$type"],
+        "app.bsky.embed.external"
+    );
+
+    // A post that already has an embed keeps it.
+    assert!(!http_builder().attach(&mut post, &agent).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_missing_thumbnail_is_left_out() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/articles/1"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(PAGE, "text/html"))
+        .mount(&server)
+        .await;
+    let (agent, mock) = uploading_agent();
+
+    let embed = http_builder()
+        .build(&format!("{}/articles/1", server.uri()), &agent)
+        .await
+        .unwrap();
+    let Embed::External(ExternalEmbed { external }) = embed else {
+        panic!("not an external embed: {:?}", embed);
+    };
+    assert_eq!(external.title, "Link cards & you");
+    assert_eq!(external.thumb, None);
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn test_only_web_pages_get_cards() {
+    let server = fixture_server().await;
+    let (agent, _mock) = uploading_agent();
+    let builder = http_builder();
+
+    let err = builder
+        .build(&format!("{}/feed.json", server.uri()), &agent)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, XrpcError::Deserialization(_)), "{:?}", err);
+
+    let err = builder
+        .build(&format!("{}/gone", server.uri()), &agent)
+        .await
+        .unwrap_err();
+    assert_eq!(err.status(), Some(404));
+
+    let mut post = Post::new("No links here", "did:plc:testuser", None, None, None, None).unwrap();
+    assert!(!builder.attach(&mut post, &agent).await.unwrap());
+    assert!(post.embed.is_none());
+}
+
+#[tokio::test]
+async fn test_oversized_responses_are_refused() {
+    let server = fixture_server().await;
+    Mock::given(method("GET"))
+        .and(path("/huge"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(vec![b' '; 100_000], "text/html"))
+        .mount(&server)
+        .await;
+    let mut config = get_default_configuration();
+    config.retry_policy = RetryPolicy::none();
+    let fetcher = HttpLinkFetcher::new(XrpcClient::new(config).unwrap()).with_max_size(10_000);
+
+    let err = fetcher
+        .fetch(&format!("{}/huge", server.uri()))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("limit of 10000 bytes"), "{}", err);
+    let page = fetcher
+        .fetch(&format!("{}/articles/1", server.uri()))
+        .await
+        .unwrap();
+    assert_eq!(page.body, PAGE.as_bytes());
+}
+
+#[tokio::test]
+async fn test_responses_without_a_length_are_cut_off() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Streams chunks without a Content-Length, far more than the limit.
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = [0; 1024];
+        let _ = socket.read(&mut request).await;
+        let head =
+            "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ntransfer-encoding: chunked\r\n\r\n";
+        socket.write_all(head.as_bytes()).await.unwrap();
+        let chunk = format!("1000\r\n{}\r\n", " ".repeat(0x1000));
+        for _ in 0..1000 {
+            if socket.write_all(chunk.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut config = get_default_configuration();
+    config.retry_policy = RetryPolicy::none();
+    let fetcher = HttpLinkFetcher::new(XrpcClient::new(config).unwrap()).with_max_size(10_000);
+    let err = fetcher
+        .fetch(&format!("http://{}/endless", address))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("limit of 10000 bytes"), "{}", err);
+}
+
+/// Serves pages from memory.
+struct FixtureFetcher(HashMap<&'static str, FetchedResource>);
+
+#[async_trait]
+impl LinkFetcher for FixtureFetcher {
+    async fn fetch(&self, url: &str) -> XrpcResult<FetchedResource> {
+        self.0
+            .get(url)
+            .cloned()
+            .ok_or_else(|| XrpcError::from_response(404, ""))
+    }
+}
+
+#[tokio::test]
+async fn test_injected_fetcher() {
+    let (agent, mock) = uploading_agent();
+    let page = r#"<meta name="twitter:title" content="From a fixture">
+        <meta name="twitter:image" content="https://cdn.example.com/card.png">"#;
+    let fetcher = FixtureFetcher(HashMap::from([
+        (
+            "https://example.com/",
+            FetchedResource {
+                content_type: None,
+                body: page.as_bytes().to_vec(),
+            },
+        ),
+        (
+            "https://cdn.example.com/card.png",
+            FetchedResource {
+                content_type: Some("image/png".to_string()),
+                body: PNG.to_vec(),
+            },
+        ),
+    ]));
+
+    let builder = LinkCardBuilder::with_fetcher(Arc::new(fetcher)).with_thumbnail(false);
+    let embed = builder.build("https://example.com/", &agent).await.unwrap();
+    let Embed::External(ExternalEmbed { external }) = embed else {
+        panic!("not an external embed: {:?}", embed);
+    };
+    assert_eq!(external.title, "From a fixture");
+    assert_eq!(external.description, "");
+    assert_eq!(external.thumb, None);
+    assert!(mock.requests().is_empty());
+}
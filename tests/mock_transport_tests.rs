@@ -0,0 +1,175 @@
+use rustysky::bsky_agent::BskyAgent;
+use rustysky::xrpc::{
+    CreateSessionRequest, HttpMethod, HttpResponse, MockTransport, Post, RetryPolicy, XrpcError,
+};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+mod common;
+
+/// A logged-out agent that retries quickly.
+fn retrying_agent() -> (BskyAgent, Arc<MockTransport>) {
+    let (client, mock) = common::mock_client(RetryPolicy {
+        initial_backoff: Duration::from_millis(1),
+        ..RetryPolicy::default()
+    });
+    (BskyAgent::new(client), mock)
+}
+
+fn session_response() -> HttpResponse {
+    HttpResponse::json(
+        200,
+        json!({
+            "did": "did:plc:testuser",
+            "handle": "test.bsky.social",
+            "email": "test@example.com",
+            "emailConfirmed": true,
+            "accessJwt": "access",
+            "refreshJwt": "refresh"
+        }),
+    )
+}
+
+fn login_request() -> CreateSessionRequest {
+    CreateSessionRequest {
+        identifier: "test.bsky.social".to_string(),
+        password: "app-password".to_string(),
+        auth_factor_token: None,
+    }
+}
+
+#[tokio::test]
+async fn test_login_and_post_against_scripted_pds() {
+    let (agent, mock) = retrying_agent();
+    mock.respond("com.atproto.server.createSession", session_response());
+    mock.respond(
+        "com.atproto.repo.createRecord",
+        HttpResponse::json(
+            200,
+            json!({
+                "uri": "at://did:plc:testuser/app.bsky.feed.post/3k2a",
+                "cid": "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"
+            }),
+        ),
+    );
+
+    let session = agent.login(&login_request()).await.unwrap();
+    assert_eq!(session.handle, "test.bsky.social");
+
+    let post = Post::new("hello #rust", &session.did, None, None, None, None).unwrap();
+    let strong_ref = agent.create_post(post).await.unwrap();
+    assert_eq!(
+        strong_ref.uri,
+        "at://did:plc:testuser/app.bsky.feed.post/3k2a"
+    );
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+        requests[0].url,
+        "https://bsky.social/xrpc/com.atproto.server.createSession"
+    );
+    assert_eq!(requests[0].json()["identifier"], "test.bsky.social");
+    assert_eq!(requests[0].header("authorization"), None);
+
+    let create = &requests[1];
+    assert_eq!(create.method, HttpMethod::Post);
+    assert_eq!(create.header("authorization"), Some("Bearer access"));
+    assert_eq!(create.json()["repo"], "did:plc:testuser");
+    assert_eq!(create.json()["collection"], "app.bsky.feed.post");
+    assert_eq!(create.json()["record"]["text"], "hello #rust");
+}
+
+#[tokio::test]
+async fn test_scripted_errors_are_classified() {
+    let (agent, mock) = retrying_agent();
+    mock.respond(
+        "com.atproto.server.createSession",
+        HttpResponse::json(
+            401,
+            json!({
+                "error": "AuthenticationRequired",
+                "message": "Invalid identifier or password"
+            }),
+        ),
+    );
+
+    let err = agent.login(&login_request()).await.unwrap_err();
+    assert!(matches!(err, XrpcError::Auth { .. }));
+    assert_eq!(err.error_name(), Some("AuthenticationRequired"));
+}
+
+#[tokio::test]
+async fn test_transport_failures_are_retried() {
+    let (agent, mock) = retrying_agent();
+    mock.fail_once(
+        "com.atproto.server.createSession",
+        XrpcError::Transport("connection reset".to_string()),
+    )
+    .respond_once(
+        "com.atproto.server.createSession",
+        HttpResponse::new(503, "Service Unavailable"),
+    )
+    .respond("com.atproto.server.createSession", session_response());
+
+    agent.login(&login_request()).await.unwrap();
+    assert_eq!(
+        mock.requests_to("com.atproto.server.createSession").len(),
+        3
+    );
+}
+
+#[tokio::test]
+async fn test_unscripted_calls_are_not_implemented() {
+    let (agent, _mock) = retrying_agent();
+    let err = agent.login(&login_request()).await.unwrap_err();
+    assert_eq!(err.status(), Some(501));
+    assert_eq!(err.error_name(), Some("MethodNotImplemented"));
+}
+
+#[tokio::test]
+async fn test_login_with_auth_factor_token() {
+    let (agent, mock) = retrying_agent();
+    mock.respond_once(
+        "com.atproto.server.createSession",
+        HttpResponse::json(
+            401,
+            json!({
+                "error": "AuthFactorTokenRequired",
+                "message": "A sign in code has been sent to your email address"
+            }),
+        ),
+    )
+    .respond(
+        "com.atproto.server.createSession",
+        HttpResponse::json(
+            200,
+            json!({
+                "did": "did:plc:testuser",
+                "handle": "test.bsky.social",
+                "email": "test@example.com",
+                "emailConfirmed": true,
+                "emailAuthFactor": true,
+                "accessJwt": "access",
+                "refreshJwt": "refresh",
+                "didDoc": {"id": "did:plc:testuser"},
+                "active": true
+            }),
+        ),
+    );
+
+    let mut request = login_request();
+    let err = agent.login(&request).await.unwrap_err();
+    assert!(err.is_auth_factor_token_required());
+
+    request.auth_factor_token = Some("ABCDE-12345".to_string());
+    let session = agent.login(&request).await.unwrap();
+    assert_eq!(session.email_auth_factor, Some(true));
+    assert_eq!(session.active, Some(true));
+    assert_eq!(session.did_doc.unwrap()["id"], "did:plc:testuser");
+
+    let requests = mock.requests_to("com.atproto.server.createSession");
+    assert!(requests[0].json().get("authFactorToken").is_none());
+    assert_eq!(requests[1].json()["authFactorToken"], "ABCDE-12345");
+}
@@ -0,0 +1,456 @@
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rustysky::identity::{DidResolver, DnsResolver, HandleResolver, IdentityResolver};
+use rustysky::oauth::{
+    AuthorizationCallback, LoopbackListener, OAuthClient, OAuthClientConfig, OAuthSession,
+    DEFAULT_SCOPE,
+};
+use rustysky::types::get_default_configuration;
+use rustysky::xrpc::{self, RetryPolicy, XrpcClient, XrpcError, XrpcResult};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use wiremock::matchers::{body_string_contains, header, method, path};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+const AS_NONCE: &str = "as-nonce";
+const RS_NONCE: &str = "rs-nonce";
+
+fn test_client(server: &MockServer) -> XrpcClient {
+    let mut config = get_default_configuration();
+    config.xrpc_host = server.uri();
+    config.retry_policy = RetryPolicy::none();
+    XrpcClient::new(config).unwrap()
+}
+
+/// Answers `_atproto.test.bsky.social` with the test account's DID.
+struct StubDns;
+
+#[async_trait]
+impl DnsResolver for StubDns {
+    async fn txt_records(&self, name: &str) -> XrpcResult<Vec<String>> {
+        Ok(match name {
+            "_atproto.test.bsky.social" => vec!["did=did:plc:testuser".to_string()],
+            _ => Vec::new(),
+        })
+    }
+}
+
+/// An OAuth client that resolves handles with `StubDns` and DIDs with `server` as the PLC
+/// directory.
+fn oauth_client(server: &MockServer, config: OAuthClientConfig) -> OAuthClient {
+    let client = test_client(server);
+    let identity = IdentityResolver::with_resolvers(
+        HandleResolver::new(client.clone()).with_dns_resolver(Arc::new(StubDns)),
+        DidResolver::new(client.clone()).with_plc_directory(&server.uri()),
+    );
+    OAuthClient::new(client, config).with_identity_resolver(identity)
+}
+
+fn did_document(did: &str, handle: &str, pds: &str) -> Value {
+    json!({
+        "id": did,
+        "alsoKnownAs": [format!("at://{}", handle)],
+        "service": [{
+            "id": "#atproto_pds",
+            "type": "AtprotoPersonalDataServer",
+            "serviceEndpoint": pds
+        }]
+    })
+}
+
+/// The claims of the request's DPoP proof.
+fn proof_claims(request: &Request) -> Value {
+    let proof = request.headers.get("dpop").unwrap().to_str().unwrap();
+    let claims = proof.split('.').nth(1).unwrap();
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).unwrap()).unwrap()
+}
+
+fn has_nonce(nonce: &'static str) -> impl Fn(&Request) -> bool + Send + Sync {
+    move |request: &Request| {
+        request.headers.contains_key("dpop") && proof_claims(request)["nonce"] == nonce
+    }
+}
+
+fn form(request: &Request) -> HashMap<String, String> {
+    url::form_urlencoded::parse(&request.body)
+        .into_owned()
+        .collect()
+}
+
+/// A PDS that is its own authorization server, and wants DPoP nonces everywhere.
+async fn stand_in_server() -> MockServer {
+    let server = MockServer::start().await;
+    let issuer = server.uri();
+
+    Mock::given(method("GET"))
+        .and(path("/.well-known/oauth-protected-resource"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "resource": issuer,
+            "authorization_servers": [issuer],
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/.well-known/oauth-authorization-server"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{}/oauth/authorize", issuer),
+            "token_endpoint": format!("{}/oauth/token", issuer),
+            "pushed_authorization_request_endpoint": format!("{}/oauth/par", issuer),
+            "scopes_supported": ["atproto", "transition:generic"],
+            "dpop_signing_alg_values_supported": ["ES256"],
+            "authorization_response_iss_parameter_supported": true,
+        })))
+        .mount(&server)
+        .await;
+
+    for endpoint in ["/oauth/par", "/oauth/token"] {
+        Mock::given(method("POST"))
+            .and(path(endpoint))
+            .respond_with(
+                ResponseTemplate::new(400)
+                    .insert_header("DPoP-Nonce", AS_NONCE)
+                    .set_body_json(json!({
+                        "error": "use_dpop_nonce",
+                        "error_description": "Authorization server requires nonce in DPoP proof",
+                    })),
+            )
+            .with_priority(10)
+            .mount(&server)
+            .await;
+    }
+    Mock::given(method("POST"))
+        .and(path("/oauth/par"))
+        .and(has_nonce(AS_NONCE))
+        .respond_with(ResponseTemplate::new(201).set_body_json(json!({
+            "request_uri": "urn:ietf:params:oauth:request_uri:req-1",
+            "expires_in": 299,
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/oauth/token"))
+        .and(has_nonce(AS_NONCE))
+        .and(body_string_contains("grant_type=authorization_code"))
+        .and(body_string_contains("code=code-1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "access-1",
+            "token_type": "DPoP",
+            "expires_in": 3600,
+            "refresh_token": "refresh-1",
+            "scope": DEFAULT_SCOPE,
+            "sub": "did:plc:testuser",
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/oauth/token"))
+        .and(has_nonce(AS_NONCE))
+        .and(body_string_contains("grant_type=refresh_token"))
+        .and(body_string_contains("refresh_token=refresh-1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "access-2",
+            "token_type": "DPoP",
+            "expires_in": 3600,
+            "refresh_token": "refresh-2",
+            "scope": DEFAULT_SCOPE,
+            "sub": "did:plc:testuser",
+        })))
+        .mount(&server)
+        .await;
+    // The PLC directory, which says the test account lives on this PDS.
+    Mock::given(method("GET"))
+        .and(path("/did:plc:testuser"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(did_document(
+            "did:plc:testuser",
+            "test.bsky.social",
+            &issuer,
+        )))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/xrpc/app.bsky.actor.getProfile"))
+        .respond_with(
+            ResponseTemplate::new(401)
+                .insert_header("DPoP-Nonce", RS_NONCE)
+                .insert_header("WWW-Authenticate", r#"DPoP error="use_dpop_nonce""#)
+                .set_body_json(json!({
+                    "error": "use_dpop_nonce",
+                    "message": "Resource server requires nonce in DPoP proof",
+                })),
+        )
+        .with_priority(10)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/xrpc/app.bsky.actor.getProfile"))
+        .and(header("authorization", "DPoP access-1"))
+        .and(has_nonce(RS_NONCE))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "did": "did:plc:testuser",
+            "handle": "test.bsky.social",
+        })))
+        .mount(&server)
+        .await;
+
+    server
+}
+
+async fn log_in(server: &MockServer) -> (OAuthClient, OAuthSession) {
+    let listener = LoopbackListener::bind(0).await.unwrap();
+    let config = OAuthClientConfig::loopback(listener.redirect_uri(), DEFAULT_SCOPE);
+    let oauth = oauth_client(server, config);
+
+    let request = oauth
+        .authorize(&server.uri(), Some("test.bsky.social"))
+        .await
+        .unwrap();
+    assert_eq!(request.expected_did.as_deref(), Some("did:plc:testuser"));
+    assert_eq!(
+        request.authorization_url,
+        format!(
+            "{}/oauth/authorize?client_id={}&request_uri=urn%3Aietf%3Aparams%3Aoauth%3Arequest_uri%3Areq-1",
+            server.uri(),
+            url::form_urlencoded::byte_serialize(oauth.config().client_id.as_bytes())
+                .collect::<String>()
+        )
+    );
+
+    // The user logs in and the browser is redirected back to the listener.
+    let redirect = format!(
+        "{}?code=code-1&state={}&iss={}",
+        listener.redirect_uri(),
+        request.state,
+        url::form_urlencoded::byte_serialize(server.uri().as_bytes()).collect::<String>()
+    );
+    let browser = tokio::spawn(async move { reqwest::get(redirect).await.unwrap().status() });
+    let callback = listener.wait_for_callback().await.unwrap();
+    assert_eq!(browser.await.unwrap(), 200);
+
+    let session = oauth.callback(&request, &callback).await.unwrap();
+    (oauth, session)
+}
+
+#[tokio::test]
+async fn test_oauth_login_against_stand_in_authorization_server() {
+    let server = stand_in_server().await;
+    let (_oauth, session) = log_in(&server).await;
+    assert_eq!(session.did, "did:plc:testuser");
+    assert_eq!(session.access_token, "access-1");
+    assert_eq!(session.refresh_token.as_deref(), Some("refresh-1"));
+    assert!(!session.needs_refresh());
+
+    let requests = server.received_requests().await.unwrap();
+    let par = requests
+        .iter()
+        .rfind(|request| request.url.path() == "/oauth/par")
+        .unwrap();
+    let token = requests
+        .iter()
+        .rfind(|request| request.url.path() == "/oauth/token")
+        .unwrap();
+    let par_form = form(par);
+    let token_form = form(token);
+    assert_eq!(par_form["code_challenge_method"], "S256");
+    assert_eq!(par_form["login_hint"], "test.bsky.social");
+    assert_eq!(
+        par_form["code_challenge"],
+        URL_SAFE_NO_PAD.encode(Sha256::digest(token_form["code_verifier"].as_bytes()))
+    );
+    // The tokens are bound to the key that signed the token request.
+    assert_eq!(
+        proof_jwk(par),
+        proof_jwk(token),
+        "PAR and token requests must use the same DPoP key"
+    );
+    assert_eq!(proof_jwk(token), session.dpop_key.public_jwk());
+}
+
+#[tokio::test]
+async fn test_xrpc_calls_with_dpop_bound_tokens() {
+    let server = stand_in_server().await;
+    let (_oauth, session) = log_in(&server).await;
+
+    let client = session.client(&test_client(&server));
+    let profile = xrpc::get_profile(&session.did, &session.access_token, &client)
+        .await
+        .unwrap();
+    assert_eq!(profile.handle, "test.bsky.social");
+
+    let requests = server.received_requests().await.unwrap();
+    let call = requests.last().unwrap();
+    let claims = proof_claims(call);
+    assert_eq!(claims["htm"], "GET");
+    assert_eq!(
+        claims["htu"],
+        format!("{}/xrpc/app.bsky.actor.getProfile", server.uri())
+    );
+    assert_eq!(
+        claims["ath"],
+        URL_SAFE_NO_PAD.encode(Sha256::digest(b"access-1"))
+    );
+}
+
+#[tokio::test]
+async fn test_oauth_refresh_rotates_tokens_and_keeps_the_key() {
+    let server = stand_in_server().await;
+    let (oauth, session) = log_in(&server).await;
+
+    let refreshed = oauth.refresh(&session).await.unwrap();
+    assert_eq!(refreshed.access_token, "access-2");
+    assert_eq!(refreshed.refresh_token.as_deref(), Some("refresh-2"));
+    assert_eq!(
+        refreshed.dpop_key.public_jwk(),
+        session.dpop_key.public_jwk()
+    );
+
+    let stored: OAuthSession =
+        serde_json::from_str(&serde_json::to_string(&refreshed).unwrap()).unwrap();
+    assert_eq!(stored.dpop_key.secret(), refreshed.dpop_key.secret());
+}
+
+#[tokio::test]
+async fn test_authorization_server_rate_limits_do_not_reach_the_pds_client() {
+    let server = stand_in_server().await;
+    let reset = (chrono::Utc::now().timestamp() + 300).to_string();
+    let rate_limited = |body: Value| {
+        ResponseTemplate::new(200)
+            .insert_header("ratelimit-limit", "100")
+            .insert_header("ratelimit-remaining", "0")
+            .insert_header("ratelimit-reset", reset.as_str())
+            .set_body_json(body)
+    };
+    Mock::given(method("GET"))
+        .and(path("/.well-known/oauth-protected-resource"))
+        .respond_with(rate_limited(json!({
+            "resource": server.uri(),
+            "authorization_servers": [server.uri()],
+        })))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/oauth/par"))
+        .and(has_nonce(AS_NONCE))
+        .respond_with(rate_limited(json!({
+            "request_uri": "urn:ietf:params:oauth:request_uri:req-1",
+            "expires_in": 299,
+        })))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    let client = test_client(&server);
+    let config = OAuthClientConfig::loopback("http://127.0.0.1:1/callback", DEFAULT_SCOPE);
+    let oauth = OAuthClient::new(client.clone(), config);
+
+    oauth.authorize(&server.uri(), None).await.unwrap();
+    assert!(client.rate_limit().is_none());
+}
+
+#[tokio::test]
+async fn test_callback_with_wrong_state_is_rejected() {
+    let server = stand_in_server().await;
+    let config = OAuthClientConfig::loopback("http://127.0.0.1:1/callback", DEFAULT_SCOPE);
+    let oauth = oauth_client(&server, config);
+    let request = oauth.authorize(&server.uri(), None).await.unwrap();
+
+    let callback = AuthorizationCallback {
+        code: "code-1".to_string(),
+        state: "forged".to_string(),
+        iss: Some(server.uri()),
+    };
+    let err = oauth.callback(&request, &callback).await.unwrap_err();
+    assert_eq!(err.error_name(), Some("invalid_state"));
+}
+
+/// Makes the code `code-2` redeem for a token for `did:plc:other`, which lives on `other_pds`.
+async fn issue_token_for_another_account(server: &MockServer, other_pds: &MockServer) {
+    Mock::given(method("POST"))
+        .and(path("/oauth/token"))
+        .and(has_nonce(AS_NONCE))
+        .and(body_string_contains("code=code-2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "access-other",
+            "token_type": "DPoP",
+            "refresh_token": "refresh-other",
+            "scope": DEFAULT_SCOPE,
+            "sub": "did:plc:other",
+        })))
+        .mount(server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/did:plc:other"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(did_document(
+            "did:plc:other",
+            "other.test",
+            &other_pds.uri(),
+        )))
+        .mount(server)
+        .await;
+    // The other account's PDS trusts another authorization server.
+    Mock::given(method("GET"))
+        .and(path("/.well-known/oauth-protected-resource"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "resource": other_pds.uri(),
+            "authorization_servers": ["https://auth.other.test"],
+        })))
+        .mount(other_pds)
+        .await;
+}
+
+#[tokio::test]
+async fn test_token_for_another_account_than_expected_is_rejected() {
+    let server = stand_in_server().await;
+    let other_pds = MockServer::start().await;
+    issue_token_for_another_account(&server, &other_pds).await;
+    let config = OAuthClientConfig::loopback("http://127.0.0.1:1/callback", DEFAULT_SCOPE);
+    let oauth = oauth_client(&server, config);
+    let request = oauth
+        .authorize(&server.uri(), Some("test.bsky.social"))
+        .await
+        .unwrap();
+
+    let callback = AuthorizationCallback {
+        code: "code-2".to_string(),
+        state: request.state.clone(),
+        iss: Some(server.uri()),
+    };
+    let err = oauth.callback(&request, &callback).await.unwrap_err();
+    assert!(matches!(err, XrpcError::Identity(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn test_token_for_an_account_on_another_authorization_server_is_rejected() {
+    let server = stand_in_server().await;
+    let other_pds = MockServer::start().await;
+    issue_token_for_another_account(&server, &other_pds).await;
+    let config = OAuthClientConfig::loopback("http://127.0.0.1:1/callback", DEFAULT_SCOPE);
+    let oauth = oauth_client(&server, config);
+    // Without a login hint, any account may log in, but only one this server speaks for.
+    let request = oauth.authorize(&server.uri(), None).await.unwrap();
+    assert_eq!(request.expected_did, None);
+
+    let callback = AuthorizationCallback {
+        code: "code-2".to_string(),
+        state: request.state.clone(),
+        iss: Some(server.uri()),
+    };
+    let err = oauth.callback(&request, &callback).await.unwrap_err();
+    assert!(matches!(err, XrpcError::Identity(_)), "{:?}", err);
+    assert!(
+        err.to_string().contains("https://auth.other.test"),
+        "{}",
+        err
+    );
+}
+
+fn proof_jwk(request: &Request) -> Value {
+    let proof = request.headers.get("dpop").unwrap().to_str().unwrap();
+    let header = proof.split('.').next().unwrap();
+    let header: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).unwrap()).unwrap();
+    header["jwk"].clone()
+}
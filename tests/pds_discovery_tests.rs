@@ -0,0 +1,172 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rustysky::accounts::AccountManager;
+use rustysky::bsky_agent::BskyAgent;
+use rustysky::identity::IdentityResolver;
+use rustysky::xrpc::{CreateSessionRequest, HttpResponse, MockTransport};
+use serde_json::{json, Value};
+
+mod common;
+
+use common::test_client;
+
+const DOH: &str = "https://cloudflare-dns.com/dns-query";
+
+fn jwt(tag: &str, expires_in: i64) -> String {
+    let exp = chrono::Utc::now().timestamp() + expires_in;
+    let payload = URL_SAFE_NO_PAD.encode(json!({ "exp": exp, "tag": tag }).to_string());
+    format!("eyJhbGciOiJub25lIn0.{}.sig", payload)
+}
+
+fn did_doc(did: &str, handle: &str, pds: &str) -> Value {
+    json!({
+        "id": did,
+        "alsoKnownAs": [format!("at://{}", handle)],
+        "service": [{
+            "id": "#atproto_pds",
+            "type": "AtprotoPersonalDataServer",
+            "serviceEndpoint": pds
+        }]
+    })
+}
+
+fn session_response(did_doc: Option<Value>) -> HttpResponse {
+    let mut session = json!({
+        "did": "did:plc:alice",
+        "handle": "alice.test",
+        "email": "alice@example.com",
+        "emailConfirmed": true,
+        "accessJwt": jwt("access", 7200),
+        "refreshJwt": jwt("refresh", 86400)
+    });
+    if let Some(did_doc) = did_doc {
+        session["didDoc"] = did_doc;
+    }
+    HttpResponse::json(200, session)
+}
+
+fn login_request(identifier: &str) -> CreateSessionRequest {
+    CreateSessionRequest {
+        identifier: identifier.to_string(),
+        password: "app-password".to_string(),
+        auth_factor_token: None,
+    }
+}
+
+/// alice.test lives on https://pds.example.com, but createSession doesn't send the didDoc.
+fn script_alice(mock: &MockTransport) {
+    mock.respond(
+        DOH,
+        HttpResponse::json(
+            200,
+            json!({"Status": 0, "Answer": [{"type": 16, "data": "\"did=did:plc:alice\""}]}),
+        ),
+    )
+    .respond(
+        "https://plc.directory/did:plc:alice",
+        HttpResponse::json(
+            200,
+            did_doc("did:plc:alice", "alice.test", "https://pds.example.com"),
+        ),
+    )
+    .respond("com.atproto.server.createSession", session_response(None))
+    .respond(
+        "app.bsky.actor.getProfile",
+        HttpResponse::json(200, json!({"did": "did:plc:alice", "handle": "alice.test"})),
+    );
+}
+
+#[tokio::test]
+async fn test_login_goes_to_the_discovered_pds() {
+    let (client, mock) = test_client();
+    script_alice(&mock);
+    let agent = BskyAgent::new(client.clone()).with_pds_discovery(IdentityResolver::new(client));
+
+    let session = agent.login(&login_request("@alice.test")).await.unwrap();
+
+    let logins = mock.requests_to("com.atproto.server.createSession");
+    assert!(logins[0].url.starts_with("https://pds.example.com/xrpc/"));
+    assert_eq!(agent.config().xrpc_host, "https://pds.example.com");
+    assert_eq!(
+        session.pds_endpoint().as_deref(),
+        Some("https://pds.example.com"),
+        "the discovered document is kept with the session"
+    );
+
+    agent.get_profile("alice.test").await.unwrap();
+    assert!(mock.requests_to("app.bsky.actor.getProfile")[0]
+        .url
+        .starts_with("https://pds.example.com/"));
+}
+
+#[tokio::test]
+async fn test_failed_discovery_falls_back_to_the_configured_host() {
+    let (client, mock) = test_client();
+    mock.respond("com.atproto.server.createSession", session_response(None));
+    let agent = BskyAgent::new(client.clone()).with_pds_discovery(IdentityResolver::new(client));
+
+    agent.login(&login_request("alice.test")).await.unwrap();
+
+    assert!(mock.requests_to("com.atproto.server.createSession")[0]
+        .url
+        .starts_with("https://bsky.social/"));
+}
+
+#[tokio::test]
+async fn test_email_logins_skip_discovery() {
+    let (client, mock) = test_client();
+    mock.respond("com.atproto.server.createSession", session_response(None));
+    let agent = BskyAgent::new(client.clone()).with_pds_discovery(IdentityResolver::new(client));
+
+    agent
+        .login(&login_request("alice@example.com"))
+        .await
+        .unwrap();
+
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_session_did_doc_repoints_the_agent() {
+    let (client, mock) = test_client();
+    mock.respond(
+        "com.atproto.server.createSession",
+        session_response(Some(did_doc(
+            "did:plc:alice",
+            "alice.test",
+            "https://shard.example.com/",
+        ))),
+    )
+    .respond(
+        "app.bsky.actor.getProfile",
+        HttpResponse::json(200, json!({"did": "did:plc:alice", "handle": "alice.test"})),
+    );
+    let agent = BskyAgent::new(client.clone());
+
+    let session = agent.login(&login_request("alice.test")).await.unwrap();
+    assert!(mock.requests_to("com.atproto.server.createSession")[0]
+        .url
+        .starts_with("https://bsky.social/"));
+    agent.get_profile("alice.test").await.unwrap();
+    assert!(mock.requests_to("app.bsky.actor.getProfile")[0]
+        .url
+        .starts_with("https://shard.example.com/xrpc/"));
+
+    let resumed = BskyAgent::with_session(client, session);
+    assert_eq!(resumed.config().xrpc_host, "https://shard.example.com");
+}
+
+#[tokio::test]
+async fn test_accounts_remember_the_discovered_pds() {
+    let (client, mock) = test_client();
+    script_alice(&mock);
+    let accounts =
+        AccountManager::in_memory(client.clone()).with_pds_discovery(IdentityResolver::new(client));
+
+    accounts
+        .add("alice", None, &login_request("alice.test"))
+        .await
+        .unwrap();
+
+    assert_eq!(accounts.list().await[0].host, "https://pds.example.com");
+}
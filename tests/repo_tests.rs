@@ -0,0 +1,455 @@
+use rustysky::dag_cbor::Cid;
+use rustysky::syntax::{Did, RecordKey, Tid};
+use rustysky::xrpc::{
+    ApplyWrites, CreateRecordRequest, DeleteRecordRequest, Embed, HttpResponse, Post,
+    PutRecordRequest, Record, StrongRef, WriteResult, XrpcError, MAX_WRITES_PER_CALL,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+mod common;
+
+use common::test_agent;
+
+const CID: &str = "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm";
+const COMMIT: &str = "bafyreigbtj4x7ip5legnfznufuopl4sg4knzc2cof6duas4b3q2fy6swua";
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Like {
+    #[serde(rename = "$type")]
+    record_type: String,
+    subject: StrongRef,
+    #[serde(rename = "createdAt")]
+    created_at: String,
+}
+
+impl Record for Like {
+    const NSID: &'static str = "app.bsky.feed.like";
+}
+
+fn like() -> Like {
+    Like {
+        record_type: Like::NSID.to_string(),
+        subject: StrongRef {
+            uri: "at://did:plc:other/app.bsky.feed.post/3k4duaz5vfs2b"
+                .parse()
+                .unwrap(),
+            cid: CID.parse().unwrap(),
+        },
+        created_at: "2024-01-01T00:00:00.000Z".to_string(),
+    }
+}
+
+fn did() -> Did {
+    "did:plc:testuser".parse().unwrap()
+}
+
+fn write_response(rkey: &str) -> HttpResponse {
+    HttpResponse::json(
+        200,
+        json!({
+            "uri": format!("at://did:plc:testuser/app.bsky.feed.like/{}", rkey),
+            "cid": CID,
+            "commit": { "cid": COMMIT, "rev": "3k4duaz5vfs2b" },
+            "validationStatus": "valid"
+        }),
+    )
+}
+
+#[tokio::test]
+async fn test_create_record_with_client_chosen_key() {
+    let (agent, mock) = test_agent();
+    let tid = Tid::now();
+    mock.respond("com.atproto.repo.createRecord", write_response(&tid));
+
+    let request = CreateRecordRequest::new(&did(), like())
+        .with_rkey(tid.clone().into())
+        .with_validate(true)
+        .with_swap_commit(COMMIT.parse().unwrap());
+    let response = agent.create_record(&request).await.unwrap();
+    assert_eq!(response.uri.rkey().unwrap(), tid.as_str());
+    assert_eq!(response.cid, CID);
+    assert_eq!(response.commit.unwrap().rev, "3k4duaz5vfs2b");
+    assert_eq!(response.validation_status.as_deref(), Some("valid"));
+
+    let body = mock.requests_to("com.atproto.repo.createRecord")[0].json();
+    assert_eq!(body["repo"], "did:plc:testuser");
+    assert_eq!(body["collection"], "app.bsky.feed.like");
+    assert_eq!(body["rkey"], tid.as_str());
+    assert_eq!(body["validate"], true);
+    assert_eq!(body["swapCommit"], COMMIT);
+    assert_eq!(body["record"]["subject"]["cid"], CID);
+}
+
+#[tokio::test]
+async fn test_create_post_leaves_options_out() {
+    let (agent, mock) = test_agent();
+    mock.respond("com.atproto.repo.createRecord", write_response("3k2a"));
+
+    let post = Post::new("hello", "did:plc:testuser", None, None, None, None).unwrap();
+    let strong_ref = agent.create_post(post).await.unwrap();
+    assert_eq!(strong_ref.cid, CID);
+
+    let body = mock.requests_to("com.atproto.repo.createRecord")[0].json();
+    assert_eq!(body["collection"], "app.bsky.feed.post");
+    for option in ["rkey", "validate", "swapCommit"] {
+        assert!(body.get(option).is_none(), "{}", option);
+    }
+}
+
+#[tokio::test]
+async fn test_put_record_with_swap() {
+    let (agent, mock) = test_agent();
+    mock.respond_once(
+        "com.atproto.repo.putRecord",
+        HttpResponse::json(
+            400,
+            json!({ "error": "InvalidSwap", "message": "Record was at bafy..." }),
+        ),
+    )
+    .respond("com.atproto.repo.putRecord", write_response("self"));
+
+    let rkey: RecordKey = "self".parse().unwrap();
+    let stale = PutRecordRequest::new(&did(), rkey.clone(), like())
+        .with_swap_record(COMMIT.parse().unwrap());
+    let err = agent.put_record(&stale).await.unwrap_err();
+    assert!(err.is_invalid_swap());
+
+    let current = Cid::for_record(&like()).unwrap();
+    let request = PutRecordRequest::new(&did(), rkey, like()).with_swap_record(current.clone());
+    let response = agent.put_record(&request).await.unwrap();
+    assert_eq!(response.uri.rkey().unwrap(), "self");
+
+    let body = &mock.requests_to("com.atproto.repo.putRecord")[1].json();
+    assert_eq!(body["rkey"], "self");
+    assert_eq!(body["swapRecord"], current.to_string());
+}
+
+#[tokio::test]
+async fn test_delete_record() {
+    let (agent, mock) = test_agent();
+    mock.respond(
+        "com.atproto.repo.deleteRecord",
+        HttpResponse::json(
+            200,
+            json!({ "commit": { "cid": COMMIT, "rev": "3k4duaz5vfs2b" } }),
+        ),
+    );
+
+    let request = DeleteRecordRequest::of::<Like>(&did(), "3k2a".parse().unwrap());
+    let response = agent.delete_record(&request).await.unwrap();
+    assert_eq!(response.commit.unwrap().cid, COMMIT);
+
+    let body = mock.requests_to("com.atproto.repo.deleteRecord")[0].json();
+    assert_eq!(body["collection"], "app.bsky.feed.like");
+    assert_eq!(body["rkey"], "3k2a");
+    assert!(body.get("swapRecord").is_none());
+}
+
+#[tokio::test]
+async fn test_get_record() {
+    let (agent, mock) = test_agent();
+    mock.respond(
+        "com.atproto.repo.getRecord",
+        HttpResponse::json(
+            200,
+            json!({
+                "uri": "at://did:plc:testuser/app.bsky.feed.like/3k2a",
+                "cid": CID,
+                "value": like()
+            }),
+        ),
+    );
+
+    let rkey = "3k2a".parse().unwrap();
+    let record = agent
+        .get_record::<Like>("test.bsky.social", &rkey)
+        .await
+        .unwrap();
+    assert_eq!(record.value, like());
+    assert_eq!(record.cid.unwrap(), CID);
+
+    let request = &mock.requests_to("com.atproto.repo.getRecord")[0];
+    assert_eq!(
+        request.url,
+        "https://bsky.social/xrpc/com.atproto.repo.getRecord?repo=test.bsky.social&collection=app.bsky.feed.like&rkey=3k2a"
+    );
+    assert_eq!(request.header("authorization"), Some("Bearer access"));
+}
+
+#[tokio::test]
+async fn test_get_missing_record() {
+    let (agent, mock) = test_agent();
+    mock.respond(
+        "com.atproto.repo.getRecord",
+        HttpResponse::json(
+            400,
+            json!({ "error": "RecordNotFound", "message": "Could not locate record" }),
+        ),
+    );
+
+    let rkey = "3k2a".parse().unwrap();
+    let err = agent
+        .get_record::<Like>("did:plc:testuser", &rkey)
+        .await
+        .unwrap_err();
+    assert!(err.is_record_not_found());
+}
+
+#[tokio::test]
+async fn test_quote_a_post_by_its_url() {
+    let (agent, mock) = test_agent();
+    mock.respond(
+        "com.atproto.repo.getRecord",
+        HttpResponse::json(
+            200,
+            json!({
+                "uri": "at://did:plc:other/app.bsky.feed.post/3k4duaz5vfs2b",
+                "cid": CID,
+                "value": { "$type": "app.bsky.feed.post", "text": "Quote me" }
+            }),
+        ),
+    )
+    .respond("com.atproto.repo.createRecord", write_response("3k2b"));
+
+    let quoted = agent
+        .resolve_web_url("https://bsky.app/profile/other.bsky.social/post/3k4duaz5vfs2b")
+        .await
+        .unwrap();
+    // The handle in the URL is replaced by the DID from the response.
+    assert_eq!(
+        quoted.uri.to_string(),
+        "at://did:plc:other/app.bsky.feed.post/3k4duaz5vfs2b"
+    );
+    assert_eq!(quoted.cid, CID);
+    let request = &mock.requests_to("com.atproto.repo.getRecord")[0];
+    assert_eq!(
+        request.url,
+        "https://bsky.social/xrpc/com.atproto.repo.getRecord?repo=other.bsky.social&collection=app.bsky.feed.post&rkey=3k4duaz5vfs2b"
+    );
+
+    let post = Post::new("So true", "did:plc:testuser", None, None, None, None)
+        .unwrap()
+        .with_embed(Embed::quote(quoted));
+    agent.create_post(post).await.unwrap();
+    let record = &mock.requests_to("com.atproto.repo.createRecord")[0].json()["record"];
+    assert_eq!(
+        record["embed"],
+        json!({
+            "$type": "app.bsky.embed.record",
+            "record": {
+                "uri": "at://did:plc:other/app.bsky.feed.post/3k4duaz5vfs2b",
+                "cid": CID
+            }
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_resolve_strong_ref_needs_a_record() {
+    let (agent, mock) = test_agent();
+    let profile = "at://did:plc:other".parse().unwrap();
+    assert!(matches!(
+        agent.resolve_strong_ref(&profile).await,
+        Err(XrpcError::Syntax(_))
+    ));
+    assert!(matches!(
+        agent.resolve_web_url("https://example.com/post/1").await,
+        Err(XrpcError::Syntax(_))
+    ));
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn test_list_records_pages() {
+    let (agent, mock) = test_agent();
+    let listed = |rkey: &str| {
+        json!({
+            "uri": format!("at://did:plc:testuser/app.bsky.feed.like/{}", rkey),
+            "cid": CID,
+            "value": like()
+        })
+    };
+    mock.respond_once(
+        "com.atproto.repo.listRecords",
+        HttpResponse::json(
+            200,
+            json!({ "cursor": "3k2b", "records": [listed("3k2c"), listed("3k2b")] }),
+        ),
+    )
+    .respond(
+        "com.atproto.repo.listRecords",
+        HttpResponse::json(200, json!({ "records": [listed("3k2a")] })),
+    );
+
+    let first = agent
+        .list_records::<Like>("did:plc:testuser", Some(2), None)
+        .await
+        .unwrap();
+    assert_eq!(first.records.len(), 2);
+    assert_eq!(first.records[0].strong_ref().uri.rkey().unwrap(), "3k2c");
+    let second = agent
+        .list_records::<Like>("did:plc:testuser", Some(2), first.cursor.as_deref())
+        .await
+        .unwrap();
+    assert_eq!(second.records.len(), 1);
+    assert_eq!(second.cursor, None);
+
+    let requests = mock.requests_to("com.atproto.repo.listRecords");
+    assert!(requests[0]
+        .url
+        .ends_with("collection=app.bsky.feed.like&limit=2"));
+    assert!(requests[1].url.ends_with("&limit=2&cursor=3k2b"));
+}
+
+fn apply_writes_response(commit: &str, results: Vec<serde_json::Value>) -> HttpResponse {
+    HttpResponse::json(
+        200,
+        json!({
+            "commit": { "cid": commit, "rev": "3k4duaz5vfs2b" },
+            "results": results
+        }),
+    )
+}
+
+#[tokio::test]
+async fn test_apply_writes_maps_results_to_writes() {
+    let (agent, mock) = test_agent();
+    mock.respond(
+        "com.atproto.repo.applyWrites",
+        apply_writes_response(
+            COMMIT,
+            vec![
+                json!({
+                    "$type": "com.atproto.repo.applyWrites#createResult",
+                    "uri": "at://did:plc:testuser/app.bsky.feed.like/3k2a",
+                    "cid": CID,
+                    "validationStatus": "valid"
+                }),
+                json!({ "$type": "com.atproto.repo.applyWrites#deleteResult" }),
+            ],
+        ),
+    );
+
+    let mut batch = ApplyWrites::new(&did());
+    batch
+        .create(&like())
+        .unwrap()
+        .delete::<Like>("3k2b".parse().unwrap());
+    let applied = agent.apply_writes(&batch).await.unwrap();
+    assert_eq!(applied.commits.len(), 1);
+    assert_eq!(
+        applied.results[0]
+            .as_ref()
+            .unwrap()
+            .strong_ref()
+            .unwrap()
+            .uri,
+        "at://did:plc:testuser/app.bsky.feed.like/3k2a"
+    );
+    assert_eq!(applied.results[1], Some(WriteResult::Delete {}));
+
+    let body = mock.requests_to("com.atproto.repo.applyWrites")[0].json();
+    assert_eq!(body["writes"][0]["value"]["subject"]["cid"], CID);
+    assert_eq!(body["writes"][1]["rkey"], "3k2b");
+}
+
+#[tokio::test]
+async fn test_apply_writes_splits_large_batches() {
+    let (agent, mock) = test_agent();
+    let deleted =
+        |n: usize| vec![json!({ "$type": "com.atproto.repo.applyWrites#deleteResult" }); n];
+    let second_commit = Cid::for_raw(b"second").to_string();
+    mock.respond_once(
+        "com.atproto.repo.applyWrites",
+        apply_writes_response(COMMIT, deleted(MAX_WRITES_PER_CALL)),
+    )
+    .respond_once(
+        "com.atproto.repo.applyWrites",
+        apply_writes_response(&second_commit, deleted(50)),
+    );
+
+    let mut batch = ApplyWrites::new(&did()).with_swap_commit(CID.parse().unwrap());
+    for i in 0..MAX_WRITES_PER_CALL + 50 {
+        batch.delete::<Post>(format!("rkey{}", i).parse().unwrap());
+    }
+    let applied = agent.apply_writes(&batch).await.unwrap();
+    assert_eq!(applied.results.len(), 250);
+    assert_eq!(applied.commits[1].cid, second_commit.as_str());
+
+    let requests = mock.requests_to("com.atproto.repo.applyWrites");
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].json()["writes"].as_array().unwrap().len(), 200);
+    assert_eq!(requests[0].json()["swapCommit"], CID);
+    assert_eq!(requests[1].json()["writes"][0]["rkey"], "rkey200");
+    assert_eq!(requests[1].json()["swapCommit"], COMMIT);
+}
+
+#[tokio::test]
+async fn test_apply_writes_stops_at_the_first_failed_call() {
+    let (agent, mock) = test_agent();
+    mock.respond_once(
+        "com.atproto.repo.applyWrites",
+        HttpResponse::json(400, json!({ "error": "InvalidSwap" })),
+    );
+
+    let mut batch = ApplyWrites::new(&did());
+    for i in 0..MAX_WRITES_PER_CALL + 1 {
+        batch.delete::<Post>(format!("rkey{}", i).parse().unwrap());
+    }
+    let err = agent.apply_writes(&batch).await.unwrap_err();
+    assert!(err.is_invalid_swap());
+    assert_eq!(mock.requests_to("com.atproto.repo.applyWrites").len(), 1);
+}
+
+#[tokio::test]
+async fn test_apply_writes_without_results_keeps_going() {
+    let (agent, mock) = test_agent();
+    let second_commit = Cid::for_raw(b"second").to_string();
+    mock.respond_once(
+        "com.atproto.repo.applyWrites",
+        HttpResponse::json(
+            200,
+            json!({ "commit": { "cid": COMMIT, "rev": "3k4duaz5vfs2b" } }),
+        ),
+    )
+    .respond_once(
+        "com.atproto.repo.applyWrites",
+        apply_writes_response(
+            &second_commit,
+            vec![json!({ "$type": "com.atproto.repo.applyWrites#deleteResult" })],
+        ),
+    );
+
+    let mut batch = ApplyWrites::new(&did()).with_swap_commit(CID.parse().unwrap());
+    for i in 0..MAX_WRITES_PER_CALL + 1 {
+        batch.delete::<Post>(format!("rkey{}", i).parse().unwrap());
+    }
+    let applied = agent.apply_writes(&batch).await.unwrap();
+    assert_eq!(applied.commits.len(), 2);
+    assert_eq!(applied.results.len(), MAX_WRITES_PER_CALL + 1);
+    assert!(applied.results[..MAX_WRITES_PER_CALL]
+        .iter()
+        .all(Option::is_none));
+    assert_eq!(
+        applied.results[MAX_WRITES_PER_CALL],
+        Some(WriteResult::Delete {})
+    );
+}
+
+#[tokio::test]
+async fn test_swapped_apply_writes_needs_a_commit_to_chain() {
+    let (agent, mock) = test_agent();
+    mock.respond_once(
+        "com.atproto.repo.applyWrites",
+        HttpResponse::json(200, json!({})),
+    );
+
+    let mut batch = ApplyWrites::new(&did()).with_swap_commit(CID.parse().unwrap());
+    for i in 0..MAX_WRITES_PER_CALL + 1 {
+        batch.delete::<Post>(format!("rkey{}", i).parse().unwrap());
+    }
+    let err = agent.apply_writes(&batch).await.unwrap_err();
+    assert!(matches!(err, XrpcError::Deserialization(_)), "{:?}", err);
+    assert_eq!(mock.requests_to("com.atproto.repo.applyWrites").len(), 1);
+}
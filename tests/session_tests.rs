@@ -0,0 +1,250 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rustysky::bsky_agent::BskyAgent;
+use rustysky::session::{MemorySessionStore, SessionEvent, SessionStore};
+use rustysky::xrpc::{
+    CreateSessionRequest, CreateSessionResponse, HttpResponse, MockTransport, XrpcError,
+};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+mod common;
+
+fn jwt(tag: &str, expires_in: i64) -> String {
+    let exp = chrono::Utc::now().timestamp() + expires_in;
+    let payload = URL_SAFE_NO_PAD.encode(json!({ "exp": exp, "tag": tag }).to_string());
+    format!("eyJhbGciOiJub25lIn0.{}.sig", payload)
+}
+
+fn stored_session(access_jwt: &str) -> CreateSessionResponse {
+    CreateSessionResponse {
+        did: "did:plc:testuser".parse().unwrap(),
+        handle: "old-handle.bsky.social".parse().unwrap(),
+        email: "test@example.com".to_string(),
+        email_confirmed: false,
+        access_jwt: access_jwt.to_string(),
+        refresh_jwt: jwt("refresh", 86400),
+        did_doc: None,
+        active: None,
+        status: None,
+        email_auth_factor: None,
+    }
+}
+
+fn get_session_response() -> HttpResponse {
+    HttpResponse::json(
+        200,
+        json!({
+            "did": "did:plc:testuser",
+            "handle": "test.bsky.social",
+            "email": "test@example.com",
+            "emailConfirmed": true,
+            "active": true
+        }),
+    )
+}
+
+fn test_agent(store: Arc<MemorySessionStore>) -> (BskyAgent, Arc<MockTransport>) {
+    let (client, mock) = common::test_client();
+    (BskyAgent::new(client).with_session_store(store), mock)
+}
+
+#[tokio::test]
+async fn test_resume_validates_the_stored_session() {
+    let store = Arc::new(MemorySessionStore::new());
+    store
+        .save(&stored_session(&jwt("access", 7200)))
+        .await
+        .unwrap();
+    let (agent, mock) = test_agent(store.clone());
+    mock.respond("com.atproto.server.getSession", get_session_response());
+
+    let session = agent.resume_from_store().await.unwrap().unwrap();
+    assert_eq!(session.handle, "test.bsky.social");
+    assert!(session.email_confirmed);
+    assert!(mock
+        .requests_to("com.atproto.server.refreshSession")
+        .is_empty());
+    assert_eq!(
+        store.load().await.unwrap().unwrap().handle,
+        "test.bsky.social"
+    );
+}
+
+#[tokio::test]
+async fn test_resume_drops_a_session_for_another_account() {
+    let store = Arc::new(MemorySessionStore::new());
+    store
+        .save(&stored_session(&jwt("access", 7200)))
+        .await
+        .unwrap();
+    let (agent, mock) = test_agent(store.clone());
+    mock.respond(
+        "com.atproto.server.getSession",
+        HttpResponse::json(
+            200,
+            json!({ "did": "did:plc:someoneelse", "handle": "else.bsky.social" }),
+        ),
+    );
+
+    let err = agent.resume_from_store().await.unwrap_err();
+    assert!(
+        matches!(err.downcast_ref(), Some(XrpcError::Identity(_))),
+        "{:?}",
+        err
+    );
+    assert!(agent.session().is_none());
+    assert!(store.load().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_resume_refreshes_an_expired_session_and_stores_the_new_tokens() {
+    let store = Arc::new(MemorySessionStore::new());
+    store
+        .save(&stored_session(&jwt("stale", -60)))
+        .await
+        .unwrap();
+    let (agent, mock) = test_agent(store.clone());
+    let fresh = jwt("fresh", 7200);
+    mock.respond(
+        "com.atproto.server.refreshSession",
+        HttpResponse::json(
+            200,
+            json!({
+                "did": "did:plc:testuser",
+                "handle": "test.bsky.social",
+                "accessJwt": fresh,
+                "refreshJwt": jwt("rotated", 86400)
+            }),
+        ),
+    );
+    mock.respond("com.atproto.server.getSession", get_session_response());
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    agent.on_session_change(move |event, _| recorded.lock().unwrap().push(event));
+
+    agent.resume_from_store().await.unwrap().unwrap();
+    assert_eq!(
+        mock.requests_to("com.atproto.server.getSession")[0].header("authorization"),
+        Some(format!("Bearer {}", fresh).as_str())
+    );
+    assert_eq!(store.load().await.unwrap().unwrap().access_jwt, fresh);
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![SessionEvent::Refreshed, SessionEvent::Resumed]
+    );
+}
+
+#[tokio::test]
+async fn test_resume_drops_a_session_that_cannot_be_refreshed() {
+    let store = Arc::new(MemorySessionStore::new());
+    store
+        .save(&stored_session(&jwt("stale", -60)))
+        .await
+        .unwrap();
+    let (agent, mock) = test_agent(store.clone());
+    mock.respond(
+        "com.atproto.server.refreshSession",
+        HttpResponse::json(
+            400,
+            json!({"error": "ExpiredToken", "message": "Token has expired"}),
+        ),
+    );
+
+    assert!(agent.resume_from_store().await.is_err());
+    assert!(agent.session().is_none());
+    assert!(store.load().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_login_is_persisted() {
+    let store = Arc::new(MemorySessionStore::new());
+    let (agent, mock) = test_agent(store.clone());
+    mock.respond(
+        "com.atproto.server.createSession",
+        HttpResponse::json(
+            200,
+            json!({
+                "did": "did:plc:testuser",
+                "handle": "test.bsky.social",
+                "email": "test@example.com",
+                "emailConfirmed": true,
+                "accessJwt": "access",
+                "refreshJwt": "refresh"
+            }),
+        ),
+    );
+
+    assert!(agent.resume_from_store().await.unwrap().is_none());
+    agent
+        .login(&CreateSessionRequest {
+            identifier: "test.bsky.social".to_string(),
+            password: "app-password".to_string(),
+            auth_factor_token: None,
+        })
+        .await
+        .unwrap();
+    assert_eq!(store.load().await.unwrap().unwrap().access_jwt, "access");
+}
+
+#[tokio::test]
+async fn test_logout_revokes_the_session_and_wipes_the_store() {
+    let store = Arc::new(MemorySessionStore::new());
+    let session = stored_session(&jwt("access", 7200));
+    store.save(&session).await.unwrap();
+    let (agent, mock) = test_agent(store.clone());
+    mock.respond("com.atproto.server.getSession", get_session_response())
+        .respond(
+            "com.atproto.server.deleteSession",
+            HttpResponse::new(200, ""),
+        );
+    agent.resume_from_store().await.unwrap().unwrap();
+
+    agent.logout().await.unwrap();
+    let delete = &mock.requests_to("com.atproto.server.deleteSession")[0];
+    assert_eq!(
+        delete.header("authorization"),
+        Some(format!("Bearer {}", session.refresh_jwt).as_str())
+    );
+    assert!(agent.session().is_none());
+    assert!(store.load().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_logout_of_a_revoked_session_still_wipes_the_store() {
+    let store = Arc::new(MemorySessionStore::new());
+    let session = stored_session(&jwt("access", 7200));
+    store.save(&session).await.unwrap();
+    let (agent, mock) = test_agent(store.clone());
+    mock.respond(
+        "com.atproto.server.deleteSession",
+        HttpResponse::json(
+            400,
+            json!({"error": "ExpiredToken", "message": "Token has been revoked"}),
+        ),
+    );
+
+    let agent =
+        BskyAgent::with_session(agent.client().clone(), session).with_session_store(store.clone());
+    agent.logout().await.unwrap();
+    assert!(store.load().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_failed_logout_keeps_the_session() {
+    let store = Arc::new(MemorySessionStore::new());
+    let session = stored_session(&jwt("access", 7200));
+    store.save(&session).await.unwrap();
+    let (agent, mock) = test_agent(store.clone());
+    mock.respond(
+        "com.atproto.server.deleteSession",
+        HttpResponse::new(503, "Service Unavailable"),
+    );
+
+    let agent =
+        BskyAgent::with_session(agent.client().clone(), session).with_session_store(store.clone());
+    assert_eq!(agent.logout().await.unwrap_err().status(), Some(503));
+    assert!(agent.session().is_some());
+    assert!(store.load().await.unwrap().is_some());
+}
@@ -0,0 +1,123 @@
+use rustysky::types::get_default_configuration;
+use rustysky::xrpc::{get_profile, RateLimiter, RetryPolicy, XrpcClient, XrpcError};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn test_client(server: &MockServer, rate_limiter: RateLimiter) -> XrpcClient {
+    let mut config = get_default_configuration();
+    config.xrpc_host = server.uri();
+    config.retry_policy = RetryPolicy::none();
+    config.rate_limiter = Arc::new(rate_limiter);
+    XrpcClient::new(config).unwrap()
+}
+
+fn rate_limited(remaining: u32, reset_in_secs: i64) -> ResponseTemplate {
+    let reset = chrono::Utc::now().timestamp() + reset_in_secs;
+    ResponseTemplate::new(429)
+        .insert_header("ratelimit-limit", "3000")
+        .insert_header("ratelimit-remaining", remaining.to_string().as_str())
+        .insert_header("ratelimit-reset", reset.to_string().as_str())
+        .insert_header("ratelimit-policy", "3000;w=300")
+        .set_body_json(json!({
+            "error": "RateLimitExceeded",
+            "message": "Rate Limit Exceeded"
+        }))
+}
+
+fn profile() -> ResponseTemplate {
+    ResponseTemplate::new(200)
+        .insert_header("ratelimit-limit", "3000")
+        .insert_header("ratelimit-remaining", "2999")
+        .insert_header("ratelimit-reset", "4102444800")
+        .set_body_json(json!({
+            "did": "did:plc:testuser",
+            "handle": "test.bsky.social"
+        }))
+}
+
+#[tokio::test]
+async fn test_rate_limit_info_is_attached_to_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/xrpc/app.bsky.actor.getProfile"))
+        .respond_with(rate_limited(0, 300))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = test_client(&server, RateLimiter::new());
+    let err = get_profile("did:plc:testuser", "access", &client)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, XrpcError::RateLimited(_)));
+    let info = err.rate_limit().unwrap();
+    assert_eq!(info.limit, 3000);
+    assert_eq!(info.remaining, 0);
+    assert_eq!(info.policy.as_deref(), Some("3000;w=300"));
+}
+
+#[tokio::test]
+async fn test_successful_responses_update_the_limiter() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/xrpc/app.bsky.actor.getProfile"))
+        .respond_with(profile())
+        .mount(&server)
+        .await;
+
+    let client = test_client(&server, RateLimiter::new());
+    get_profile("did:plc:testuser", "access", &client)
+        .await
+        .unwrap();
+    assert_eq!(client.rate_limit().unwrap().remaining, 2999);
+}
+
+#[tokio::test]
+async fn test_waits_for_reset_on_rate_limit() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/xrpc/app.bsky.actor.getProfile"))
+        .respond_with(rate_limited(0, 1))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/xrpc/app.bsky.actor.getProfile"))
+        .respond_with(profile())
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = test_client(
+        &server,
+        RateLimiter::new().with_wait_on_limit(Duration::from_secs(5)),
+    );
+    let profile = get_profile("did:plc:testuser", "access", &client)
+        .await
+        .unwrap();
+    assert_eq!(profile.handle, "test.bsky.social");
+}
+
+#[tokio::test]
+async fn test_does_not_wait_longer_than_max_wait() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/xrpc/app.bsky.actor.getProfile"))
+        .respond_with(rate_limited(0, 300))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = test_client(
+        &server,
+        RateLimiter::new().with_wait_on_limit(Duration::from_secs(5)),
+    );
+    let err = get_profile("did:plc:testuser", "access", &client)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, XrpcError::RateLimited(_)));
+}
@@ -0,0 +1,116 @@
+use rustysky::types::get_default_configuration;
+use rustysky::xrpc::{
+    create_post, get_profile, CreatePostRequest, Post, RetryPolicy, XrpcClient, XrpcError,
+};
+use serde_json::json;
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn test_client(server: &MockServer) -> XrpcClient {
+    let mut config = get_default_configuration();
+    config.xrpc_host = server.uri();
+    config.retry_policy = RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(5),
+        ..RetryPolicy::default()
+    };
+    XrpcClient::new(config).unwrap()
+}
+
+#[tokio::test]
+async fn test_query_is_retried_until_it_succeeds() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/xrpc/app.bsky.actor.getProfile"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/xrpc/app.bsky.actor.getProfile"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "did": "did:plc:testuser",
+            "handle": "test.bsky.social"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let profile = get_profile("did:plc:testuser", "access", &test_client(&server))
+        .await
+        .unwrap();
+    assert_eq!(profile.handle, "test.bsky.social");
+}
+
+#[tokio::test]
+async fn test_query_gives_up_after_max_attempts() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/xrpc/app.bsky.actor.getProfile"))
+        .respond_with(ResponseTemplate::new(502))
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    let err = get_profile("did:plc:testuser", "access", &test_client(&server))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, XrpcError::Server(_)));
+}
+
+#[tokio::test]
+async fn test_create_record_is_not_replayed_on_server_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/xrpc/com.atproto.repo.createRecord"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let did = "did:plc:testuser";
+    let post = Post::new("hello", did, None, None, None, None).unwrap();
+    let request = CreatePostRequest::new(&did.parse().unwrap(), post);
+    let err = create_post(&request, "access", &test_client(&server))
+        .await
+        .unwrap_err();
+    assert_eq!(err.status(), Some(500));
+}
+
+#[tokio::test]
+async fn test_create_record_is_retried_when_rate_limited() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/xrpc/com.atproto.repo.createRecord"))
+        .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+            "error": "RateLimitExceeded",
+            "message": "Rate Limit Exceeded"
+        })))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/xrpc/com.atproto.repo.createRecord"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "uri": "at://did:plc:testuser/app.bsky.feed.post/3k2a",
+            "cid": "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let did = "did:plc:testuser";
+    let post = Post::new("hello", did, None, None, None, None).unwrap();
+    let request = CreatePostRequest::new(&did.parse().unwrap(), post);
+    let strong_ref = create_post(&request, "access", &test_client(&server))
+        .await
+        .unwrap();
+    assert_eq!(
+        strong_ref.cid,
+        "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"
+    );
+}